@@ -0,0 +1,535 @@
+//! Derive the `encoding::bson::Marshaler` and `Unmarshaler` of the myblog-api from the field attributes.
+//!
+//! The messages are generated from the protobuf in another crate, so the attributes are put on a schema struct
+//! which mirrors the fields of the message, and the `remote` attribute names the message to implement.
+//!
+//! ```ignore
+//! #[derive(Marshaler, Unmarshaler)]
+//! #[bson(remote = "Comment")]
+//! struct CommentSchema {
+//!     #[bson(rename = "_id", object_id)]
+//!     id: String,
+//!     text: String,
+//!     #[bson(reference)]
+//!     author: Option<User>,
+//!     #[bson(timestamp)]
+//!     created_at: Option<Timestamp>,
+//! }
+//! ```
+//!
+//! The field is stored under its camelCase name unless it is renamed, and it is read and written as follows.
+//!
+//! - `object_id` stores the hex string as an ObjectId.
+//! - `timestamp` stores the timestamp as a date time at the millisecond precision.
+//! - `embedded` stores the message as a nested document.
+//! - `reference` stores only the ID of the message, which is an ObjectId if `object_id` is also given,
+//!   and reads either the ID or the document which is embedded by a `$lookup`.
+//! - `optional` reads a missing field as its default and leaves an unset `Option` out of the document,
+//!   otherwise the field is required on both sides.
+//! - `skip_if_default` leaves the field out of the document when it is empty or zero.
+//! - `read_only` never writes the field, which is managed by its own updates.
+//! - `read_with = "path"` reads the field with a `fn(&Document) -> T` and never writes it.
+//!
+//! The fields of the message which are missing from the schema are neither written nor read, but left default.
+//! The schema struct of a `remote` message is never constructed, so the derive also keeps it from being
+//! reported as dead code.
+//!
+//! A field which cannot be read fails with a `DecodeError` of its path, e.g. `derivatives.1.width`, within the
+//! document.
+
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::quote;
+use syn::ext::IdentExt;
+use syn::{
+    parse_macro_input, Attribute, Data, DeriveInput, Fields, GenericArgument, Ident, Lit, Meta, NestedMeta, Path,
+    PathArguments, Type,
+};
+
+#[proc_macro_derive(Marshaler, attributes(bson))]
+pub fn derive_marshaler(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+
+    Schema::parse(&input)
+        .map(|schema| {
+            let marshaler = schema.marshaler();
+            let used = schema.used();
+            quote! { #marshaler #used }
+        })
+        .unwrap_or_else(|err| err.to_compile_error())
+        .into()
+}
+
+#[proc_macro_derive(Unmarshaler, attributes(bson))]
+pub fn derive_unmarshaler(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+
+    Schema::parse(&input)
+        .map(|schema| {
+            let unmarshaler = schema.unmarshaler();
+            let used = schema.used();
+            quote! { #unmarshaler #used }
+        })
+        .unwrap_or_else(|err| err.to_compile_error())
+        .into()
+}
+
+/// A struct whose fields describe how the message is stored.
+struct Schema {
+    ident: Ident,
+    target: Path,
+    /// Whether the target is another type than the schema struct itself.
+    remote: bool,
+    fields: Vec<Field>,
+}
+
+/// A field of the schema with its document key and the shape of its type.
+struct Field {
+    ident: Ident,
+    key: String,
+    ty: Type,
+    shape: Shape,
+    /// The element type, e.g. `User` of an `Option<Box<User>>`.
+    element: Type,
+    boxed: bool,
+    options: FieldOptions,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Shape {
+    Plain,
+    Option,
+    Vec,
+}
+
+#[derive(Default)]
+struct FieldOptions {
+    rename: Option<String>,
+    object_id: bool,
+    timestamp: bool,
+    embedded: bool,
+    reference: bool,
+    optional: bool,
+    skip_if_default: bool,
+    read_only: bool,
+    read_with: Option<Path>,
+}
+
+impl Schema {
+    fn parse(input: &DeriveInput) -> syn::Result<Self> {
+        let mut target: Path = input.ident.clone().into();
+        let mut remote = false;
+        for meta in bson_attributes(&input.attrs)? {
+            match meta {
+                NestedMeta::Meta(Meta::NameValue(nv)) if nv.path.is_ident("remote") => {
+                    target = parse_lit_str(&nv.lit)?;
+                    remote = true;
+                }
+                meta => return Err(syn::Error::new_spanned(meta, "unknown bson container attribute")),
+            }
+        }
+
+        let fields = match &input.data {
+            Data::Struct(data) => match &data.fields {
+                Fields::Named(fields) => fields.named.iter().map(Field::parse).collect::<syn::Result<Vec<_>>>()?,
+                _ => return Err(syn::Error::new_spanned(&input.ident, "only structs with named fields are supported")),
+            },
+            _ => return Err(syn::Error::new_spanned(&input.ident, "only structs are supported")),
+        };
+
+        // A reference is read into a default message with only its ID, which the schema struct itself is not
+        if let Some(field) = fields.iter().find(|field| field.options.reference && !remote) {
+            return Err(syn::Error::new_spanned(&field.ident, "`reference` requires the `remote` container attribute"));
+        }
+
+        Ok(Schema { ident: input.ident.clone(), target, remote, fields })
+    }
+
+    /// An item which constructs the schema struct of a remote message from all of its fields, so neither
+    /// the struct nor its fields are reported as dead code.
+    fn used(&self) -> TokenStream2 {
+        if !self.remote {
+            return quote! {};
+        }
+
+        let ident = &self.ident;
+        let fields = self.fields.iter().map(|field| &field.ident);
+
+        quote! {
+            const _: () = {
+                #[allow(dead_code)]
+                fn used(schema: #ident) -> #ident {
+                    #ident { #(#fields: schema.#fields),* }
+                }
+            };
+        }
+    }
+
+    fn marshaler(&self) -> TokenStream2 {
+        let target = &self.target;
+        let fields = self
+            .fields
+            .iter()
+            .filter(|field| !field.options.read_only && field.options.read_with.is_none())
+            .map(Field::marshal);
+
+        quote! {
+            impl crate::encoding::bson::Marshaler for #target {
+                fn marshal_bson(
+                    &self,
+                ) -> ::std::result::Result<::mongodb::bson::Document, crate::encoding::bson::MarshalError> {
+                    let mut document = ::mongodb::bson::Document::new();
+                    #(#fields)*
+                    Ok(document)
+                }
+            }
+        }
+    }
+
+    fn unmarshaler(&self) -> TokenStream2 {
+        let target = &self.target;
+        let type_name = target.segments.last().map(|segment| segment.ident.to_string()).unwrap_or_default();
+        let fields = self.fields.iter().map(Field::unmarshal);
+
+        // The schema may list all fields of the message, or leave the ones which are not stored to their defaults
+        quote! {
+            #[allow(clippy::needless_update)]
+            impl crate::encoding::bson::Unmarshaler for #target {
+                fn unmarshal_bson(
+                    document: &::mongodb::bson::Document,
+                ) -> ::std::result::Result<Self, crate::encoding::bson::DecodeError>
+                    where
+                        Self: Sized,
+                {
+                    fn unmarshal_fields(
+                        document: &::mongodb::bson::Document,
+                    ) -> ::std::result::Result<#target, crate::encoding::bson::DecodeError> {
+                        Ok(#target {
+                            #(#fields)*
+                            ..::std::default::Default::default()
+                        })
+                    }
+
+                    unmarshal_fields(document).map_err(|err| err.of(#type_name, document))
+                }
+            }
+        }
+    }
+}
+
+impl Field {
+    fn parse(field: &syn::Field) -> syn::Result<Self> {
+        let ident = field.ident.clone().expect("named field");
+
+        let mut options = FieldOptions::default();
+        for meta in bson_attributes(&field.attrs)? {
+            match meta {
+                NestedMeta::Meta(Meta::NameValue(nv)) if nv.path.is_ident("rename") => {
+                    options.rename = match &nv.lit {
+                        Lit::Str(s) => Some(s.value()),
+                        lit => return Err(syn::Error::new_spanned(lit, "expected a string literal")),
+                    };
+                }
+                NestedMeta::Meta(Meta::NameValue(nv)) if nv.path.is_ident("read_with") => {
+                    options.read_with = Some(parse_lit_str(&nv.lit)?);
+                }
+                NestedMeta::Meta(Meta::Path(path)) => {
+                    let flag = match path.get_ident().map(Ident::to_string).as_deref() {
+                        Some("object_id") => &mut options.object_id,
+                        Some("timestamp") => &mut options.timestamp,
+                        Some("embedded") => &mut options.embedded,
+                        Some("reference") => &mut options.reference,
+                        Some("optional") => &mut options.optional,
+                        Some("skip_if_default") => &mut options.skip_if_default,
+                        Some("read_only") => &mut options.read_only,
+                        _ => return Err(syn::Error::new_spanned(path, "unknown bson field attribute")),
+                    };
+                    *flag = true;
+                }
+                meta => return Err(syn::Error::new_spanned(meta, "unknown bson field attribute")),
+            }
+        }
+
+        // A field which is never written cannot be left out of the document when it is empty either
+        if [options.timestamp, options.embedded, options.reference].iter().filter(|flag| **flag).count() > 1
+            || (options.object_id && (options.timestamp || options.embedded))
+            || (options.skip_if_default && (options.read_only || options.read_with.is_some()))
+        {
+            return Err(syn::Error::new_spanned(&ident, "conflicting bson field attributes"));
+        }
+
+        let (shape, element) = match generic_argument(&field.ty, "Option") {
+            Some(element) => (Shape::Option, element),
+            None => match generic_argument(&field.ty, "Vec") {
+                Some(element) => (Shape::Vec, element),
+                None => (Shape::Plain, field.ty.clone()),
+            },
+        };
+        let (boxed, element) = match generic_argument(&element, "Box") {
+            Some(element) => (true, element),
+            None => (false, element),
+        };
+
+        let key = options.rename.clone().unwrap_or_else(|| camel_case(&ident.unraw().to_string()));
+
+        Ok(Field { ident, key, ty: field.ty.clone(), shape, element, boxed, options })
+    }
+
+    /// A closure which converts a reference to the element into its BSON value.
+    fn encoder(&self) -> TokenStream2 {
+        let element = &self.element;
+        let conversion = if self.options.reference {
+            if self.options.object_id {
+                quote! { crate::encoding::bson::value::object_id_to_bson(value.id.as_str()) }
+            } else {
+                quote! { Ok(::mongodb::bson::Bson::String(value.id.clone())) }
+            }
+        } else if self.options.embedded {
+            quote! { crate::encoding::bson::value::embedded_to_bson(value) }
+        } else if self.options.timestamp {
+            quote! { Ok(::mongodb::bson::Bson::DateTime(crate::encoding::bson::to_datetime(value))) }
+        } else if self.options.object_id {
+            quote! { crate::encoding::bson::value::object_id_to_bson(value.as_str()) }
+        } else {
+            quote! { Ok(crate::encoding::bson::value::BsonValue::to_bson(value)) }
+        };
+
+        quote! {
+            |value: &#element| -> ::std::result::Result<
+                ::mongodb::bson::Bson,
+                crate::encoding::bson::MarshalError,
+            > { #conversion }
+        }
+    }
+
+    /// A closure which converts the BSON value into the element, boxed if the field is.
+    fn decoder(&self) -> TokenStream2 {
+        let element = &self.element;
+        let conversion = if self.options.reference {
+            quote! {
+                crate::encoding::bson::unmarshal_reference(value, |id| #element {
+                    id,
+                    ..::std::default::Default::default()
+                })
+            }
+        } else if self.options.embedded {
+            quote! { crate::encoding::bson::value::embedded_from_bson::<#element>(value) }
+        } else if self.options.timestamp {
+            quote! { crate::encoding::bson::value::timestamp_from_bson(value).map_err(::std::convert::From::from) }
+        } else if self.options.object_id {
+            quote! { crate::encoding::bson::value::object_id_from_bson(value).map_err(::std::convert::From::from) }
+        } else {
+            quote! {
+                <#element as crate::encoding::bson::value::BsonValue>::from_bson(value)
+                    .map_err(::std::convert::From::from)
+            }
+        };
+        let (output, conversion) = if self.boxed {
+            (quote! { ::std::boxed::Box<#element> }, quote! { #conversion.map(::std::boxed::Box::new) })
+        } else {
+            (quote! { #element }, conversion)
+        };
+
+        quote! {
+            |value: &::mongodb::bson::Bson| -> ::std::result::Result<
+                #output,
+                crate::encoding::bson::DecodeError,
+            > { #conversion }
+        }
+    }
+
+    fn marshal(&self) -> TokenStream2 {
+        let ident = &self.ident;
+        let key = &self.key;
+        let to_bson = self.encoder();
+        let element = if self.boxed { quote! { &**value } } else { quote! { value } };
+
+        let insert = match self.shape {
+            Shape::Option => {
+                let name = ident.unraw().to_string();
+                let none = if self.options.optional {
+                    quote! {}
+                } else {
+                    quote! { return Err(crate::encoding::bson::MarshalError::MissingField(#name)); }
+                };
+
+                quote! {
+                    match &self.#ident {
+                        Some(value) => {
+                            document.insert(#key, to_bson(#element)?);
+                        }
+                        None => {
+                            #none
+                        }
+                    }
+                }
+            }
+            Shape::Vec => {
+                let insert = quote! {
+                    let values = self.#ident
+                        .iter()
+                        .map(|value| to_bson(#element))
+                        .collect::<::std::result::Result<::std::vec::Vec<_>, _>>()?;
+                    document.insert(#key, ::mongodb::bson::Bson::Array(values));
+                };
+
+                if self.options.skip_if_default {
+                    quote! { if !self.#ident.is_empty() { #insert } }
+                } else {
+                    insert
+                }
+            }
+            Shape::Plain => {
+                let value = if self.boxed { quote! { &*self.#ident } } else { quote! { &self.#ident } };
+                let insert = quote! { document.insert(#key, to_bson(#value)?); };
+
+                if self.options.skip_if_default {
+                    let element = &self.element;
+                    quote! { if #value != &<#element as ::std::default::Default>::default() { #insert } }
+                } else {
+                    insert
+                }
+            }
+        };
+
+        quote! {
+            {
+                let to_bson = #to_bson;
+                #insert
+            }
+        }
+    }
+
+    fn unmarshal(&self) -> TokenStream2 {
+        let ident = &self.ident;
+        if let Some(read_with) = &self.options.read_with {
+            return quote! { #ident: #read_with(document), };
+        }
+
+        let key = &self.key;
+        let ty = &self.ty;
+        let from_bson = self.decoder();
+        let missing = if self.options.optional {
+            quote! { Ok(::std::default::Default::default()) }
+        } else {
+            quote! { Err(::mongodb::bson::document::ValueAccessError::NotPresent.into()) }
+        };
+        let present = match self.shape {
+            Shape::Option => quote! { from_bson(value).map(Some) },
+            Shape::Vec => quote! {
+                match value {
+                    ::mongodb::bson::Bson::Array(values) => values
+                        .iter()
+                        .enumerate()
+                        .map(|(index, value)| from_bson(value).map_err(|err| err.at(index)))
+                        .collect(),
+                    _ => Err(::mongodb::bson::document::ValueAccessError::UnexpectedType.into()),
+                }
+            },
+            Shape::Plain => quote! { from_bson(value) },
+        };
+
+        quote! {
+            #ident: {
+                let from_bson = #from_bson;
+                let result: ::std::result::Result<#ty, crate::encoding::bson::DecodeError> =
+                    match document.get(#key) {
+                        None | Some(::mongodb::bson::Bson::Null) => #missing,
+                        Some(value) => #present,
+                    };
+                result.map_err(|err| err.at(#key))?
+            },
+        }
+    }
+}
+
+/// Collect the items of every `#[bson(...)]` attribute.
+fn bson_attributes(attrs: &[Attribute]) -> syn::Result<Vec<NestedMeta>> {
+    let mut items = vec![];
+    for attr in attrs.iter().filter(|attr| attr.path.is_ident("bson")) {
+        match attr.parse_meta()? {
+            Meta::List(list) => items.extend(list.nested),
+            meta => return Err(syn::Error::new_spanned(meta, "expected #[bson(...)]")),
+        }
+    }
+
+    Ok(items)
+}
+
+fn parse_lit_str<T: syn::parse::Parse>(lit: &Lit) -> syn::Result<T> {
+    match lit {
+        Lit::Str(s) => s.parse(),
+        _ => Err(syn::Error::new_spanned(lit, "expected a string literal")),
+    }
+}
+
+/// Return the type argument if the type is the wrapper, e.g. `T` of `Option<T>`.
+fn generic_argument(ty: &Type, wrapper: &str) -> Option<Type> {
+    let segment = match ty {
+        Type::Path(ty) if ty.qself.is_none() => ty.path.segments.last()?,
+        _ => return None,
+    };
+    if segment.ident != wrapper {
+        return None;
+    }
+
+    match &segment.arguments {
+        PathArguments::AngleBracketed(args) => match args.args.first()? {
+            GenericArgument::Type(ty) if args.args.len() == 1 => Some(ty.clone()),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// Convert the snake_case field name into the camelCase key, e.g. `uploaded_file_path` to `uploadedFilePath`.
+fn camel_case(name: &str) -> String {
+    let mut key = String::with_capacity(name.len());
+    let mut upper = false;
+    for c in name.chars() {
+        if c == '_' {
+            upper = !key.is_empty();
+        } else if upper {
+            key.extend(c.to_uppercase());
+            upper = false;
+        } else {
+            key.push(c);
+        }
+    }
+
+    key
+}
+
+#[cfg(test)]
+mod tests {
+    use quote::ToTokens;
+    use syn::Type;
+
+    use crate::{camel_case, generic_argument};
+
+    #[test]
+    fn convert_field_name_to_camel_case() {
+        // Given
+
+        // When
+
+        // Then
+        assert_eq!("uploadedFilePath", camel_case("uploaded_file_path"));
+        assert_eq!("sha256", camel_case("sha256"));
+        assert_eq!("type", camel_case("type"));
+        assert_eq!("private", camel_case("_private"));
+    }
+
+    #[test]
+    fn unwrap_generic_argument() {
+        // Given
+        let ty: Type = syn::parse_str("Option<Box<Comment>>").unwrap();
+
+        // When
+        let result = generic_argument(&ty, "Option").and_then(|ty| generic_argument(&ty, "Box"));
+
+        // Then
+        assert_eq!(Some(String::from("Comment")), result.map(|ty| ty.to_token_stream().to_string()));
+        assert!(generic_argument(&ty, "Vec").is_none());
+    }
+}
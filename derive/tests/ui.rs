@@ -0,0 +1,10 @@
+#[test]
+fn reject_invalid_attributes() {
+    // Given
+    let t = trybuild::TestCases::new();
+
+    // When
+
+    // Then
+    t.compile_fail("tests/ui/*.rs");
+}
@@ -0,0 +1,10 @@
+use myblog_api_derive::Unmarshaler;
+
+#[derive(Unmarshaler)]
+#[bson(remote = "File")]
+struct FileSchema {
+    #[bson(object_id, timestamp)]
+    uploaded_at: Option<Timestamp>,
+}
+
+fn main() {}
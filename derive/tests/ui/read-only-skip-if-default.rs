@@ -0,0 +1,10 @@
+use myblog_api_derive::Unmarshaler;
+
+#[derive(Unmarshaler)]
+#[bson(remote = "User")]
+struct UserSchema {
+    #[bson(read_only, skip_if_default)]
+    roles: Vec<String>,
+}
+
+fn main() {}
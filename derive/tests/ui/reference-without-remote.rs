@@ -0,0 +1,13 @@
+use myblog_api_derive::Unmarshaler;
+
+struct User {
+    id: String,
+}
+
+#[derive(Unmarshaler)]
+struct CommentSchema {
+    #[bson(reference)]
+    author: Option<User>,
+}
+
+fn main() {}
@@ -0,0 +1,10 @@
+use myblog_api_derive::Unmarshaler;
+
+#[derive(Unmarshaler)]
+#[bson(remote = "Post")]
+struct PostSchema {
+    #[bson(indexed)]
+    slug: String,
+}
+
+fn main() {}
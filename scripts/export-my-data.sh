@@ -0,0 +1,12 @@
+#!/bin/sh
+
+set -e
+
+grpcurl -plaintext \
+  -import-path ../myblog-proto \
+  -proto proto/auth/service.proto \
+  -H "Authorization: Bearer ${ACCESS_TOKEN}" \
+  localhost:8081 \
+  myblog.proto.auth.AuthService/ExportMyData \
+  | jq -r '.data' \
+  | while read -r chunk; do echo "${chunk}" | base64 -d; done > my-data.json
@@ -0,0 +1,5 @@
+#!/bin/sh
+
+set -e
+
+curl -i -H "Range: bytes=0-1023" localhost:8094/files/${SLUG}
@@ -0,0 +1,10 @@
+#!/bin/sh
+
+set -e
+
+grpcurl -plaintext \
+  -import-path ../myblog-proto \
+  -proto proto/blog/service.proto \
+  -d '{"slug": "hello-world"}' \
+  localhost:8082 \
+  myblog.proto.blog.BlogService/GetPostBySlug
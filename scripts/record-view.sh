@@ -0,0 +1,5 @@
+#!/bin/sh
+
+set -e
+
+curl -X POST -i localhost:8092/posts/5b2863365c31b411b041995e/views
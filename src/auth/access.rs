@@ -0,0 +1,158 @@
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+use mongodb::bson::DateTime;
+
+use crate::auth::Claims;
+use crate::auth::user::UserRepository;
+
+/// Return the permissions which are granted by the local role.
+pub fn role_permissions(role: &str) -> &'static [&'static str] {
+    match role {
+        "admin" => &["admin:user", "write:post", "review:post", "publish:post", "write:comment"],
+        "editor" => &["write:post", "review:post", "publish:post"],
+        "reviewer" => &["review:post"],
+        "author" => &["write:post"],
+        _ => &[],
+    }
+}
+
+/// An active ban of the user.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Ban {
+    pub reason: String,
+    /// The ban never expires if it is `None`.
+    pub expires_at: Option<DateTime>,
+}
+
+impl Ban {
+    pub fn is_active(&self) -> bool {
+        self.expires_at.map_or(true, |expires_at| expires_at > DateTime::now())
+    }
+}
+
+/// A cached list of the bans and the local roles of the users which is consulted on every request.
+#[derive(Default)]
+pub struct AccessControl {
+    bans: RwLock<HashMap<String, Ban>>,
+    roles: RwLock<HashMap<String, Vec<String>>>,
+}
+
+impl AccessControl {
+    pub fn new() -> Self {
+        AccessControl::default()
+    }
+
+    /// Return the active ban of the subject, if any.
+    pub fn ban_of(&self, sub: &str) -> Option<Ban> {
+        self.bans.read().unwrap().get(sub).filter(|ban| ban.is_active()).cloned()
+    }
+
+    /// Merge the permissions of the local roles of the subject into the claims.
+    pub fn merge_permissions(&self, claims: &mut Claims) {
+        if let Some(roles) = self.roles.read().unwrap().get(&claims.sub) {
+            for permission in roles.iter().flat_map(|role| role_permissions(role.as_str())) {
+                if !claims.has_permission(permission) {
+                    claims.permissions.push(permission.to_string());
+                }
+            }
+        }
+    }
+
+    pub fn set_ban(&self, sub: &str, ban: Option<Ban>) {
+        let mut bans = self.bans.write().unwrap();
+
+        match ban {
+            Some(ban) => bans.insert(sub.to_owned(), ban),
+            _ => bans.remove(sub),
+        };
+    }
+
+    pub fn set_roles(&self, sub: &str, roles: Vec<String>) {
+        self.roles.write().unwrap().insert(sub.to_owned(), roles);
+    }
+
+    /// Replace the whole cache with the latest bans and roles from the repository.
+    pub async fn refresh(&self, repository: &dyn UserRepository) -> Result<(), Box<dyn std::error::Error>> {
+        let (bans, roles) = repository.find_access_entries().await?;
+
+        *self.bans.write().unwrap() = bans;
+        *self.roles.write().unwrap() = roles;
+
+        Ok(())
+    }
+
+    /// Refresh the cache from the repository in a background task, once every period.
+    pub fn spawn_refresh(self: &Arc<Self>, repository: Box<dyn UserRepository>, period: Duration) {
+        let access_control = self.clone();
+
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(period);
+
+            loop {
+                interval.tick().await;
+
+                if let Err(e) = access_control.refresh(repository.as_ref()).await {
+                    eprintln!("failed to refresh the ban list: {}", e);
+                }
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use mongodb::bson::DateTime;
+
+    use crate::auth::Claims;
+    use crate::auth::access::{AccessControl, Ban};
+
+    #[test]
+    fn ban_of_banned_subject() {
+        // Given
+        let access_control = AccessControl::new();
+        access_control.set_ban("github|1", Some(Ban { reason: String::from("Spam"), expires_at: None }));
+
+        // When
+        let result = access_control.ban_of("github|1");
+
+        // Then
+        assert_eq!("Spam", result.unwrap().reason);
+        assert!(access_control.ban_of("github|2").is_none());
+    }
+
+    #[test]
+    fn ban_of_expired_ban() {
+        // Given
+        let access_control = AccessControl::new();
+        access_control.set_ban("github|1", Some(Ban {
+            reason: String::from("Spam"),
+            expires_at: Some(DateTime::from_millis(DateTime::now().timestamp_millis() - 1000)),
+        }));
+
+        // When
+        let result = access_control.ban_of("github|1");
+
+        // Then
+        assert!(result.is_none());
+    }
+
+    #[test]
+    fn merge_permissions_of_local_roles() {
+        // Given
+        let access_control = AccessControl::new();
+        access_control.set_roles("github|1", vec![String::from("editor")]);
+        let mut claims = Claims {
+            sub: String::from("github|1"),
+            permissions: vec![String::from("write:post")],
+            ..Default::default()
+        };
+
+        // When
+        access_control.merge_permissions(&mut claims);
+
+        // Then
+        assert_eq!(vec!["write:post", "review:post", "publish:post"], claims.permissions);
+    }
+}
@@ -0,0 +1,243 @@
+use mongodb::{bson::Bson, bson::doc, bson::Document, Collection, Cursor};
+use myblog_proto_rust::myblog::proto::auth::ExportDataChunk;
+use serde_json::Value;
+use tokio::sync::mpsc::Sender;
+use tokio_stream::StreamExt;
+use tonic::Status;
+
+/// A size of the archive chunks which are sent to the client.
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// A JSON object writer which emits its output in chunks, so the whole archive never has to be held in memory.
+pub struct ArchiveWriter {
+    buffer: Vec<u8>,
+    /// Whether the next value at each nesting level needs a leading comma.
+    needs_comma: Vec<bool>,
+}
+
+impl Default for ArchiveWriter {
+    fn default() -> Self {
+        ArchiveWriter::new()
+    }
+}
+
+impl ArchiveWriter {
+    pub fn new() -> Self {
+        ArchiveWriter { buffer: b"{".to_vec(), needs_comma: vec![false] }
+    }
+
+    fn separate(&mut self) {
+        if let Some(needs_comma) = self.needs_comma.last_mut() {
+            if *needs_comma {
+                self.buffer.push(b',');
+            }
+            *needs_comma = true;
+        }
+    }
+
+    fn key(&mut self, name: &str) {
+        self.separate();
+        self.buffer.extend_from_slice(Value::from(name).to_string().as_bytes());
+        self.buffer.push(b':');
+    }
+
+    /// Write a field with the value to the archive object.
+    pub fn field(&mut self, name: &str, value: &Value) {
+        self.key(name);
+        self.buffer.extend_from_slice(value.to_string().as_bytes());
+    }
+
+    /// Start an array field, the elements are written with `push` until `end_array`.
+    pub fn begin_array(&mut self, name: &str) {
+        self.key(name);
+        self.buffer.push(b'[');
+        self.needs_comma.push(false);
+    }
+
+    pub fn push(&mut self, value: &Value) {
+        self.separate();
+        self.buffer.extend_from_slice(value.to_string().as_bytes());
+    }
+
+    pub fn end_array(&mut self) {
+        self.buffer.push(b']');
+        self.needs_comma.pop();
+    }
+
+    /// Return the buffered output once it has reached the chunk size.
+    pub fn take_chunk(&mut self) -> Option<Vec<u8>> {
+        if self.buffer.len() >= CHUNK_SIZE {
+            return Some(std::mem::take(&mut self.buffer));
+        }
+
+        None
+    }
+
+    /// Close the archive object and return the remaining output.
+    pub fn finish(mut self) -> Vec<u8> {
+        self.buffer.push(b'}');
+        self.buffer
+    }
+}
+
+/// A personal data exporter definition.
+#[tonic::async_trait]
+pub trait UserDataExporter: Send + Sync + 'static {
+    /// Stream everything tied to the user as a JSON archive to the sender.
+    async fn export(
+        &self,
+        sub: &str,
+        tx: &Sender<Result<ExportDataChunk, Status>>,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>>;
+}
+
+/// An implementation of the UserDataExporter specifies with MongoDB.
+pub struct MongoUserDataExporter {
+    comments: Collection<Document>,
+    posts: Collection<Document>,
+    reactions: Collection<Document>,
+    review_comments: Collection<Document>,
+    users: Collection<Document>,
+}
+
+impl MongoUserDataExporter {
+    pub fn new(
+        comments: Collection<Document>,
+        posts: Collection<Document>,
+        reactions: Collection<Document>,
+        review_comments: Collection<Document>,
+        users: Collection<Document>,
+    ) -> Self {
+        MongoUserDataExporter { comments, posts, reactions, review_comments, users }
+    }
+}
+
+/// Write all documents of the cursor as an array field, sending the chunks as soon as they are full.
+async fn write_cursor(
+    writer: &mut ArchiveWriter,
+    name: &str,
+    mut cursor: Cursor<Document>,
+    tx: &Sender<Result<ExportDataChunk, Status>>,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    writer.begin_array(name);
+
+    while let Some(document) = cursor.try_next().await? {
+        writer.push(&Bson::from(document).into_relaxed_extjson());
+
+        if let Some(data) = writer.take_chunk() {
+            tx.send(Ok(ExportDataChunk { data })).await?;
+        }
+    }
+
+    writer.end_array();
+    Ok(())
+}
+
+#[tonic::async_trait]
+impl UserDataExporter for MongoUserDataExporter {
+    async fn export(
+        &self,
+        sub: &str,
+        tx: &Sender<Result<ExportDataChunk, Status>>,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let mut writer = ArchiveWriter::new();
+
+        writer.field("exportedAt", &Value::from(chrono::Utc::now().to_rfc3339()));
+        writer.field("user", &match self.users.find_one(doc! {"_id": sub}, None).await? {
+            Some(document) => Bson::from(document).into_relaxed_extjson(),
+            _ => Value::Null,
+        });
+
+        // Comments and review comments are exported with the title of the post they belong to
+        let comments = self.comments
+            .aggregate(
+                vec![
+                    doc! {"$match": {"author": sub}},
+                    doc! {"$lookup": {"from": "posts", "localField": "_id", "foreignField": "comments", "as": "post"}},
+                    doc! {"$addFields": {"postTitle": {"$arrayElemAt": ["$post.title", 0]}}},
+                    doc! {"$project": {"post": 0}},
+                ],
+                None,
+            )
+            .await?;
+        write_cursor(&mut writer, "comments", comments, tx).await?;
+
+        let review_comments = self.review_comments
+            .aggregate(
+                vec![
+                    doc! {"$match": {"author": sub}},
+                    doc! {"$lookup": {"from": "posts", "localField": "_id", "foreignField": "reviewComments", "as": "post"}},
+                    doc! {"$addFields": {"postTitle": {"$arrayElemAt": ["$post.title", 0]}}},
+                    doc! {"$project": {"post": 0}},
+                ],
+                None,
+            )
+            .await?;
+        write_cursor(&mut writer, "reviewComments", review_comments, tx).await?;
+
+        let reactions = self.reactions.find(doc! {"user": sub}, None).await?;
+        write_cursor(&mut writer, "reactions", reactions, tx).await?;
+
+        let posts = self.posts
+            .find(doc! {"$or": [{"author": sub}, {"coAuthors": sub}]}, None)
+            .await?;
+        write_cursor(&mut writer, "posts", posts, tx).await?;
+
+        tx.send(Ok(ExportDataChunk { data: writer.finish() })).await?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{json, Value};
+
+    use crate::auth::export::{ArchiveWriter, CHUNK_SIZE};
+
+    #[test]
+    fn write_archive() {
+        // Given
+        let mut writer = ArchiveWriter::new();
+
+        // When
+        writer.field("user", &json!({"_id": "github|1"}));
+        writer.begin_array("comments");
+        writer.push(&json!({"text": "Hello"}));
+        writer.push(&json!({"text": "World"}));
+        writer.end_array();
+        writer.begin_array("posts");
+        writer.end_array();
+        let result: Value = serde_json::from_slice(&writer.finish()).unwrap();
+
+        // Then
+        assert_eq!(
+            json!({"user": {"_id": "github|1"}, "comments": [{"text": "Hello"}, {"text": "World"}], "posts": []}),
+            result,
+        );
+    }
+
+    #[test]
+    fn write_archive_in_chunks() {
+        // Given
+        let mut writer = ArchiveWriter::new();
+        let mut output: Vec<u8> = vec![];
+        let text = "a".repeat(1024);
+
+        // When
+        writer.begin_array("comments");
+        for _ in 0..200 {
+            writer.push(&json!({"text": text}));
+            if let Some(chunk) = writer.take_chunk() {
+                assert!(chunk.len() >= CHUNK_SIZE);
+                output.extend(chunk);
+            }
+        }
+        writer.end_array();
+        output.extend(writer.finish());
+        let result: Value = serde_json::from_slice(&output).unwrap();
+
+        // Then
+        assert_eq!(200, result["comments"].as_array().unwrap().len());
+    }
+}
@@ -1,22 +1,81 @@
+use std::sync::Arc;
+
 use alcoholic_jwt::{JWKS, token_kid, validate, Validation};
+use myblog_proto_rust::myblog::proto::auth::User;
 use serde::{Deserialize, Serialize};
 use tonic::{Request, Status};
 
+use crate::auth::access::AccessControl;
+use crate::auth::user::SyncedProfile;
+
+pub mod access;
+pub mod export;
 pub mod service;
 pub mod user;
 
 /// User context that deserializes from the JSON Web Token string.
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
 pub struct Claims {
     pub sub: String,
+    #[serde(default)]
     pub permissions: Vec<String>,
+
+    /* Standard OpenID Connect profile claims */
+    #[serde(default)]
+    pub name: Option<String>,
+    #[serde(default)]
+    pub picture: Option<String>,
+    #[serde(default)]
+    pub email: Option<String>,
+    #[serde(default)]
+    pub email_verified: Option<bool>,
+}
+
+impl Claims {
+    /// Refresh the user profile with the profile claims which are present in the token.
+    ///
+    /// The display name and picture are only overwritten when the identity provider has changed them
+    /// since the last sync, so the edits which the user made in between are kept.
+    pub fn sync_profile(&self, user: &mut User, synced: &mut SyncedProfile) {
+        if let Some(name) = &self.name {
+            if synced.display_name.as_ref() != Some(name) {
+                user.display_name = name.clone();
+                synced.display_name = Some(name.clone());
+            }
+        }
+        if let Some(picture) = &self.picture {
+            if synced.profile_picture.as_ref() != Some(picture) {
+                user.profile_picture = picture.clone();
+                synced.profile_picture = Some(picture.clone());
+            }
+        }
+        if let Some(email) = &self.email {
+            user.email = email.clone();
+            user.email_verified = self.email_verified.unwrap_or_default();
+        }
+    }
+
+    /// Return true if the permission has been granted to the token subject.
+    pub fn has_permission(&self, permission: &str) -> bool {
+        self.permissions.iter().any(|p| p == permission)
+    }
+}
+
+/// Fetch the JSON Web Key Set which is used to verify the token signatures from the authority.
+pub async fn fetch_jwks(authority: &str) -> Result<JWKS, Box<dyn std::error::Error>> {
+    let url = format!("{}/.well-known/jwks.json", authority.trim_end_matches('/'));
+
+    Ok(reqwest::get(url).await?.json::<JWKS>().await?)
 }
 
 /// The gRPC interceptor for validating and extracting user info from the Bearer token (if exists).
+///
+/// The banned subjects are rejected and the permissions of their local roles are merged into the claims.
 pub fn new_interceptor(
     authority: String,
     audience: String,
     jwks: JWKS,
+    access_control: Arc<AccessControl>,
 ) -> impl FnMut(Request<()>) -> Result<Request<()>, Status> + Clone {
     move |mut r| -> Result<Request<()>, Status> {
         if let Some(metadata) = r.metadata().get("Authorization") {
@@ -42,8 +101,14 @@ pub fn new_interceptor(
             let jwk = jwks.find(&kid).expect("Specified key not found in set");
 
             if let Ok(valid_jwt) = validate(token, jwk, validations) {
-                r.extensions_mut()
-                    .insert(serde_json::from_value::<Claims>(valid_jwt.claims).unwrap());
+                let mut claims = serde_json::from_value::<Claims>(valid_jwt.claims).unwrap();
+
+                if let Some(ban) = access_control.ban_of(claims.sub.as_str()) {
+                    return Err(Status::permission_denied(format!("Banned: {}", ban.reason)));
+                }
+                access_control.merge_permissions(&mut claims);
+
+                r.extensions_mut().insert(claims);
 
                 return Ok(r);
             }
@@ -54,3 +119,76 @@ pub fn new_interceptor(
         Ok(r)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use myblog_proto_rust::myblog::proto::auth::User;
+
+    use crate::auth::Claims;
+    use crate::auth::user::SyncedProfile;
+
+    #[test]
+    fn deserialize_claims_without_profile() {
+        // Given
+        let value = serde_json::json!({"sub": "github|1"});
+
+        // When
+        let claims: Claims = serde_json::from_value(value).unwrap();
+
+        // Then
+        assert_eq!("github|1", claims.sub);
+        assert!(claims.permissions.is_empty());
+        assert!(claims.name.is_none());
+    }
+
+    #[test]
+    fn sync_profile_from_claims() {
+        // Given
+        let value = serde_json::json!({
+            "sub": "github|1",
+            "name": "Natcha Luangaroonchai",
+            "email": "me@nomkhonwaan.com",
+            "email_verified": true,
+        });
+        let claims: Claims = serde_json::from_value(value).unwrap();
+        let mut user = User {
+            display_name: String::from("nomkhonwaan"),
+            profile_picture: String::from("https://example.com/me.png"),
+            ..Default::default()
+        };
+
+        let mut synced = SyncedProfile::default();
+
+        // When
+        claims.sync_profile(&mut user, &mut synced);
+
+        // Then
+        assert_eq!("Natcha Luangaroonchai", user.display_name);
+        assert_eq!("https://example.com/me.png", user.profile_picture);
+        assert_eq!("me@nomkhonwaan.com", user.email);
+        assert!(user.email_verified);
+        assert_eq!(Some(String::from("Natcha Luangaroonchai")), synced.display_name);
+        assert_eq!(None, synced.profile_picture);
+    }
+
+    #[test]
+    fn sync_profile_keeps_edited_display_name() {
+        // Given
+        let value = serde_json::json!({"sub": "github|1", "name": "Natcha Luangaroonchai"});
+        let claims: Claims = serde_json::from_value(value).unwrap();
+        let mut user = User {
+            display_name: String::from("nomkhonwaan"),
+            ..Default::default()
+        };
+        let mut synced = SyncedProfile {
+            display_name: Some(String::from("Natcha Luangaroonchai")),
+            ..Default::default()
+        };
+
+        // When
+        claims.sync_profile(&mut user, &mut synced);
+
+        // Then
+        assert_eq!("nomkhonwaan", user.display_name);
+    }
+}
@@ -1,62 +1,362 @@
+use std::sync::Arc;
+use std::time::SystemTime;
+
 use myblog_proto_rust::myblog::proto::auth::{
     auth_service_server::AuthService,
+    AssignUserRolesRequest, AssignUserRolesResponse,
+    BanUserRequest, BanUserResponse,
     CreateUserRequest, CreateUserResponse,
+    ExportDataChunk, ExportUserDataRequest,
+    GetMeResponse,
+    ListUsersRequest, ListUsersResponse,
+    UnbanUserRequest, UnbanUserResponse,
+    UpdateMeRequest, UpdateMeResponse,
+    User, UserBan,
 };
+use prost_types::Timestamp;
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
 use tonic::{Request, Response, Status};
 
 use crate::auth::Claims;
-use crate::auth::user::UserRepository;
+use crate::auth::access::{AccessControl, Ban, role_permissions};
+use crate::auth::export::UserDataExporter;
+use crate::auth::user::{deleted_user, DELETED_USER_ID, SyncedProfile, UserQuery, UserRepository};
+use crate::blog::post::PostRepository;
+use crate::discussion::comment::CommentRepository;
+use crate::discussion::reaction::ReactionRepository;
+use crate::encoding::bson::to_datetime;
+
+/// A permission which is required to manage the other users.
+const ADMIN_PERMISSION: &str = "admin:user";
 
 pub struct MyAuthService {
+    comment_repository: Box<dyn CommentRepository>,
+    post_repository: Box<dyn PostRepository>,
+    reaction_repository: Box<dyn ReactionRepository>,
+    review_comment_repository: Box<dyn CommentRepository>,
     user_repository: Box<dyn UserRepository>,
+
+    access_control: Arc<AccessControl>,
+    user_data_exporter: Arc<dyn UserDataExporter>,
 }
 
 impl MyAuthService {
     pub fn builder() -> MyAuthServiceBuilder { MyAuthServiceBuilder::default() }
+
+    async fn find_user(&self, id: &str) -> Result<User, Status> {
+        match self.user_repository.find_by_id(id).await {
+            Ok(Some(user)) => Ok(user),
+            Ok(None) => Err(Status::not_found("User not found")),
+            Err(e) => Err(Status::internal(e.to_string())),
+        }
+    }
+
+    /// Stream the personal data archive of the user from a background task.
+    fn export_data(&self, sub: String) -> ReceiverStream<Result<ExportDataChunk, Status>> {
+        let (tx, rx) = mpsc::channel(4);
+        let exporter = self.user_data_exporter.clone();
+
+        tokio::spawn(async move {
+            if let Err(e) = exporter.export(sub.as_str(), &tx).await {
+                let _ = tx.send(Err(Status::internal(e.to_string()))).await;
+            }
+        });
+
+        ReceiverStream::new(rx)
+    }
+}
+
+/// Return the claims of the token subject if the admin permission has been granted.
+fn admin<T>(request: &Request<T>) -> Result<Claims, Status> {
+    match request.extensions().get::<Claims>() {
+        Some(claims) if claims.has_permission(ADMIN_PERMISSION) => Ok(claims.clone()),
+        Some(_) => Err(Status::permission_denied(format!("Missing required '{}' permission", ADMIN_PERMISSION))),
+        _ => Err(Status::unauthenticated("Forbidden")),
+    }
 }
 
 #[tonic::async_trait]
 impl AuthService for MyAuthService {
+    type ExportMyDataStream = ReceiverStream<Result<ExportDataChunk, Status>>;
+    type ExportUserDataStream = ReceiverStream<Result<ExportDataChunk, Status>>;
+
     async fn create_user(&self, request: Request<CreateUserRequest>) -> Result<Response<CreateUserResponse>, Status> {
+        let claims = match request.extensions().get::<Claims>() {
+            Some(claims) => Ok(claims.clone()),
+            _ => Err(Status::unauthenticated("Forbidden")),
+        }?;
+        let now = Timestamp::from(SystemTime::now());
+
+        let existing_user = self.user_repository.find_by_id(claims.sub.as_str()).await
+            .or_else(|err| Err(Status::internal(err.to_string())))?;
+        let mut synced = self.user_repository.find_synced_profile(claims.sub.as_str()).await
+            .or_else(|err| Err(Status::internal(err.to_string())))?;
+        // A login is not a change of the profile, so the update time is left as is
+        let mut user = match existing_user {
+            Some(user) => user,
+            // The client-supplied profile is only used for the claims which are missing from the token
+            _ => {
+                let mut user = request.into_inner().user.unwrap_or_default();
+                user.created_at = Some(now.clone());
+                user.updated_at = None;
+                user
+            }
+        };
+
+        user.id = claims.sub.clone();
+        claims.sync_profile(&mut user, &mut synced);
+        user.last_login_at = Some(now);
+
+        match self.user_repository.upsert(&user, &synced).await {
+            Ok(_) => Ok(Response::new(CreateUserResponse { user: Some(user) })),
+            Err(e) => Err(Status::internal(e.to_string())),
+        }
+    }
+
+    async fn get_me(&self, request: Request<()>) -> Result<Response<GetMeResponse>, Status> {
+        let sub = match request.extensions().get::<Claims>() {
+            Some(claims) => Ok(claims.sub.clone()),
+            _ => Err(Status::unauthenticated("Forbidden")),
+        }?;
+
+        match self.user_repository.find_by_id(sub.as_str()).await {
+            Ok(Some(user)) => Ok(Response::new(GetMeResponse { user: Some(user) })),
+            Ok(None) => Err(Status::not_found("User not found")),
+            Err(e) => Err(Status::internal(e.to_string())),
+        }
+    }
+
+    async fn update_me(&self, request: Request<UpdateMeRequest>) -> Result<Response<UpdateMeResponse>, Status> {
         let sub = match request.extensions().get::<Claims>() {
             Some(claims) => Ok(claims.sub.clone()),
             _ => Err(Status::unauthenticated("Forbidden")),
         }?;
-        let mut user = match request.into_inner().user {
+        let changes = match request.into_inner().user {
             Some(user) => Ok(user),
             _ => Err(Status::invalid_argument("Missing required 'user' field"))
         }?;
 
-        user.id = sub;
+        let mut user = match self.user_repository.find_by_id(sub.as_str()).await {
+            Ok(Some(user)) => Ok(user),
+            Ok(None) => Err(Status::not_found("User not found")),
+            Err(e) => Err(Status::internal(e.to_string())),
+        }?;
+
+        // Only the profile fields can be changed, the ID and creation time are kept as is
+        user.display_name = changes.display_name;
+        user.profile_picture = changes.profile_picture;
+        user.bio = changes.bio;
+        user.social_links = changes.social_links;
+        user.updated_at = Some(Timestamp::from(SystemTime::now()));
+
+        match self.user_repository.update(&user).await {
+            Ok(_) => Ok(Response::new(UpdateMeResponse { user: Some(user) })),
+            Err(e) => Err(Status::internal(e.to_string())),
+        }
+    }
+
+    async fn delete_me(&self, request: Request<()>) -> Result<Response<()>, Status> {
+        let sub = match request.extensions().get::<Claims>() {
+            Some(claims) => Ok(claims.sub.clone()),
+            _ => Err(Status::unauthenticated("Forbidden")),
+        }?;
+
+        // The upsert keeps concurrent deletions from racing to create the same placeholder user
+        self.user_repository.upsert(&deleted_user(), &SyncedProfile::default()).await
+            .or_else(|err| Err(Status::internal(err.to_string())))?;
 
-        let existing_user = self.user_repository.find_by_id(user.id.as_str()).await
+        // Comments are anonymised and posts are handed over to the placeholder user before removing the user,
+        // so nothing refers to the user which no longer exists
+        self.comment_repository.reassign_author(sub.as_str(), DELETED_USER_ID).await
+            .or_else(|err| Err(Status::internal(err.to_string())))?;
+        self.review_comment_repository.reassign_author(sub.as_str(), DELETED_USER_ID).await
+            .or_else(|err| Err(Status::internal(err.to_string())))?;
+        self.reaction_repository.delete_by_user(sub.as_str()).await
+            .or_else(|err| Err(Status::internal(err.to_string())))?;
+        self.post_repository.reassign_author(sub.as_str(), DELETED_USER_ID).await
             .or_else(|err| Err(Status::internal(err.to_string())))?;
-        if existing_user.is_some() {
-            return Ok(Response::new(CreateUserResponse { user: existing_user }));
+
+        match self.user_repository.delete(sub.as_str()).await {
+            Ok(_) => Ok(Response::new(())),
+            Err(e) => Err(Status::internal(e.to_string())),
         }
+    }
 
-        match self.user_repository.create(&user).await {
-            Ok(_) => Ok(Response::new(CreateUserResponse { user: Some(user) })),
+    async fn export_my_data(&self, request: Request<()>) -> Result<Response<Self::ExportMyDataStream>, Status> {
+        let sub = match request.extensions().get::<Claims>() {
+            Some(claims) => Ok(claims.sub.clone()),
+            _ => Err(Status::unauthenticated("Forbidden")),
+        }?;
+
+        Ok(Response::new(self.export_data(sub)))
+    }
+
+    async fn export_user_data(
+        &self,
+        request: Request<ExportUserDataRequest>,
+    ) -> Result<Response<Self::ExportUserDataStream>, Status> {
+        admin(&request)?;
+        let user_id = request.into_inner().user_id;
+        self.find_user(user_id.as_str()).await?;
+
+        Ok(Response::new(self.export_data(user_id)))
+    }
+
+    async fn list_users(&self, request: Request<ListUsersRequest>) -> Result<Response<ListUsersResponse>, Status> {
+        admin(&request)?;
+        let ListUsersRequest { search, offset, limit } = request.into_inner();
+
+        let q = UserQuery::builder()
+            .with_search(search.as_str())
+            .with_offset(offset)
+            .with_limit(if limit > 0 { limit } else { 20 });
+
+        match self.user_repository.find_all(&q).await {
+            Ok(users) => Ok(Response::new(ListUsersResponse { users })),
             Err(e) => Err(Status::internal(e.to_string())),
         }
     }
+
+    async fn assign_user_roles(
+        &self,
+        request: Request<AssignUserRolesRequest>,
+    ) -> Result<Response<AssignUserRolesResponse>, Status> {
+        admin(&request)?;
+        let AssignUserRolesRequest { user_id, roles } = request.into_inner();
+
+        if let Some(role) = roles.iter().find(|role| role_permissions(role.as_str()).is_empty()) {
+            return Err(Status::invalid_argument(format!("Unknown role '{}'", role)));
+        }
+        let mut user = self.find_user(user_id.as_str()).await?;
+
+        self.user_repository.update_roles(user_id.as_str(), &roles).await
+            .or_else(|err| Err(Status::internal(err.to_string())))?;
+        self.access_control.set_roles(user_id.as_str(), roles.clone());
+
+        user.roles = roles;
+        Ok(Response::new(AssignUserRolesResponse { user: Some(user) }))
+    }
+
+    async fn ban_user(&self, request: Request<BanUserRequest>) -> Result<Response<BanUserResponse>, Status> {
+        let claims = admin(&request)?;
+        let BanUserRequest { user_id, reason, expires_at, hide_comments } = request.into_inner();
+
+        if user_id == claims.sub {
+            return Err(Status::invalid_argument("Cannot ban yourself"));
+        }
+        let now = Timestamp::from(SystemTime::now());
+        if let Some(expires_at) = &expires_at {
+            if expires_at.seconds <= now.seconds {
+                return Err(Status::invalid_argument("Ban must expire in the future"));
+            }
+        }
+        let mut user = self.find_user(user_id.as_str()).await?;
+
+        let ban = UserBan {
+            reason,
+            banned_by: claims.sub.clone(),
+            banned_at: Some(now),
+            expires_at,
+            hide_comments,
+        };
+        self.user_repository.ban(user_id.as_str(), &ban).await
+            .or_else(|err| Err(Status::internal(err.to_string())))?;
+        if hide_comments {
+            self.comment_repository.set_hidden_by_author(user_id.as_str(), true).await
+                .or_else(|err| Err(Status::internal(err.to_string())))?;
+            self.review_comment_repository.set_hidden_by_author(user_id.as_str(), true).await
+                .or_else(|err| Err(Status::internal(err.to_string())))?;
+        }
+
+        // The other services pick the ban up on their next refresh of the ban list
+        self.access_control.set_ban(user_id.as_str(), Some(Ban {
+            reason: ban.reason.clone(),
+            expires_at: ban.expires_at.as_ref().map(to_datetime),
+        }));
+
+        user.ban = Some(ban);
+        Ok(Response::new(BanUserResponse { user: Some(user) }))
+    }
+
+    async fn unban_user(&self, request: Request<UnbanUserRequest>) -> Result<Response<UnbanUserResponse>, Status> {
+        admin(&request)?;
+        let user_id = request.into_inner().user_id;
+        let mut user = self.find_user(user_id.as_str()).await?;
+
+        self.user_repository.unban(user_id.as_str()).await
+            .or_else(|err| Err(Status::internal(err.to_string())))?;
+        if user.ban.as_ref().map_or(false, |ban| ban.hide_comments) {
+            self.comment_repository.set_hidden_by_author(user_id.as_str(), false).await
+                .or_else(|err| Err(Status::internal(err.to_string())))?;
+            self.review_comment_repository.set_hidden_by_author(user_id.as_str(), false).await
+                .or_else(|err| Err(Status::internal(err.to_string())))?;
+        }
+        self.access_control.set_ban(user_id.as_str(), None);
+
+        user.ban = None;
+        Ok(Response::new(UnbanUserResponse { user: Some(user) }))
+    }
 }
 
 #[derive(Default)]
 pub struct MyAuthServiceBuilder {
     /* Repository */
+    comment_repository: Option<Box<dyn CommentRepository>>,
+    post_repository: Option<Box<dyn PostRepository>>,
+    reaction_repository: Option<Box<dyn ReactionRepository>>,
+    review_comment_repository: Option<Box<dyn CommentRepository>>,
     user_repository: Option<Box<dyn UserRepository>>,
+
+    access_control: Option<Arc<AccessControl>>,
+    user_data_exporter: Option<Arc<dyn UserDataExporter>>,
 }
 
 impl MyAuthServiceBuilder {
+    pub fn with_comment_repository(mut self, repository: Box<dyn CommentRepository>) -> Self {
+        self.comment_repository = Some(repository);
+        self
+    }
+
+    pub fn with_post_repository(mut self, repository: Box<dyn PostRepository>) -> Self {
+        self.post_repository = Some(repository);
+        self
+    }
+
+    pub fn with_reaction_repository(mut self, repository: Box<dyn ReactionRepository>) -> Self {
+        self.reaction_repository = Some(repository);
+        self
+    }
+
+    pub fn with_review_comment_repository(mut self, repository: Box<dyn CommentRepository>) -> Self {
+        self.review_comment_repository = Some(repository);
+        self
+    }
+
     pub fn with_user_repository(mut self, repository: Box<dyn UserRepository>) -> Self {
         self.user_repository = Some(repository);
         self
     }
 
+    pub fn with_access_control(mut self, access_control: Arc<AccessControl>) -> Self {
+        self.access_control = Some(access_control);
+        self
+    }
+
+    pub fn with_user_data_exporter(mut self, exporter: Arc<dyn UserDataExporter>) -> Self {
+        self.user_data_exporter = Some(exporter);
+        self
+    }
+
     pub fn build(self) -> MyAuthService {
         MyAuthService {
+            comment_repository: self.comment_repository.unwrap(),
+            post_repository: self.post_repository.unwrap(),
+            reaction_repository: self.reaction_repository.unwrap(),
+            review_comment_repository: self.review_comment_repository.unwrap(),
             user_repository: self.user_repository.unwrap(),
+            access_control: self.access_control.unwrap_or_default(),
+            user_data_exporter: self.user_data_exporter.unwrap(),
         }
     }
 }
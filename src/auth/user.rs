@@ -1,26 +1,131 @@
+use std::collections::HashMap;
 use std::time::SystemTime;
 
-use mongodb::{bson::DateTime, bson::doc, bson::Document, Collection};
-use myblog_proto_rust::myblog::proto::auth::User;
+use mongodb::{bson::DateTime, bson::doc, bson::Document, bson::Regex, Collection};
+use mongodb::options::{FindOneOptions, FindOptions, UpdateOptions};
+use myblog_proto_rust::myblog::proto::auth::{User, UserBan};
 use prost_types::Timestamp;
+use tokio_stream::StreamExt;
 
-use crate::encoding::bson::{Marshaler, Unmarshaler};
+use crate::auth::access::Ban;
+use crate::encoding::bson::{ListingMode, Marshaler, Unmarshaler};
+
+/// An ID of the placeholder user which takes over the content of the deleted users.
+pub const DELETED_USER_ID: &str = "deleted-user";
+
+/// Return the placeholder user which takes over the content of the deleted users.
+pub fn deleted_user() -> User {
+    User {
+        id: DELETED_USER_ID.to_owned(),
+        display_name: String::from("Deleted user"),
+        created_at: Some(Timestamp::from(SystemTime::now())),
+        ..Default::default()
+    }
+}
+
+/// The profile claims of the identity provider which have been copied into the user on the last login.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SyncedProfile {
+    pub display_name: Option<String>,
+    pub profile_picture: Option<String>,
+}
 
 /// A user repository definition.
 #[tonic::async_trait]
 pub trait UserRepository: Send + Sync + 'static {
     async fn create(&self, u: &User) -> Result<(), Box<dyn std::error::Error>>;
     async fn find_by_id(&self, id: &str) -> Result<Option<User>, Box<dyn std::error::Error>>;
+    async fn find_synced_profile(&self, id: &str) -> Result<SyncedProfile, Box<dyn std::error::Error>>;
+    async fn find_all(&self, q: &UserQuery) -> Result<Vec<User>, Box<dyn std::error::Error>>;
+    async fn find_all_by_ids(&self, ids: &[&str]) -> Result<Vec<User>, Box<dyn std::error::Error>>;
+    /// Return the active bans and the local roles of all users, keyed by the user ID.
+    async fn find_access_entries(
+        &self,
+    ) -> Result<(HashMap<String, Ban>, HashMap<String, Vec<String>>), Box<dyn std::error::Error>>;
+    async fn update(&self, u: &User) -> Result<(), Box<dyn std::error::Error>>;
+    async fn update_roles(&self, id: &str, roles: &[String]) -> Result<(), Box<dyn std::error::Error>>;
+    async fn ban(&self, id: &str, ban: &UserBan) -> Result<(), Box<dyn std::error::Error>>;
+    async fn unban(&self, id: &str) -> Result<(), Box<dyn std::error::Error>>;
+    /// Insert or replace the user, along with the profile claims which have been synced into it.
+    async fn upsert(&self, u: &User, synced: &SyncedProfile) -> Result<(), Box<dyn std::error::Error>>;
+    async fn delete(&self, id: &str) -> Result<(), Box<dyn std::error::Error>>;
+}
+
+/// A user query builder.
+#[derive(Default)]
+pub struct UserQuery {
+    /* Filters */
+    search: Option<String>,
+
+    /* Pagination Options */
+    offset: u32,
+    limit: u32,
+}
+
+impl UserQuery {
+    pub fn builder() -> Self {
+        UserQuery {
+            offset: 0,
+            limit: 20,
+            ..Default::default()
+        }
+    }
+
+    /// Search the users by display name or email, case-insensitively.
+    pub fn with_search(mut self, search: &str) -> Self {
+        self.search = if search.trim().is_empty() { None } else { Some(search.trim().to_owned()) };
+        self
+    }
+
+    pub fn with_offset(mut self, offset: u32) -> Self {
+        self.offset = offset;
+        self
+    }
+
+    pub fn with_limit(mut self, limit: u32) -> Self {
+        self.limit = limit;
+        self
+    }
+
+    fn filter(&self) -> Document {
+        match &self.search {
+            Some(search) => {
+                let pattern = Regex { pattern: escape_regex(search.as_str()), options: String::from("i") };
+
+                doc! {"$or": [{"displayName": pattern.clone()}, {"email": pattern}]}
+            }
+            _ => doc! {},
+        }
+    }
+}
+
+/// Escape the regular expression metacharacters, so the search is matched literally.
+fn escape_regex(s: &str) -> String {
+    s.chars()
+        .fold(String::with_capacity(s.len()), |mut result, c| {
+            if "\\^$.|?*+()[]{}".contains(c) {
+                result.push('\\');
+            }
+            result.push(c);
+            result
+        })
 }
 
 /// An implementation of the UserRepository specifies with MongoDB.
 pub struct MongoUserRepository {
     collection: Collection<Document>,
+    listing_mode: ListingMode,
 }
 
 impl MongoUserRepository {
     pub fn new(collection: Collection<Document>) -> Self {
-        MongoUserRepository { collection }
+        MongoUserRepository { collection, listing_mode: ListingMode::default() }
+    }
+
+    /// Set how the listings of the users treat the documents which cannot be un-marshaled.
+    pub fn with_listing_mode(mut self, listing_mode: ListingMode) -> Self {
+        self.listing_mode = listing_mode;
+        self
     }
 }
 
@@ -41,46 +146,253 @@ impl UserRepository for MongoUserRepository {
 
         Ok(None)
     }
-}
 
-impl Marshaler for User {
-    fn marshal_bson(&self) -> Result<Document, mongodb::bson::oid::Error> {
-        let mut document = doc! {
-            "_id": self.id.as_str(),
-            "displayName": self.display_name.as_str(),
-            "profilePicture": self.profile_picture.as_str(),
-            "createdAt": DateTime::from_millis(self.created_at.as_ref().unwrap().seconds * 1000),
-        };
-
-        if self.updated_at.is_some() {
-            document.insert(
-                "updatedAt",
-                DateTime::from_millis(self.updated_at.as_ref().unwrap().seconds * 1000),
-            );
+    async fn find_synced_profile(&self, id: &str) -> Result<SyncedProfile, Box<dyn std::error::Error>> {
+        let options = FindOneOptions::builder().projection(doc! {"syncedProfile": 1}).build();
+
+        match self.collection.find_one(doc! {"_id": id}, options).await? {
+            Some(document) => match document.get_document("syncedProfile") {
+                Ok(synced) => Ok(SyncedProfile {
+                    display_name: synced.get_str("displayName").ok().map(str::to_owned),
+                    profile_picture: synced.get_str("profilePicture").ok().map(str::to_owned),
+                }),
+                _ => Ok(SyncedProfile::default()),
+            },
+            _ => Ok(SyncedProfile::default()),
         }
+    }
+
+    async fn find_all(&self, q: &UserQuery) -> Result<Vec<User>, Box<dyn std::error::Error>> {
+        let options = FindOptions::builder()
+            .sort(doc! {"createdAt": -1})
+            .skip(q.offset as u64)
+            .limit(q.limit as i64)
+            .build();
 
-        Ok(document)
+        let mut cursor = self.collection.find(q.filter(), options).await?;
+        let mut result: Vec<User> = vec![];
+
+        while let Some(document) = cursor.try_next().await? {
+            if let Some(user) = self.listing_mode.unmarshal(&document)? {
+                result.push(user);
+            }
+        }
+
+        Ok(result)
+    }
+
+    async fn find_all_by_ids(&self, ids: &[&str]) -> Result<Vec<User>, Box<dyn std::error::Error>> {
+        let filter = doc! {"_id": {"$in": ids}};
+
+        let mut cursor = self.collection.find(filter, None).await?;
+        let mut result: Vec<User> = vec![];
+
+        while let Some(document) = cursor.try_next().await? {
+            if let Some(user) = self.listing_mode.unmarshal(&document)? {
+                result.push(user);
+            }
+        }
+
+        Ok(result)
+    }
+
+    async fn find_access_entries(
+        &self,
+    ) -> Result<(HashMap<String, Ban>, HashMap<String, Vec<String>>), Box<dyn std::error::Error>> {
+        let filter = doc! {"$or": [{"ban": {"$exists": true}}, {"roles.0": {"$exists": true}}]};
+        let options = FindOptions::builder().projection(doc! {"ban": 1, "roles": 1}).build();
+
+        let mut cursor = self.collection.find(filter, options).await?;
+        let mut bans: HashMap<String, Ban> = HashMap::new();
+        let mut roles: HashMap<String, Vec<String>> = HashMap::new();
+
+        while let Some(document) = cursor.try_next().await? {
+            let id = document.get_str("_id")?.to_owned();
+
+            if let Ok(ban) = document.get_document("ban") {
+                let ban = Ban {
+                    reason: ban.get_str("reason").unwrap_or_default().to_owned(),
+                    expires_at: ban.get_datetime("expiresAt").ok().cloned(),
+                };
+                if ban.is_active() {
+                    bans.insert(id.clone(), ban);
+                }
+            }
+            if let Ok(user_roles) = document.get_array("roles") {
+                roles.insert(id, user_roles.iter().filter_map(|role| role.as_str()).map(String::from).collect());
+            }
+        }
+
+        Ok((bans, roles))
+    }
+
+    async fn update(&self, u: &User) -> Result<(), Box<dyn std::error::Error>> {
+        let mut document = u.marshal_bson()?;
+        document.remove("_id");
+        document.remove("createdAt");
+
+        self.collection
+            .update_one(doc! {"_id": u.id.as_str()}, doc! {"$set": document}, None)
+            .await?;
+
+        Ok(())
+    }
+
+    async fn update_roles(&self, id: &str, roles: &[String]) -> Result<(), Box<dyn std::error::Error>> {
+        self.collection
+            .update_one(
+                doc! {"_id": id},
+                doc! {"$set": {"roles": roles, "updatedAt": DateTime::now()}},
+                None,
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    async fn ban(&self, id: &str, ban: &UserBan) -> Result<(), Box<dyn std::error::Error>> {
+        let ban = UserBan { banned_at: Some(Timestamp::from(SystemTime::now())), ..ban.clone() };
+
+        self.collection
+            .update_one(doc! {"_id": id}, doc! {"$set": {"ban": ban.marshal_bson()?}}, None)
+            .await?;
+
+        Ok(())
+    }
+
+    async fn unban(&self, id: &str) -> Result<(), Box<dyn std::error::Error>> {
+        self.collection
+            .update_one(doc! {"_id": id}, doc! {"$unset": {"ban": ""}}, None)
+            .await?;
+
+        Ok(())
+    }
+
+    async fn upsert(&self, u: &User, synced: &SyncedProfile) -> Result<(), Box<dyn std::error::Error>> {
+        let mut document = u.marshal_bson()?;
+        document.remove("_id");
+        let created_at = document.remove("createdAt");
+        if let Some(display_name) = &synced.display_name {
+            document.insert("syncedProfile.displayName", display_name.as_str());
+        }
+        if let Some(profile_picture) = &synced.profile_picture {
+            document.insert("syncedProfile.profilePicture", profile_picture.as_str());
+        }
+
+        self.collection
+            .update_one(
+                doc! {"_id": u.id.as_str()},
+                doc! {"$set": document, "$setOnInsert": {"createdAt": created_at}},
+                UpdateOptions::builder().upsert(true).build(),
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    async fn delete(&self, id: &str) -> Result<(), Box<dyn std::error::Error>> {
+        self.collection.delete_one(doc! {"_id": id}, None).await?;
+
+        Ok(())
     }
 }
 
-impl Unmarshaler for User {
-    fn unmarshal_bson(
-        document: &Document,
-    ) -> Result<Self, mongodb::bson::document::ValueAccessError>
-        where
-            Self: Sized,
-    {
-        Ok(User {
-            id: document.get_str("_id")?.to_owned(),
-            display_name: document.get_str("displayName")?.to_owned(),
-            profile_picture: document.get_str("profilePicture")?.to_owned(),
-            created_at: Some(document.get_datetime("createdAt").and_then(|created_at| {
-                Ok(Timestamp::from(SystemTime::from(created_at.to_owned())))
-            })?),
-            updated_at: match document.get_datetime("updatedAt") {
-                Ok(updated_at) => Some(Timestamp::from(SystemTime::from(updated_at.to_owned()))),
-                _ => None,
-            },
-        })
+/// A schema of the stored user, the roles and ban are managed by their own updates and only read.
+#[derive(Marshaler, Unmarshaler)]
+#[bson(remote = "User")]
+struct UserSchema {
+    #[bson(rename = "_id")]
+    id: String,
+    display_name: String,
+    profile_picture: String,
+    #[bson(optional)]
+    email: String,
+    #[bson(optional)]
+    email_verified: bool,
+    #[bson(optional)]
+    bio: String,
+    #[bson(optional)]
+    social_links: HashMap<String, String>,
+    #[bson(timestamp)]
+    created_at: Option<Timestamp>,
+    #[bson(timestamp, optional)]
+    updated_at: Option<Timestamp>,
+    #[bson(timestamp, optional)]
+    last_login_at: Option<Timestamp>,
+    #[bson(optional, read_only)]
+    roles: Vec<String>,
+    #[bson(embedded, optional, read_only)]
+    ban: Option<UserBan>,
+}
+
+#[derive(Marshaler, Unmarshaler)]
+#[bson(remote = "UserBan")]
+struct UserBanSchema {
+    #[bson(optional)]
+    reason: String,
+    #[bson(optional)]
+    banned_by: String,
+    #[bson(timestamp, optional)]
+    banned_at: Option<Timestamp>,
+    #[bson(timestamp, optional)]
+    expires_at: Option<Timestamp>,
+    #[bson(optional)]
+    hide_comments: bool,
+}
+
+#[cfg(test)]
+mod tests {
+    use myblog_proto_rust::myblog::proto::auth::User;
+    use proptest::prelude::*;
+
+    use crate::auth::user::{escape_regex, UserQuery};
+    use crate::encoding::bson::{arbitrary, Marshaler, Unmarshaler};
+
+    #[test]
+    fn init_user_query() {
+        // Given
+
+        // When
+        let q = UserQuery::builder();
+
+        // Then
+        assert_eq!(None, q.search);
+        assert_eq!(0, q.offset);
+        assert_eq!(20, q.limit);
+    }
+
+    #[test]
+    fn user_query_with_blank_search() {
+        // Given
+
+        // When
+        let q = UserQuery::builder().with_search("  ");
+
+        // Then
+        assert_eq!(None, q.search);
+    }
+
+    #[test]
+    fn escape_regex_metacharacters() {
+        // Given
+
+        // When
+        let result = escape_regex("me+blog@example.com");
+
+        // Then
+        assert_eq!("me\\+blog@example\\.com", result);
+    }
+
+    proptest! {
+        #[test]
+        fn round_trip_user(user in arbitrary::user()) {
+            // Given
+
+            // When
+            let result = User::unmarshal_bson(&user.marshal_bson().unwrap()).unwrap();
+
+            // Then
+            prop_assert_eq!(user, result);
+        }
     }
 }
@@ -1,4 +1,6 @@
 use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
 
 use clap::{Arg, Command};
 use mongodb::{bson::doc, Client, Database, options::ClientOptions};
@@ -6,9 +8,17 @@ use myblog_proto_rust::myblog::proto::auth::auth_service_server::AuthServiceServ
 use tonic::transport::Server;
 
 use myblog_api::auth::{
+    access::AccessControl,
+    export::MongoUserDataExporter,
+    fetch_jwks,
+    new_interceptor,
     service::MyAuthService,
     user::MongoUserRepository,
 };
+use myblog_api::blog::post::MongoPostRepository;
+use myblog_api::discussion::comment::MongoCommentRepository;
+use myblog_api::discussion::reaction::MongoReactionRepository;
+use myblog_api::encoding::bson::ListingMode;
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
@@ -29,36 +39,83 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                 .takes_value(true)
                 .required(true),
         )
-        // .arg(
-        //     Arg::new("authority")
-        //         .help("Specify the address of the token-issuing authentication server")
-        //         .long("authority")
-        //         .takes_value(true)
-        //         .required(true),
-        // )
-        // .arg(
-        //     Arg::new("audience")
-        //         .help("Specify the resource server that should accept the token")
-        //         .long("audience")
-        //         .takes_value(true)
-        //         .required(true),
-        // )
+        .arg(
+            Arg::new("authority")
+                .help("Specify the address of the token-issuing authentication server")
+                .long("authority")
+                .takes_value(true)
+                .required(true),
+        )
+        .arg(
+            Arg::new("audience")
+                .help("Specify the resource server that should accept the token")
+                .long("audience")
+                .takes_value(true)
+                .required(true),
+        )
+        .arg(
+            Arg::new("tolerant-listings")
+                .help("Log and skip the documents which cannot be decoded instead of failing the whole listing")
+                .long("tolerant-listings"),
+        )
         .get_matches();
 
     let addr: SocketAddr = matches.value_of("listen-address").unwrap().parse().unwrap();
+    let listing_mode = if matches.is_present("tolerant-listings") {
+        ListingMode::Tolerant
+    } else {
+        ListingMode::Strict
+    };
     let database = connect_mongodb(
         matches.value_of("mongodb-uri").unwrap(),
         &"beta_nomkhonwaan_com",
     ).await?;
 
+    // Bans and local roles are cached and refreshed every minute, so they apply across all services
+    let access_control = Arc::new(AccessControl::new());
+    access_control.spawn_refresh(
+        Box::from(MongoUserRepository::new(database.collection("users"))),
+        Duration::from_secs(60),
+    );
+    let authority = matches.value_of("authority").unwrap();
+    let interceptor = new_interceptor(
+        authority.to_owned(),
+        matches.value_of("audience").unwrap().to_owned(),
+        fetch_jwks(authority).await?,
+        access_control.clone(),
+    );
+
     println!("auth-service listening on {}", addr);
     Server::builder()
-        .add_service(AuthServiceServer::new(
+        .add_service(AuthServiceServer::with_interceptor(
             MyAuthService::builder()
-                .with_user_repository(Box::from(MongoUserRepository::new(
+                .with_comment_repository(Box::from(MongoCommentRepository::new(
+                    database.collection("comments"),
+                )))
+                .with_post_repository(Box::from(
+                    MongoPostRepository::new(database.collection("posts")).with_listing_mode(listing_mode),
+                ))
+                .with_reaction_repository(Box::from(MongoReactionRepository::new(
+                    database.collection("reactions"),
+                    database.collection("posts"),
+                    database.collection("comments"),
+                )))
+                .with_review_comment_repository(Box::from(MongoCommentRepository::new(
+                    database.collection("reviewComments"),
+                )))
+                .with_user_repository(Box::from(
+                    MongoUserRepository::new(database.collection("users")).with_listing_mode(listing_mode),
+                ))
+                .with_access_control(access_control)
+                .with_user_data_exporter(Arc::new(MongoUserDataExporter::new(
+                    database.collection("comments"),
+                    database.collection("posts"),
+                    database.collection("reactions"),
+                    database.collection("reviewComments"),
                     database.collection("users"),
                 )))
                 .build(),
+            interceptor,
         ))
         .serve(addr)
         .await?;
@@ -1,21 +1,45 @@
 use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
 
+use chrono_tz::Tz;
 use clap::{Arg, Command};
 use mongodb::{bson::doc, Client, Database, options::ClientOptions};
 use myblog_proto_rust::myblog::proto::blog::blog_service_server::BlogServiceServer;
 use tonic::transport::Server;
 
+use myblog_api::auth::{
+    access::AccessControl,
+    fetch_jwks,
+    new_interceptor,
+    user::MongoUserRepository,
+};
 use myblog_api::blog::{
-    post::MongoPostRepository,
+    post::{MongoPostRepository, PostRepository},
+    preview::{MongoPreviewTokenRepository, PreviewTokenSigner},
+    series::MongoSeriesRepository,
     service::MyBlogService,
+    slug,
     taxonomy::MongoTaxonomyRepository,
+    view::{self, MongoViewRepository, ViewCounter},
+};
+use myblog_api::discussion::{
+    comment::MongoCommentRepository,
+    reaction::MongoReactionRepository,
 };
+use myblog_api::encoding::bson::ListingMode;
+use myblog_api::storage::file::MongoFileRepository;
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let matches = Command::new("blog-service")
         .override_help("Part of myblog-api provides all blogging APIs")
         .version("3.0.0")
+        .subcommand_negates_reqs(true)
+        .subcommand(
+            Command::new("backfill-content")
+                .about("Derive the excerpt, word count and reading time of all existing posts, then exit"),
+        )
         .arg(
             Arg::new("listen-address")
                 .default_value("[::1]:8082")
@@ -23,6 +47,13 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                 .long("listen-address")
                 .takes_value(true),
         )
+        .arg(
+            Arg::new("http-listen-address")
+                .default_value("[::1]:8092")
+                .help("Specify the host/IP and port to which HTTP server binds for listening")
+                .long("http-listen-address")
+                .takes_value(true),
+        )
         .arg(
             Arg::new("mongodb-uri")
                 .help("Specify URI which can be used to create a MongoDB instance")
@@ -30,25 +61,153 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                 .takes_value(true)
                 .required(true),
         )
+        .arg(
+            Arg::new("authority")
+                .help("Specify the address of the token-issuing authentication server")
+                .long("authority")
+                .takes_value(true)
+                .required(true),
+        )
+        .arg(
+            Arg::new("audience")
+                .help("Specify the resource server that should accept the token")
+                .long("audience")
+                .takes_value(true)
+                .required(true),
+        )
+        .arg(
+            Arg::new("preview-token-secret")
+                .help("Specify the secret key which is used to sign the draft preview tokens")
+                .long("preview-token-secret")
+                .takes_value(true)
+                .required(true),
+        )
+        .arg(
+            Arg::new("time-zone")
+                .default_value("Asia/Bangkok")
+                .help("Specify the IANA time zone which is used for bucketing the archives by year and month")
+                .long("time-zone")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::new("tolerant-listings")
+                .help("Log and skip the documents which cannot be decoded instead of failing the whole listing")
+                .long("tolerant-listings"),
+        )
         .get_matches();
 
     let addr: SocketAddr = matches.value_of("listen-address").unwrap().parse().unwrap();
+    let http_addr: SocketAddr = matches.value_of("http-listen-address").unwrap().parse().unwrap();
+    let time_zone: Tz = matches.value_of("time-zone").unwrap().parse()?;
+    let listing_mode = if matches.is_present("tolerant-listings") {
+        ListingMode::Tolerant
+    } else {
+        ListingMode::Strict
+    };
     let database = connect_mongodb(
-        matches.value_of("mongodb-uri").unwrap(),
+        // The requirements are not enforced by clap when a subcommand is given
+        matches.value_of("mongodb-uri").ok_or("The argument '--mongodb-uri <mongodb-uri>' was not provided")?,
         &"beta_nomkhonwaan_com",
     ).await?;
 
-    println!("blog-service listening on {}", addr);
+    if matches.subcommand_matches("backfill-content").is_some() {
+        let n = MongoPostRepository::new(database.collection("posts")).backfill_content_summaries().await?;
+        println!("backfilled the content summaries of {} post(s)", n);
+
+        return Ok(());
+    }
+    slug::create_indexes(&database.collection("posts")).await?;
+    slug::create_indexes(&database.collection("taxonomies")).await?;
+
+    // Bans and local roles are cached and refreshed every minute, so they apply across all services
+    let access_control = Arc::new(AccessControl::new());
+    access_control.spawn_refresh(
+        Box::from(MongoUserRepository::new(database.collection("users"))),
+        Duration::from_secs(60),
+    );
+    let authority = matches.value_of("authority").unwrap();
+    let interceptor = new_interceptor(
+        authority.to_owned(),
+        matches.value_of("audience").unwrap().to_owned(),
+        fetch_jwks(authority).await?,
+        access_control.clone(),
+    );
+
+    let scheduled_post_repository = MongoPostRepository::new(database.collection("posts"));
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_secs(60));
+
+        loop {
+            interval.tick().await;
+
+            match scheduled_post_repository.publish_scheduled().await {
+                Ok(n) if n > 0 => println!("published {} scheduled post(s)", n),
+                Err(e) => eprintln!("failed to publish scheduled posts: {}", e),
+                _ => (),
+            }
+        }
+    });
+
+    // Visitors are counted once per post within 30 minutes, pending counts are flushed every minute
+    // and the published posts, which are the only ones counted, are refreshed along with them
+    let view_counter = Arc::new(ViewCounter::new(Duration::from_secs(30 * 60)));
+    let view_repository = MongoViewRepository::new(database.collection("postViews"));
+    let published_post_repository = MongoPostRepository::new(database.collection("posts"));
+    let flushing_view_counter = view_counter.clone();
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_secs(60));
+
+        loop {
+            interval.tick().await;
+
+            if let Err(e) = flushing_view_counter.refresh(&published_post_repository).await {
+                eprintln!("failed to refresh the published posts: {}", e);
+            }
+            if let Err(e) = flushing_view_counter.flush(&view_repository).await {
+                eprintln!("failed to flush post views: {}", e);
+            }
+        }
+    });
+
+    println!("blog-service listening on {} (HTTP on {})", addr, http_addr);
+    tokio::spawn(warp::serve(view::routes(view_counter.clone())).run(http_addr));
     Server::builder()
-        .add_service(BlogServiceServer::new(
+        .add_service(BlogServiceServer::with_interceptor(
             MyBlogService::builder()
-                .with_post_repository(Box::from(MongoPostRepository::new(
+                .with_file_repository(Box::from(MongoFileRepository::new(
+                    database.collection("files"),
+                )))
+                .with_post_repository(Box::from(
+                    MongoPostRepository::new(database.collection("posts")).with_listing_mode(listing_mode),
+                ))
+                .with_preview_token_repository(Box::from(MongoPreviewTokenRepository::new(
+                    database.collection("previewTokens"),
+                )))
+                .with_reaction_repository(Box::from(MongoReactionRepository::new(
+                    database.collection("reactions"),
                     database.collection("posts"),
+                    database.collection("comments"),
+                )))
+                .with_review_comment_repository(Box::from(MongoCommentRepository::new(
+                    database.collection("reviewComments"),
                 )))
-                .with_taxonomy_repository(Box::from(MongoTaxonomyRepository::new(
+                .with_series_repository(Box::from(MongoSeriesRepository::new(
+                    database.client().clone(),
                     database.collection("taxonomies"),
                 )))
+                .with_taxonomy_repository(Box::from(
+                    MongoTaxonomyRepository::new(database.collection("taxonomies")).with_listing_mode(listing_mode),
+                ))
+                .with_user_repository(Box::from(
+                    MongoUserRepository::new(database.collection("users")).with_listing_mode(listing_mode),
+                ))
+                .with_preview_token_signer(PreviewTokenSigner::new(
+                    matches.value_of("preview-token-secret").unwrap().as_bytes(),
+                ))
+                .with_view_counter(view_counter)
+                .with_time_zone(time_zone)
                 .build(),
+            interceptor,
         ))
         .serve(addr)
         .await?;
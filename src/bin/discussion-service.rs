@@ -1,12 +1,21 @@
 use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
 
 use clap::{Arg, Command};
 use mongodb::{bson::doc, Client, Database, options::ClientOptions};
 use myblog_proto_rust::myblog::proto::discussion::discussion_service_server::DiscussionServiceServer;
 use tonic::transport::Server;
 
+use myblog_api::auth::{
+    access::AccessControl,
+    fetch_jwks,
+    new_interceptor,
+    user::MongoUserRepository,
+};
 use myblog_api::discussion::{
     comment::MongoCommentRepository,
+    reaction::MongoReactionRepository,
     service::MyDiscussionService,
 };
 
@@ -29,6 +38,27 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                 .takes_value(true)
                 .required(true),
         )
+        .arg(
+            Arg::new("authority")
+                .help("Specify the address of the token-issuing authentication server")
+                .long("authority")
+                .takes_value(true)
+                .required(true),
+        )
+        .arg(
+            Arg::new("audience")
+                .help("Specify the resource server that should accept the token")
+                .long("audience")
+                .takes_value(true)
+                .required(true),
+        )
+        .arg(
+            Arg::new("reactions")
+                .default_value("like,love,haha,wow,sad")
+                .help("Specify the comma-separated set of reactions which can be given on posts and comments")
+                .long("reactions")
+                .takes_value(true),
+        )
         .get_matches();
 
     let addr: SocketAddr = matches.value_of("listen-address").unwrap().parse().unwrap();
@@ -37,14 +67,44 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         &"beta_nomkhonwaan_com",
     ).await?;
 
+    // Bans and local roles are cached and refreshed every minute, so they apply across all services
+    let access_control = Arc::new(AccessControl::new());
+    access_control.spawn_refresh(
+        Box::from(MongoUserRepository::new(database.collection("users"))),
+        Duration::from_secs(60),
+    );
+    let authority = matches.value_of("authority").unwrap();
+    let interceptor = new_interceptor(
+        authority.to_owned(),
+        matches.value_of("audience").unwrap().to_owned(),
+        fetch_jwks(authority).await?,
+        access_control.clone(),
+    );
+
+    let reaction_repository = MongoReactionRepository::new(
+        database.collection("reactions"),
+        database.collection("posts"),
+        database.collection("comments"),
+    );
+    reaction_repository.create_indexes().await?;
+
     println!("discussion-service listening on {}", addr);
     Server::builder()
-        .add_service(DiscussionServiceServer::new(
+        .add_service(DiscussionServiceServer::with_interceptor(
             MyDiscussionService::builder()
                 .with_comment_repository(Box::from(MongoCommentRepository::new(
                     database.collection("comments"),
                 )))
-                .build()
+                .with_reaction_repository(Box::from(reaction_repository))
+                .with_reactions(
+                    matches.value_of("reactions").unwrap()
+                        .split(',')
+                        .map(|reaction| reaction.trim().to_owned())
+                        .filter(|reaction| !reaction.is_empty())
+                        .collect(),
+                )
+                .build(),
+            interceptor,
         ))
         .serve(addr)
         .await?;
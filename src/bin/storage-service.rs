@@ -0,0 +1,332 @@
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+use clap::{Arg, Command};
+use mongodb::{bson::doc, Client, Database, options::ClientOptions};
+use myblog_proto_rust::myblog::proto::storage::storage_service_server::StorageServiceServer;
+use tonic::transport::Server;
+
+use myblog_api::auth::{
+    access::AccessControl,
+    fetch_jwks,
+    new_interceptor,
+    user::MongoUserRepository,
+};
+use myblog_api::blog::post::MongoPostRepository;
+use myblog_api::storage::{
+    backend::StorageBackend,
+    file::MongoFileRepository,
+    gc::{GarbageCollector, GcReport},
+    local::LocalStorageBackend,
+    s3::S3StorageBackend,
+    serve::{self, UrlSigner},
+    service::MyStorageService,
+    usage::MongoUsageRepository,
+    validate::{parse_size_limits, UploadLimits},
+};
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let matches = Command::new("storage-service")
+        .override_help("Part of myblog-api provides all file storage APIs")
+        .version("3.0.0")
+        .subcommand_negates_reqs(true)
+        .subcommand(
+            Command::new("gc")
+                .about("Collect the files which are no longer referenced by any post, then exit")
+                .arg(
+                    Arg::new("dry-run")
+                        .help("Report what would be collected without marking or deleting anything")
+                        .long("dry-run"),
+                ),
+        )
+        .arg(
+            Arg::new("listen-address")
+                .default_value("[::1]:8084")
+                .help("Specify the host/IP and port to which gRPC server binds for listening")
+                .long("listen-address")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::new("http-listen-address")
+                .default_value("[::1]:8094")
+                .help("Specify the host/IP and port to which HTTP server binds for listening")
+                .long("http-listen-address")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::new("mongodb-uri")
+                .help("Specify URI which can be used to create a MongoDB instance")
+                .long("mongodb-uri")
+                .takes_value(true)
+                .required(true),
+        )
+        .arg(
+            Arg::new("authority")
+                .help("Specify the address of the token-issuing authentication server")
+                .long("authority")
+                .takes_value(true)
+                .required(true),
+        )
+        .arg(
+            Arg::new("audience")
+                .help("Specify the resource server that should accept the token")
+                .long("audience")
+                .takes_value(true)
+                .required(true),
+        )
+        .arg(
+            Arg::new("file-url-secret")
+                .help("Specify the secret key which is used to sign the expiring URLs of the private files")
+                .long("file-url-secret")
+                .takes_value(true)
+                .required(true),
+        )
+        .arg(
+            Arg::new("file-base-url")
+                .default_value("http://[::1]:8094/files")
+                .help("Specify the base URL from which the stored files are served")
+                .long("file-base-url")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::new("image-widths")
+                .default_value("320,640,1280")
+                .help("Specify the comma-separated widths of the derivatives which are produced from every uploaded image")
+                .long("image-widths")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::new("upload-size-limits")
+                .default_value("image/*=10,*=50")
+                .help("Specify the comma-separated size limits in MiB by MIME type, which is either exact, type/* or *")
+                .long("upload-size-limits")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::new("max-image-dimension")
+                .default_value("10000")
+                .help("Specify the maximum width and height in pixels of the uploaded images")
+                .long("max-image-dimension")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::new("max-image-pixels")
+                .default_value("40000000")
+                .help("Specify the maximum number of pixels of the uploaded images")
+                .long("max-image-pixels")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::new("storage-quota")
+                .default_value("1024")
+                .help("Specify the total size in MiB which each user can upload")
+                .long("storage-quota")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::new("storage-provider")
+                .default_value("local")
+                .possible_values(["local", "s3"])
+                .help("Specify the backend to which the uploaded files are stored")
+                .long("storage-provider")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::new("local-storage-root")
+                .default_value("./uploads")
+                .help("Specify the directory to which the local backend stores the uploaded files")
+                .long("local-storage-root")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::new("s3-endpoint")
+                .help("Specify the endpoint of an S3-compatible server (e.g. MinIO), Amazon S3 is used if omitted")
+                .long("s3-endpoint")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::new("s3-region")
+                .default_value("ap-southeast-1")
+                .help("Specify the region of the S3 bucket")
+                .long("s3-region")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::new("s3-bucket")
+                .help("Specify the name of the S3 bucket")
+                .long("s3-bucket")
+                .takes_value(true)
+                .required_if_eq("storage-provider", "s3"),
+        )
+        .arg(
+            Arg::new("s3-access-key")
+                .help("Specify the access key of the S3 bucket")
+                .long("s3-access-key")
+                .takes_value(true)
+                .required_if_eq("storage-provider", "s3"),
+        )
+        .arg(
+            Arg::new("s3-secret-key")
+                .help("Specify the secret key of the S3 bucket")
+                .long("s3-secret-key")
+                .takes_value(true)
+                .required_if_eq("storage-provider", "s3"),
+        )
+        .arg(
+            Arg::new("gc-interval-minutes")
+                .default_value("60")
+                .help("Specify how often the orphaned files are collected, 0 disables the scheduled collection")
+                .long("gc-interval-minutes")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::new("gc-grace-period-hours")
+                .default_value("24")
+                .help("Specify how long a file must stay unreferenced before it is collected")
+                .long("gc-grace-period-hours")
+                .takes_value(true),
+        )
+        .get_matches();
+
+    let addr: SocketAddr = matches.value_of("listen-address").unwrap().parse().unwrap();
+    let http_addr: SocketAddr = matches.value_of("http-listen-address").unwrap().parse().unwrap();
+    let database = connect_mongodb(
+        // The requirements are not enforced by clap when a subcommand is given
+        matches.value_of("mongodb-uri").ok_or("The argument '--mongodb-uri <mongodb-uri>' was not provided")?,
+        &"beta_nomkhonwaan_com",
+    ).await?;
+
+    let storage_backend: Arc<dyn StorageBackend> = match matches.value_of("storage-provider").unwrap() {
+        "s3" => Arc::new(S3StorageBackend::new(
+            matches.value_of("s3-endpoint"),
+            matches.value_of("s3-region").unwrap(),
+            matches.value_of("s3-bucket").ok_or("The argument '--s3-bucket <s3-bucket>' was not provided")?,
+            matches.value_of("s3-access-key").ok_or("The argument '--s3-access-key <s3-access-key>' was not provided")?,
+            matches.value_of("s3-secret-key").ok_or("The argument '--s3-secret-key <s3-secret-key>' was not provided")?,
+        )?),
+        _ => Arc::new(LocalStorageBackend::new(PathBuf::from(
+            matches.value_of("local-storage-root").unwrap(),
+        ))),
+    };
+
+    let gc_grace_period = Duration::from_secs(matches.value_of("gc-grace-period-hours").unwrap().parse::<u64>()? * 60 * 60);
+    let new_garbage_collector = || GarbageCollector::new(
+        Box::from(MongoFileRepository::new(database.collection("files"))),
+        Box::from(MongoPostRepository::new(database.collection("posts"))),
+        Box::from(MongoUsageRepository::new(database.collection("storageUsage"))),
+        storage_backend.clone(),
+        gc_grace_period,
+    );
+
+    if let Some(gc_matches) = matches.subcommand_matches("gc") {
+        let dry_run = gc_matches.is_present("dry-run");
+        print_report(&new_garbage_collector().run(dry_run).await?, dry_run);
+
+        return Ok(());
+    }
+
+    let gc_interval_minutes = matches.value_of("gc-interval-minutes").unwrap().parse::<u64>()?;
+    if gc_interval_minutes > 0 {
+        let garbage_collector = new_garbage_collector();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(Duration::from_secs(gc_interval_minutes * 60));
+
+            loop {
+                interval.tick().await;
+
+                match garbage_collector.run(false).await {
+                    Ok(report) => print_report(&report, false),
+                    Err(e) => eprintln!("failed to collect the orphaned files: {}", e),
+                }
+            }
+        });
+    }
+
+    // Bans and local roles are cached and refreshed every minute, so they apply across all services
+    let access_control = Arc::new(AccessControl::new());
+    access_control.spawn_refresh(
+        Box::from(MongoUserRepository::new(database.collection("users"))),
+        Duration::from_secs(60),
+    );
+    let authority = matches.value_of("authority").unwrap();
+    let interceptor = new_interceptor(
+        authority.to_owned(),
+        matches.value_of("audience").unwrap().to_owned(),
+        fetch_jwks(authority).await?,
+        access_control.clone(),
+    );
+
+    let url_signer = Arc::new(UrlSigner::new(matches.value_of("file-url-secret").unwrap().as_bytes()));
+
+    println!("storage-service listening on {} (HTTP on {})", addr, http_addr);
+    tokio::spawn(warp::serve(serve::routes(
+        Arc::new(MongoFileRepository::new(database.collection("files"))),
+        storage_backend.clone(),
+        url_signer.clone(),
+    )).run(http_addr));
+    Server::builder()
+        .add_service(StorageServiceServer::with_interceptor(
+            MyStorageService::builder()
+                .with_file_repository(Box::from(MongoFileRepository::new(
+                    database.collection("files"),
+                )))
+                .with_usage_repository(Box::from(MongoUsageRepository::new(
+                    database.collection("storageUsage"),
+                )))
+                .with_storage_backend(storage_backend)
+                .with_url_signer(url_signer)
+                .with_file_base_url(matches.value_of("file-base-url").unwrap())
+                .with_image_widths(
+                    matches.value_of("image-widths").unwrap()
+                        .split(',')
+                        .map(|width| width.trim().parse())
+                        .collect::<Result<Vec<u32>, _>>()?,
+                )
+                .with_upload_limits(UploadLimits {
+                    size_limits: parse_size_limits(matches.value_of("upload-size-limits").unwrap())?,
+                    max_image_dimension: matches.value_of("max-image-dimension").unwrap().parse()?,
+                    max_image_pixels: matches.value_of("max-image-pixels").unwrap().parse()?,
+                    quota: matches.value_of("storage-quota").unwrap().parse::<u64>()? * 1024 * 1024,
+                })
+                .build(),
+            interceptor,
+        ))
+        .serve(addr)
+        .await?;
+
+    Ok(())
+}
+
+/// Print the files and objects which have been collected, or would be on a dry run.
+fn print_report(report: &GcReport, dry_run: bool) {
+    let action = if dry_run { "would delete" } else { "deleted" };
+
+    println!("gc: {} files newly marked as orphaned", report.marked);
+    for file in report.orphaned_files.iter() {
+        println!("gc: {} file {} ({})", action, file.id, file.uploaded_file_path);
+    }
+    for path in report.orphaned_objects.iter() {
+        println!("gc: {} object without metadata {}", action, path);
+    }
+    for failure in report.failures.iter() {
+        eprintln!("gc: failed to delete {}", failure);
+    }
+}
+
+/// Perform a database connection to MongoDB.
+async fn connect_mongodb(uri: &str, database: &str) -> Result<Database, mongodb::error::Error> {
+    let client_options = ClientOptions::parse(uri).await?;
+    let client = Client::with_options(client_options)?;
+
+    match client
+        .database(database)
+        .run_command(doc! {"ping": 1}, None)
+        .await
+    {
+        Ok(_) => Ok(client.database(database)),
+        Err(e) => Err(e),
+    }
+}
@@ -0,0 +1,58 @@
+use chrono::TimeZone;
+use chrono_tz::Tz;
+use prost_types::Timestamp;
+
+/// Return the start (inclusive) and the end (exclusive) of the month in the time zone.
+pub fn month_range(tz: &Tz, year: i32, month: u32) -> Option<(Timestamp, Timestamp)> {
+    let (next_year, next_month) = if month == 12 { (year + 1, 1) } else { (year, month + 1) };
+
+    let start = tz.with_ymd_and_hms(year, month, 1, 0, 0, 0).single()?;
+    let end = tz.with_ymd_and_hms(next_year, next_month, 1, 0, 0, 0).single()?;
+
+    Some((
+        Timestamp { seconds: start.timestamp(), nanos: 0 },
+        Timestamp { seconds: end.timestamp(), nanos: 0 },
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono_tz::Asia::Bangkok;
+
+    use crate::blog::archive::month_range;
+
+    #[test]
+    fn month_range_in_bangkok() {
+        // Given
+
+        // When
+        let (start, end) = month_range(&Bangkok, 2021, 6).unwrap();
+
+        // Then
+        assert_eq!(1622480400, start.seconds);
+        assert_eq!(1625072400, end.seconds);
+    }
+
+    #[test]
+    fn month_range_of_december() {
+        // Given
+
+        // When
+        let (start, end) = month_range(&Bangkok, 2021, 12).unwrap();
+
+        // Then
+        assert_eq!(1638291600, start.seconds);
+        assert_eq!(1640970000, end.seconds);
+    }
+
+    #[test]
+    fn month_range_of_invalid_month() {
+        // Given
+
+        // When
+        let result = month_range(&Bangkok, 2021, 13);
+
+        // Then
+        assert_eq!(None, result);
+    }
+}
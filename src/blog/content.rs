@@ -0,0 +1,179 @@
+use icu_segmenter::{SentenceSegmenter, WordSegmenter};
+use pulldown_cmark::{Event, Options, Parser, Tag};
+
+/// An average number of words read per minute which is used to estimate the reading time.
+const WORDS_PER_MINUTE: u32 = 200;
+
+/// A maximum number of characters in the excerpt.
+const EXCERPT_LENGTH: usize = 200;
+
+/// A summary of the post content which is derived from its markdown.
+#[derive(Debug, Default, PartialEq)]
+pub struct ContentSummary {
+    pub excerpt: String,
+    pub word_count: u32,
+    /// An estimated reading time, in minutes.
+    pub reading_time: u32,
+}
+
+/// Analyze the markdown and return its summary.
+pub fn analyze(markdown: &str) -> ContentSummary {
+    let text = plain_text(markdown);
+    let word_count = word_count(text.as_str());
+
+    ContentSummary {
+        excerpt: excerpt(text.as_str(), EXCERPT_LENGTH),
+        word_count,
+        reading_time: word_count.div_ceil(WORDS_PER_MINUTE),
+    }
+}
+
+/// Render the markdown into plain text, code blocks and raw HTML are left out.
+pub fn plain_text(markdown: &str) -> String {
+    let mut text = String::new();
+    let mut in_code_block = false;
+
+    // The heading attributes are parsed the same way as the table of contents, so `{#id}` is not part of the text
+    for event in Parser::new_ext(markdown, Options::ENABLE_HEADING_ATTRIBUTES) {
+        match event {
+            Event::Start(Tag::CodeBlock(_)) => in_code_block = true,
+            Event::End(Tag::CodeBlock(_)) => in_code_block = false,
+            Event::Text(t) | Event::Code(t) if !in_code_block => text.push_str(&t),
+            Event::SoftBreak | Event::HardBreak => text.push(' '),
+            Event::End(Tag::Paragraph) | Event::End(Tag::Heading(..)) | Event::End(Tag::Item) => {
+                text.push('\n')
+            }
+            _ => (),
+        }
+    }
+
+    text.trim().to_owned()
+}
+
+/// Count words in the text, the words of the languages without spaces (e.g. Thai) are found by dictionary.
+pub fn word_count(text: &str) -> u32 {
+    let breakpoints: Vec<usize> = WordSegmenter::new_dictionary().segment_str(text).collect();
+
+    breakpoints
+        .windows(2)
+        .filter(|w| text[w[0]..w[1]].chars().any(char::is_alphanumeric))
+        .count() as u32
+}
+
+/// Return the leading sentences of the text which fit into `max_chars` characters.
+///
+/// If even the first sentence does not fit, it is cut at the last word boundary and followed by an ellipsis.
+pub fn excerpt(text: &str, max_chars: usize) -> String {
+    let text = text.split_whitespace().collect::<Vec<&str>>().join(" ");
+    if text.chars().count() <= max_chars {
+        return text;
+    }
+
+    let fits = |end: &usize| text[..*end].chars().count() <= max_chars;
+
+    let sentence_end = SentenceSegmenter::new()
+        .segment_str(text.as_str())
+        .skip(1)
+        .take_while(fits)
+        .last();
+    if let Some(end) = sentence_end {
+        return text[..end].trim().to_owned();
+    }
+
+    let word_end = WordSegmenter::new_dictionary()
+        .segment_str(text.as_str())
+        .skip(1)
+        .take_while(|end| text[..*end].chars().count() < max_chars)
+        .last()
+        .unwrap_or_default();
+
+    format!("{}…", text[..word_end].trim_end())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::blog::content::{analyze, excerpt, plain_text, word_count};
+
+    #[test]
+    fn plain_text_of_markdown() {
+        // Given
+        let markdown = "# Hello\n\nThis is **my** `blog`.\n\n```rust\nfn main() {}\n```\n";
+
+        // When
+        let result = plain_text(markdown);
+
+        // Then
+        assert_eq!("Hello\nThis is my blog.", result);
+    }
+
+    #[test]
+    fn plain_text_without_heading_attributes() {
+        // Given
+        let markdown = "# Hello {#intro}\n\nWorld\n";
+
+        // When
+        let result = plain_text(markdown);
+
+        // Then
+        assert_eq!("Hello\nWorld", result);
+    }
+
+    #[test]
+    fn word_count_of_english_text() {
+        // Given
+
+        // When
+        let result = word_count("Hello, world! This is my blog.");
+
+        // Then
+        assert_eq!(6, result);
+    }
+
+    #[test]
+    fn word_count_of_thai_text() {
+        // Given
+
+        // When
+        let result = word_count("สวัสดีครับ ยินดีต้อนรับสู่บล็อกของผม");
+
+        // Then
+        assert_eq!(8, result);
+    }
+
+    #[test]
+    fn excerpt_at_sentence_boundary() {
+        // Given
+        let text = "First sentence. Second sentence. Third sentence.";
+
+        // When
+        let result = excerpt(text, 35);
+
+        // Then
+        assert_eq!("First sentence. Second sentence.", result);
+    }
+
+    #[test]
+    fn excerpt_of_long_sentence() {
+        // Given
+        let text = "This sentence is too long to fit";
+
+        // When
+        let result = excerpt(text, 16);
+
+        // Then
+        assert_eq!("This sentence…", result);
+    }
+
+    #[test]
+    fn analyze_reading_time() {
+        // Given
+        let markdown = "word ".repeat(401);
+
+        // When
+        let result = analyze(markdown.as_str());
+
+        // Then
+        assert_eq!(401, result.word_count);
+        assert_eq!(3, result.reading_time);
+    }
+}
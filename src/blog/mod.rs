@@ -1,3 +1,12 @@
+pub mod archive;
+pub mod content;
 pub mod service;
 pub mod post;
+pub mod preview;
+pub mod related;
+pub mod series;
+pub mod slug;
 pub mod taxonomy;
+pub mod toc;
+pub mod view;
+pub mod workflow;
@@ -1,35 +1,106 @@
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::fmt;
 use std::str::FromStr;
-use std::time::SystemTime;
 
-use mongodb::{bson::doc, bson::oid::ObjectId, bson::Document, Collection};
+use mongodb::{bson::Bson, bson::DateTime, bson::doc, bson::oid::ObjectId, bson::Document, Collection};
+use mongodb::options::FindOptions;
 use myblog_proto_rust::myblog::proto::{
     auth::User,
-    blog::{Post, PostStatus, Taxonomy},
-    discussion::Comment,
+    blog::{Archive, Post, PostStatus, Taxonomy},
+    discussion::{Comment, ReactionCount},
     storage::File,
 };
 use prost_types::Timestamp;
 use tokio_stream::StreamExt;
 use tonic;
 
-use crate::encoding::bson::Unmarshaler;
+use crate::blog::content;
+use crate::blog::slug::{self, SlugLookup};
+use crate::discussion::reaction::unmarshal_reaction_counts;
+use crate::encoding::bson::{ListingMode, Marshaler, to_datetime, Unmarshaler};
+
+/// An error returned when the new order of the attachments does not contain exactly the current attachments.
+#[derive(Debug)]
+pub struct AttachmentMismatchError {
+    pub id: String,
+}
+
+impl fmt::Display for AttachmentMismatchError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "New order must contain exactly the current attachments of post '{}'", self.id)
+    }
+}
+
+impl Error for AttachmentMismatchError {}
+
+/// An error returned when the post is no longer in the status which it has been moved from.
+#[derive(Debug)]
+pub struct StatusMismatchError {
+    pub id: String,
+    pub from: PostStatus,
+}
+
+impl fmt::Display for StatusMismatchError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Post '{}' is no longer {:?}", self.id, self.from)
+    }
+}
+
+impl Error for StatusMismatchError {}
+
+/// A number of milliseconds in a day.
+const DAY_IN_MILLIS: i64 = 24 * 60 * 60 * 1000;
 
 /// A post repository definition.
 #[tonic::async_trait]
 pub trait PostRepository: Send + Sync + 'static {
     async fn find_by_id(&self, id: &str) -> Result<Option<Post>, Box<dyn std::error::Error>>;
     async fn find_all(&self, q: &PostQuery) -> Result<Vec<Post>, Box<dyn std::error::Error>>;
+    async fn find_archives(&self, time_zone: &str) -> Result<Vec<Archive>, Box<dyn std::error::Error>>;
+    async fn count_published_by_author(&self, author: &str) -> Result<u64, Box<dyn std::error::Error>>;
+    async fn count_taxonomies(&self) -> Result<(u64, HashMap<String, u64>), Box<dyn std::error::Error>>;
+    async fn find_popular(&self, days: u32, q: &PostQuery) -> Result<Vec<Post>, Box<dyn std::error::Error>>;
+    /// Return the IDs of all published posts.
+    async fn find_published_ids(&self) -> Result<HashSet<String>, Box<dyn std::error::Error>>;
+    async fn find_by_slug(&self, slug: &str) -> Result<SlugLookup<Post>, Box<dyn std::error::Error>>;
+    async fn update_slug(&self, id: &str, slug: &str) -> Result<bool, Box<dyn std::error::Error>>;
+    async fn update_content(&self, id: &str, markdown: &str, html: &str) -> Result<(), Box<dyn std::error::Error>>;
+    /// Derive the excerpt, word count and reading time of all posts again, return the number of changed posts.
+    async fn backfill_content_summaries(&self) -> Result<u64, Box<dyn std::error::Error>>;
+    async fn update_status(&self, id: &str, from: PostStatus, status: PostStatus, published_at: Option<Timestamp>) -> Result<(), Box<dyn std::error::Error>>;
+    async fn update_reviewers(&self, id: &str, reviewers: &[String]) -> Result<(), Box<dyn std::error::Error>>;
+    async fn update_co_authors(&self, id: &str, co_authors: &[String]) -> Result<(), Box<dyn std::error::Error>>;
+    async fn publish_scheduled(&self) -> Result<u64, Box<dyn std::error::Error>>;
+    async fn reassign_author(&self, from: &str, to: &str) -> Result<(), Box<dyn std::error::Error>>;
+    async fn find_review_comments(&self, id: &str) -> Result<Vec<Comment>, Box<dyn std::error::Error>>;
+    async fn push_review_comment(&self, id: &str, comment_id: &str) -> Result<(), Box<dyn std::error::Error>>;
     // async fn find_post_comments(&self, id: &str, q: &PostQuery) -> Result<Vec<Comment>, Box<dyn std::error::Error>>;
-    // async fn find_post_attachments(&self, id: &str) -> Result<Vec<File>, Box<dyn std::error::Error>>;
+    async fn find_post_attachments(&self, id: &str) -> Result<Vec<File>, Box<dyn std::error::Error>>;
+    async fn push_attachment(&self, id: &str, file_id: &str) -> Result<(), Box<dyn std::error::Error>>;
+    async fn pull_attachment(&self, id: &str, file_id: &str) -> Result<(), Box<dyn std::error::Error>>;
+    async fn reorder_attachments(&self, id: &str, file_ids: &[String]) -> Result<(), Box<dyn std::error::Error>>;
+    /// Return the IDs of all files which are referenced as a featured image or an attachment.
+    async fn find_referenced_files(&self) -> Result<Vec<String>, Box<dyn std::error::Error>>;
 }
 
 /// A post query builder.
 #[derive(Default)]
 pub struct PostQuery {
     /* Filters */
+    ids: Vec<String>,
     status: Option<PostStatus>,
     category: Option<Taxonomy>,
     tag: Option<Taxonomy>,
+    author: Option<String>,
+    taxonomies: Vec<String>,
+    exclude: Option<String>,
+    reviewer: Option<String>,
+    published_after: Option<Timestamp>,
+    published_before: Option<Timestamp>,
+
+    /* Projection Options */
+    content: bool,
 
     /* Pagination Options */
     offset: u32,
@@ -45,6 +116,11 @@ impl PostQuery {
         }
     }
 
+    pub fn with_ids(mut self, ids: Vec<String>) -> Self {
+        self.ids = ids;
+        self
+    }
+
     pub fn with_status(mut self, status: PostStatus) -> Self {
         self.status = Some(status);
         self
@@ -60,6 +136,44 @@ impl PostQuery {
         self
     }
 
+    /// Filter posts which are written by the user, either as the author or one of the co-authors.
+    pub fn with_author(mut self, author: &str) -> Self {
+        self.author = Some(author.to_owned());
+        self
+    }
+
+    /// Filter posts which have at least one of the categories or tags.
+    pub fn with_taxonomies(mut self, taxonomies: Vec<String>) -> Self {
+        self.taxonomies = taxonomies;
+        self
+    }
+
+    pub fn with_exclude(mut self, id: &str) -> Self {
+        self.exclude = Some(id.to_owned());
+        self
+    }
+
+    pub fn with_reviewer(mut self, reviewer: &str) -> Self {
+        self.reviewer = Some(reviewer.to_owned());
+        self
+    }
+
+    pub fn with_published_after(mut self, published_after: Option<Timestamp>) -> Self {
+        self.published_after = published_after;
+        self
+    }
+
+    pub fn with_published_before(mut self, published_before: Option<Timestamp>) -> Self {
+        self.published_before = published_before;
+        self
+    }
+
+    /// Include the full `markdown` and `html` in the result, index pages only need the summary.
+    pub fn with_content(mut self, content: bool) -> Self {
+        self.content = content;
+        self
+    }
+
     pub fn with_offset(mut self, offset: u32) -> Self {
         self.offset = offset;
         self
@@ -74,20 +188,30 @@ impl PostQuery {
 /// An implementation of the PostRepository specifies with MongoDB.
 pub struct MongoPostRepository {
     collection: Collection<Document>,
+    listing_mode: ListingMode,
 }
 
 impl MongoPostRepository {
     pub fn new(collection: Collection<Document>) -> Self {
-        MongoPostRepository { collection }
+        MongoPostRepository { collection, listing_mode: ListingMode::default() }
+    }
+
+    /// Set how the listings of the posts treat the documents which cannot be un-marshaled.
+    pub fn with_listing_mode(mut self, listing_mode: ListingMode) -> Self {
+        self.listing_mode = listing_mode;
+        self
     }
 }
 
 #[tonic::async_trait]
 impl PostRepository for MongoPostRepository {
     async fn find_by_id(&self, id: &str) -> Result<Option<Post>, Box<dyn std::error::Error>> {
-        let filter = doc! {"_id": ObjectId::from_str(id)?};
+        let mut pipeline = vec![doc! {"$match": {"_id": ObjectId::from_str(id)?}}];
+        pipeline.append(&mut lookup_stages());
+
+        let mut cursor = self.collection.aggregate(pipeline, None).await?;
 
-        if let Some(document) = self.collection.find_one(filter, None).await? {
+        if let Some(document) = cursor.try_next().await? {
             return Ok(Some(Post::unmarshal_bson(&document)?));
         }
 
@@ -97,6 +221,15 @@ impl PostRepository for MongoPostRepository {
     async fn find_all(&self, q: &PostQuery) -> Result<Vec<Post>, Box<dyn std::error::Error>> {
         let mut pipeline: Vec<Document> = vec![];
 
+        if !q.ids.is_empty() {
+            let ids = q.ids
+                .iter()
+                .map(|id| ObjectId::from_str(id.as_str()))
+                .collect::<Result<Vec<ObjectId>, _>>()?;
+
+            pipeline.push(doc! {"$match": {"_id": {"$in": ids}}});
+        }
+
         if let Some(status) = q.status {
             pipeline.push(doc! {"$match": {"status": status as i32}});
 
@@ -116,14 +249,45 @@ impl PostRepository for MongoPostRepository {
             pipeline
                 .push(doc! {"$match": {"tags": ObjectId::from_str(tag.id.as_str())?}});
         }
+        if let Some(author) = &q.author {
+            pipeline.push(doc! {"$match": author_filter(author.as_str())});
+        }
+        if !q.taxonomies.is_empty() {
+            let taxonomies = q.taxonomies
+                .iter()
+                .map(|id| ObjectId::from_str(id.as_str()))
+                .collect::<Result<Vec<ObjectId>, _>>()?;
+
+            pipeline.push(doc! {"$match": {"$or": [
+                {"categories": {"$in": &taxonomies}},
+                {"tags": {"$in": &taxonomies}},
+            ]}});
+        }
+        if let Some(exclude) = &q.exclude {
+            pipeline.push(doc! {"$match": {"_id": {"$ne": ObjectId::from_str(exclude.as_str())?}}});
+        }
+        if let Some(reviewer) = &q.reviewer {
+            pipeline.push(doc! {"$match": {"reviewers": reviewer.as_str()}});
+        }
+        if q.published_after.is_some() || q.published_before.is_some() {
+            let mut published_at = Document::new();
+
+            if let Some(published_after) = &q.published_after {
+                published_at.insert("$gte", to_datetime(published_after));
+            }
+            if let Some(published_before) = &q.published_before {
+                published_at.insert("$lt", to_datetime(published_before));
+            }
+
+            pipeline.push(doc! {"$match": {"publishedAt": published_at}});
+        }
+
+        if !q.content {
+            pipeline.push(doc! {"$project": {"markdown": 0, "html": 0}});
+        }
 
+        pipeline.append(&mut lookup_stages());
         pipeline.append(&mut vec![
-            doc! {"$lookup": {"from": "users", "localField": "author", "foreignField": "_id", "as": "author"}},
-            doc! {"$unwind": {"path": "$author"}},
-            doc! {"$lookup": {"from": "taxonomies", "localField": "categories", "foreignField": "_id", "as": "categories"}},
-            doc! {"$lookup": {"from": "taxonomies", "localField": "tags", "foreignField": "_id", "as": "tags"}},
-            doc! {"$lookup": {"from": "files", "localField": "featuredImage", "foreignField": "_id", "as": "featuredImage"}},
-            doc! {"$unwind": {"path": "$featuredImage", "preserveNullAndEmptyArrays": true}},
             doc! {"$skip": q.offset as i64},
             doc! {"$limit": q.limit as i64},
         ]);
@@ -132,16 +296,343 @@ impl PostRepository for MongoPostRepository {
         let mut result: Vec<Post> = vec![];
 
         while let Some(document) = cursor.try_next().await? {
-            result.push(Post::unmarshal_bson(&document)?);
+            if let Some(post) = self.listing_mode.unmarshal(&document)? {
+                result.push(post);
+            }
+        }
+
+        Ok(result)
+    }
+
+    async fn find_archives(&self, time_zone: &str) -> Result<Vec<Archive>, Box<dyn std::error::Error>> {
+        let pipeline = vec![
+            doc! {"$match": {"status": PostStatus::Published as i32, "publishedAt": {"$type": "date"}}},
+            doc! {"$group": {
+                "_id": {
+                    "year": {"$year": {"date": "$publishedAt", "timezone": time_zone}},
+                    "month": {"$month": {"date": "$publishedAt", "timezone": time_zone}},
+                },
+                "count": {"$sum": 1},
+            }},
+            doc! {"$sort": {"_id.year": -1, "_id.month": -1}},
+        ];
+
+        let mut cursor = self.collection.aggregate(pipeline, None).await?;
+        let mut result: Vec<Archive> = vec![];
+
+        while let Some(document) = cursor.try_next().await? {
+            let id = document.get_document("_id")?;
+
+            result.push(Archive {
+                year: id.get_i32("year")?,
+                month: id.get_i32("month")?,
+                count: document.get_i32("count")?,
+            });
+        }
+
+        Ok(result)
+    }
+
+    async fn count_published_by_author(&self, author: &str) -> Result<u64, Box<dyn std::error::Error>> {
+        let mut filter = author_filter(author);
+        filter.insert("status", PostStatus::Published as i32);
+
+        Ok(self.collection.count_documents(filter, None).await?)
+    }
+
+    async fn count_taxonomies(&self) -> Result<(u64, HashMap<String, u64>), Box<dyn std::error::Error>> {
+        let total = self.collection
+            .count_documents(doc! {"status": PostStatus::Published as i32}, None)
+            .await?;
+
+        let pipeline = vec![
+            doc! {"$match": {"status": PostStatus::Published as i32}},
+            doc! {"$project": {"taxonomies": {"$concatArrays": [
+                {"$ifNull": ["$categories", []]},
+                {"$ifNull": ["$tags", []]},
+            ]}}},
+            doc! {"$unwind": {"path": "$taxonomies"}},
+            doc! {"$group": {"_id": "$taxonomies", "count": {"$sum": 1}}},
+        ];
+
+        let mut cursor = self.collection.aggregate(pipeline, None).await?;
+        let mut frequencies: HashMap<String, u64> = HashMap::new();
+
+        while let Some(document) = cursor.try_next().await? {
+            frequencies.insert(
+                document.get_object_id("_id")?.to_hex(),
+                document.get_i32("count")? as u64,
+            );
+        }
+
+        Ok((total, frequencies))
+    }
+
+    async fn find_popular(&self, days: u32, q: &PostQuery) -> Result<Vec<Post>, Box<dyn std::error::Error>> {
+        let now = DateTime::now();
+        let since = DateTime::from_millis(now.timestamp_millis() - days as i64 * DAY_IN_MILLIS);
+        // Views lose half of their weight every half of the period
+        let half_life = days as i64 * DAY_IN_MILLIS / 2;
+
+        let mut pipeline = vec![
+            doc! {"$match": {"status": PostStatus::Published as i32}},
+            doc! {"$lookup": {
+                "from": "postViews",
+                "let": {"post": "$_id"},
+                "pipeline": [
+                    {"$match": {"$expr": {"$and": [{"$eq": ["$post", "$$post"]}, {"$gte": ["$day", since]}]}}},
+                    {"$project": {"weighted": {"$multiply": [
+                        "$count",
+                        {"$pow": [0.5, {"$divide": [{"$subtract": [now, "$day"]}, half_life]}]},
+                    ]}}},
+                ],
+                "as": "views",
+            }},
+            doc! {"$addFields": {"popularity": {"$sum": "$views.weighted"}}},
+            doc! {"$match": {"popularity": {"$gt": 0}}},
+            doc! {"$sort": {"popularity": -1, "publishedAt": -1}},
+            doc! {"$skip": q.offset as i64},
+            doc! {"$limit": q.limit as i64},
+        ];
+        pipeline.append(&mut lookup_stages());
+
+        let mut cursor = self.collection.aggregate(pipeline, None).await?;
+        let mut result: Vec<Post> = vec![];
+
+        while let Some(document) = cursor.try_next().await? {
+            if let Some(post) = self.listing_mode.unmarshal(&document)? {
+                result.push(post);
+            }
+        }
+
+        Ok(result)
+    }
+
+    async fn find_published_ids(&self) -> Result<HashSet<String>, Box<dyn std::error::Error>> {
+        let ids = self.collection
+            .distinct("_id", doc! {"status": PostStatus::Published as i32}, None)
+            .await?;
+
+        Ok(ids.iter().filter_map(|id| id.as_object_id()).map(|id| id.to_hex()).collect())
+    }
+
+    async fn find_by_slug(&self, slug: &str) -> Result<SlugLookup<Post>, Box<dyn std::error::Error>> {
+        let mut pipeline = vec![doc! {"$match": slug::slug_filter(Bson::Null, slug)}, doc! {"$limit": 1}];
+        pipeline.append(&mut lookup_stages());
+
+        let mut cursor = self.collection.aggregate(pipeline, None).await?;
+
+        if let Some(document) = cursor.try_next().await? {
+            let post = Post::unmarshal_bson(&document)?;
+
+            return if post.slug == slug {
+                Ok(SlugLookup::Found(post))
+            } else {
+                Ok(SlugLookup::Moved(post))
+            };
+        }
+
+        Ok(SlugLookup::NotFound)
+    }
+
+    async fn update_slug(&self, id: &str, slug: &str) -> Result<bool, Box<dyn std::error::Error>> {
+        slug::update_slug(&self.collection, id, slug).await
+    }
+
+    async fn update_content(
+        &self,
+        id: &str,
+        markdown: &str,
+        html: &str,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let summary = content::analyze(markdown);
+
+        self.collection
+            .update_one(
+                doc! {"_id": ObjectId::from_str(id)?},
+                doc! {"$set": {
+                    "markdown": markdown,
+                    "html": html,
+                    "excerpt": summary.excerpt,
+                    "wordCount": summary.word_count,
+                    "readingTime": summary.reading_time,
+                    "updatedAt": DateTime::now(),
+                }},
+                None,
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    async fn backfill_content_summaries(&self) -> Result<u64, Box<dyn std::error::Error>> {
+        let find_options = FindOptions::builder().projection(doc! {"markdown": 1}).build();
+        let mut cursor = self.collection.find(doc! {"markdown": {"$type": "string"}}, find_options).await?;
+        let mut result = 0;
+
+        while let Some(document) = cursor.try_next().await? {
+            let summary = content::analyze(document.get_str("markdown")?);
+
+            // The `updatedAt` is left as is, the content itself has not been changed
+            result += self.collection
+                .update_one(
+                    doc! {"_id": document.get_object_id("_id")?},
+                    doc! {"$set": {
+                        "excerpt": summary.excerpt,
+                        "wordCount": summary.word_count,
+                        "readingTime": summary.reading_time,
+                    }},
+                    None,
+                )
+                .await?
+                .modified_count;
         }
 
         Ok(result)
     }
 
+    async fn update_status(
+        &self,
+        id: &str,
+        from: PostStatus,
+        status: PostStatus,
+        published_at: Option<Timestamp>,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let mut update = doc! {"status": status as i32, "updatedAt": DateTime::now()};
+
+        if let Some(published_at) = published_at {
+            update.insert("publishedAt", to_datetime(&published_at));
+        }
+
+        // The current status is part of the filter, so a concurrent transition cannot be overwritten
+        let result = self.collection
+            .update_one(
+                doc! {"_id": ObjectId::from_str(id)?, "status": from as i32},
+                doc! {"$set": update},
+                None,
+            )
+            .await?;
+        if result.matched_count == 0 {
+            return Err(Box::new(StatusMismatchError { id: id.to_owned(), from }));
+        }
+
+        Ok(())
+    }
+
+    async fn update_reviewers(
+        &self,
+        id: &str,
+        reviewers: &[String],
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        self.collection
+            .update_one(
+                doc! {"_id": ObjectId::from_str(id)?},
+                doc! {"$set": {"reviewers": reviewers, "updatedAt": DateTime::now()}},
+                None,
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    async fn update_co_authors(
+        &self,
+        id: &str,
+        co_authors: &[String],
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        self.collection
+            .update_one(
+                doc! {"_id": ObjectId::from_str(id)?},
+                doc! {"$set": {"coAuthors": co_authors, "updatedAt": DateTime::now()}},
+                None,
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    async fn publish_scheduled(&self) -> Result<u64, Box<dyn std::error::Error>> {
+        let result = self.collection
+            .update_many(
+                doc! {"status": PostStatus::Scheduled as i32, "publishedAt": {"$lte": DateTime::now()}},
+                doc! {"$set": {"status": PostStatus::Published as i32, "updatedAt": DateTime::now()}},
+                None,
+            )
+            .await?;
+
+        Ok(result.modified_count)
+    }
+
+    async fn reassign_author(&self, from: &str, to: &str) -> Result<(), Box<dyn std::error::Error>> {
+        self.collection
+            .update_many(doc! {"author": from}, doc! {"$set": {"author": to}}, None)
+            .await?;
+        self.collection
+            .update_many(doc! {"coAuthors": from}, doc! {"$pull": {"coAuthors": from}}, None)
+            .await?;
+        self.collection
+            .update_many(doc! {"reviewers": from}, doc! {"$pull": {"reviewers": from}}, None)
+            .await?;
+
+        Ok(())
+    }
+
+    async fn find_review_comments(&self, id: &str) -> Result<Vec<Comment>, Box<dyn std::error::Error>> {
+        let pipeline = vec![
+            doc! {"$match": {"_id": ObjectId::from_str(id)?}},
+            doc! {"$lookup": {
+                "from": "reviewComments",
+                "let": {"reviewComments": {"$ifNull": ["$reviewComments", []]}},
+                "pipeline": [
+                    {"$match": {"$expr": {"$in": ["$_id", "$$reviewComments"]}, "hidden": {"$ne": true}}},
+                    {"$lookup": {"from": "users", "localField": "author", "foreignField": "_id", "as": "author"}},
+                    {"$unwind": {"path": "$author"}},
+                    {"$sort": {"createdAt": 1}},
+                ],
+                "as": "reviewComments",
+            }},
+            doc! {"$project": {"reviewComments": 1}},
+        ];
+
+        let mut cursor = self.collection.aggregate(pipeline, None).await?;
+        let mut result: Vec<Comment> = vec![];
+
+        while let Some(document) = cursor.try_next().await? {
+            result = document.get_array("reviewComments")?
+                .iter()
+                .filter_map(|comment| comment.as_document())
+                .map(|comment| Comment::unmarshal_bson(comment))
+                .collect::<Result<Vec<Comment>, _>>()?;
+        }
+
+        Ok(result)
+    }
+
+    async fn push_review_comment(
+        &self,
+        id: &str,
+        comment_id: &str,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        self.collection
+            .update_one(
+                doc! {"_id": ObjectId::from_str(id)?},
+                doc! {"$push": {"reviewComments": ObjectId::from_str(comment_id)?}},
+                None,
+            )
+            .await?;
+
+        Ok(())
+    }
+
     // async fn find_post_comments(&self, id: &str, q: &PostQuery) -> Result<Vec<Comment>, Box<dyn std::error::Error>> {
     //     let pipeline = vec![
     //         doc! {"$match": {"_id": ObjectId::from_str(id)?}},
-    //         doc! {"$lookup": {"from": "comments", "localField": "comments", "foreignField": "_id", "as": "comments"}},
+    //         doc! {"$lookup": {
+    //             "from": "comments",
+    //             "let": {"comments": {"$ifNull": ["$comments", []]}},
+    //             "pipeline": [{"$match": {"$expr": {"$in": ["$_id", "$$comments"]}, "hidden": {"$ne": true}}}],
+    //             "as": "comments",
+    //         }},
     //         doc! {"$project": {"comments": 1}},
     //         doc! {"$skip": q.offset as i64},
     //         doc! {"$limit": q.limit as i64},
@@ -165,92 +656,183 @@ impl PostRepository for MongoPostRepository {
     //     Ok(result)
     // }
     //
-    // async fn find_post_attachments(&self, id: &str) -> Result<Vec<File>, Box<dyn std::error::Error>> {
-    //     let pipeline = vec![
-    //         doc! {"$match": {"_id": ObjectId::from_str(id)?}},
-    //         doc! {"$lookup": {"from": "files", "localField": "attachments", "foreignField": "_id", "as": "attachments"}},
-    //         doc! {"$project": {"attachments": 1}},
-    //     ];
-    //
-    //     let mut cursor = self.collection.aggregate(pipeline, None).await?;
-    //     let mut result: Vec<File> = vec![];
-    //
-    //     while let Some(document) = cursor.try_next().await? {
-    //         result = document.get_array("attachments")
-    //             .and_then(|files| {
-    //                 files
-    //                     .into_iter()
-    //                     .map(|file| file.as_document())
-    //                     .filter_map(|file| file)
-    //                     .map(|file| File::unmarshal_bson(file))
-    //                     .collect::<Result<Vec<File>, _>>()
-    //             })?;
-    //     }
-    //
-    //     Ok(result)
-    // }
-}
+    async fn find_post_attachments(&self, id: &str) -> Result<Vec<File>, Box<dyn std::error::Error>> {
+        let pipeline = vec![
+            doc! {"$match": {"_id": ObjectId::from_str(id)?}},
+            doc! {"$lookup": {
+                "from": "files",
+                "let": {"attachments": {"$ifNull": ["$attachments", []]}},
+                "pipeline": [
+                    {"$match": {"$expr": {"$in": ["$_id", "$$attachments"]}}},
+                    // The lookup does not keep the order of the attachments array, so it is restored here
+                    {"$addFields": {"position": {"$indexOfArray": ["$$attachments", "$_id"]}}},
+                    {"$sort": {"position": 1}},
+                ],
+                "as": "attachments",
+            }},
+            doc! {"$project": {"attachments": 1}},
+        ];
+
+        let mut cursor = self.collection.aggregate(pipeline, None).await?;
+        let mut result: Vec<File> = vec![];
+
+        while let Some(document) = cursor.try_next().await? {
+            result = document.get_array("attachments")?
+                .iter()
+                .filter_map(|file| file.as_document())
+                .map(File::unmarshal_bson)
+                .collect::<Result<Vec<File>, _>>()?;
+        }
 
-impl Unmarshaler for Post {
-    fn unmarshal_bson(
-        document: &Document,
-    ) -> Result<Self, mongodb::bson::document::ValueAccessError>
-        where
-            Self: Sized,
-    {
-        Ok(Post {
-            id: document.get_object_id("_id")?.to_hex(),
-            title: document.get_str("title")?.to_owned(),
-            slug: document.get_str("slug")?.to_owned(),
-            status: document.get_i32("status")?.to_owned(),
-            markdown: document.get_str("markdown")?.to_owned(),
-            html: document.get_str("html")?.to_owned(),
-            published_at: match document.get_datetime("publishedAt") {
-                Ok(published_at) => {
-                    Some(Timestamp::from(SystemTime::from(published_at.to_owned())))
+        Ok(result)
+    }
+
+    async fn push_attachment(&self, id: &str, file_id: &str) -> Result<(), Box<dyn std::error::Error>> {
+        self.collection
+            .update_one(
+                doc! {"_id": ObjectId::from_str(id)?},
+                doc! {
+                    "$addToSet": {"attachments": ObjectId::from_str(file_id)?},
+                    "$set": {"updatedAt": DateTime::now()},
+                },
+                None,
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    async fn pull_attachment(&self, id: &str, file_id: &str) -> Result<(), Box<dyn std::error::Error>> {
+        self.collection
+            .update_one(
+                doc! {"_id": ObjectId::from_str(id)?},
+                doc! {
+                    "$pull": {"attachments": ObjectId::from_str(file_id)?},
+                    "$set": {"updatedAt": DateTime::now()},
+                },
+                None,
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    async fn reorder_attachments(&self, id: &str, file_ids: &[String]) -> Result<(), Box<dyn std::error::Error>> {
+        let attachments = file_ids
+            .iter()
+            .map(|id| ObjectId::from_str(id.as_str()))
+            .collect::<Result<Vec<ObjectId>, _>>()?;
+        if attachments.iter().collect::<HashSet<&ObjectId>>().len() != attachments.len() {
+            return Err(Box::new(AttachmentMismatchError { id: id.to_owned() }));
+        }
+
+        // The attachments are kept unique, so the same size with all of the IDs means the same set,
+        // which is checked by the same update that writes the new order
+        let mut current = doc! {"$size": attachments.len() as i64};
+        if !attachments.is_empty() {
+            current.insert("$all", attachments.clone());
+        }
+
+        let result = self.collection
+            .update_one(
+                doc! {"_id": ObjectId::from_str(id)?, "attachments": current},
+                doc! {"$set": {"attachments": attachments, "updatedAt": DateTime::now()}},
+                None,
+            )
+            .await?;
+        if result.matched_count == 0 {
+            return Err(Box::new(AttachmentMismatchError { id: id.to_owned() }));
+        }
+
+        Ok(())
+    }
+
+    async fn find_referenced_files(&self) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+        let mut result: Vec<String> = vec![];
+
+        for field in ["featuredImage", "attachments"] {
+            for id in self.collection.distinct(field, None, None).await? {
+                if let Some(id) = id.as_object_id() {
+                    result.push(id.to_hex());
                 }
-                _ => None,
-            },
-            author: Some(
-                document
-                    .get_document("author")
-                    .and_then(|author| User::unmarshal_bson(author))?,
-            ),
-            categories: document.get_array("categories").and_then(|categories| {
-                categories
-                    .into_iter()
-                    .map(|category| category.as_document())
-                    .filter_map(|category| category)
-                    .map(|category| Taxonomy::unmarshal_bson(category))
-                    .collect::<Result<Vec<Taxonomy>, _>>()
-            })?,
-            tags: document.get_array("tags").and_then(|tags| {
-                tags.into_iter()
-                    .map(|tag| tag.as_document())
-                    .filter_map(|tag| tag)
-                    .map(|tag| Taxonomy::unmarshal_bson(tag))
-                    .collect::<Result<Vec<Taxonomy>, _>>()
-            })?,
-            featured_image: match document.get_document("featuredImage") {
-                Ok(featured_image) => Some(File::unmarshal_bson(featured_image)?),
-                _ => None,
-            },
-            created_at: Some(document.get_datetime("createdAt").and_then(|created_at| {
-                Ok(Timestamp::from(SystemTime::from(created_at.to_owned())))
-            })?),
-            updated_at: match document.get_datetime("updatedAt") {
-                Ok(updated_at) => Some(Timestamp::from(SystemTime::from(updated_at.to_owned()))),
-                _ => None,
-            },
-        })
+            }
+        }
+        result.sort();
+        result.dedup();
+
+        Ok(result)
     }
 }
 
+/// Return a filter that matches posts of the author, including the co-authored ones.
+fn author_filter(author: &str) -> Document {
+    doc! {"$or": [{"author": author}, {"coAuthors": author}]}
+}
+
+/// Return the aggregation stages which resolve all references of the post into embedded documents.
+fn lookup_stages() -> Vec<Document> {
+    vec![
+        doc! {"$lookup": {"from": "users", "localField": "author", "foreignField": "_id", "as": "author"}},
+        doc! {"$unwind": {"path": "$author"}},
+        doc! {"$lookup": {"from": "users", "localField": "coAuthors", "foreignField": "_id", "as": "coAuthors"}},
+        doc! {"$lookup": {"from": "users", "localField": "reviewers", "foreignField": "_id", "as": "reviewers"}},
+        doc! {"$lookup": {"from": "taxonomies", "localField": "categories", "foreignField": "_id", "as": "categories"}},
+        doc! {"$lookup": {"from": "taxonomies", "localField": "tags", "foreignField": "_id", "as": "tags"}},
+        doc! {"$lookup": {"from": "files", "localField": "featuredImage", "foreignField": "_id", "as": "featuredImage"}},
+        doc! {"$unwind": {"path": "$featuredImage", "preserveNullAndEmptyArrays": true}},
+    ]
+}
+
+/// A schema of the stored post, the references are only stored as their IDs and the derived content,
+/// i.e. the table of contents and series, is never stored.
+#[derive(Marshaler, Unmarshaler)]
+#[bson(remote = "Post")]
+struct PostSchema {
+    #[bson(rename = "_id", object_id)]
+    id: String,
+    title: String,
+    slug: String,
+    status: i32,
+    #[bson(optional)]
+    markdown: String,
+    #[bson(optional)]
+    html: String,
+    #[bson(optional)]
+    excerpt: String,
+    #[bson(optional)]
+    word_count: u32,
+    #[bson(optional)]
+    reading_time: u32,
+    #[bson(timestamp, optional)]
+    published_at: Option<Timestamp>,
+    #[bson(reference)]
+    author: Option<User>,
+    #[bson(reference, optional)]
+    co_authors: Vec<User>,
+    #[bson(reference, optional)]
+    reviewers: Vec<User>,
+    #[bson(reference, object_id, optional)]
+    categories: Vec<Taxonomy>,
+    #[bson(reference, object_id, optional)]
+    tags: Vec<Taxonomy>,
+    #[bson(reference, object_id, optional)]
+    featured_image: Option<File>,
+    #[bson(read_with = "unmarshal_reaction_counts")]
+    reactions: Vec<ReactionCount>,
+    #[bson(timestamp)]
+    created_at: Option<Timestamp>,
+    #[bson(timestamp, optional)]
+    updated_at: Option<Timestamp>,
+}
+
 #[cfg(test)]
 mod tests {
-    use myblog_proto_rust::myblog::proto::blog::{PostStatus, Taxonomy, TaxonomyType};
+    use myblog_proto_rust::myblog::proto::blog::{Post, PostStatus, Taxonomy, TaxonomyType};
+    use proptest::prelude::*;
+    use prost_types::Timestamp;
 
     use crate::blog::post::{MongoPostRepository, PostQuery};
+    use crate::encoding::bson::{arbitrary, Marshaler, Unmarshaler};
 
     #[test]
     fn init_post_query() {
@@ -264,6 +846,17 @@ mod tests {
         assert_eq!(5, q.limit);
     }
 
+    #[test]
+    fn post_query_with_ids() {
+        // Given
+
+        // When
+        let q = PostQuery::builder().with_ids(vec![String::from("1"), String::from("2")]);
+
+        // Then
+        assert_eq!(vec!["1", "2"], q.ids);
+    }
+
     #[test]
     fn post_query_with_status() {
         // Given
@@ -309,6 +902,85 @@ mod tests {
         assert_eq!("2", q.tag.unwrap().id);
     }
 
+    #[test]
+    fn post_query_with_author() {
+        // Given
+
+        // When
+        let q = PostQuery::builder().with_author("github|1");
+
+        // Then
+        assert_eq!("github|1", q.author.unwrap());
+    }
+
+    #[test]
+    fn post_query_with_taxonomies() {
+        // Given
+
+        // When
+        let q = PostQuery::builder().with_taxonomies(vec![String::from("1"), String::from("2")]);
+
+        // Then
+        assert_eq!(vec!["1", "2"], q.taxonomies);
+    }
+
+    #[test]
+    fn post_query_with_exclude() {
+        // Given
+
+        // When
+        let q = PostQuery::builder().with_exclude("1");
+
+        // Then
+        assert_eq!("1", q.exclude.unwrap());
+    }
+
+    #[test]
+    fn post_query_with_reviewer() {
+        // Given
+
+        // When
+        let q = PostQuery::builder().with_reviewer("github|1");
+
+        // Then
+        assert_eq!("github|1", q.reviewer.unwrap());
+    }
+
+    #[test]
+    fn post_query_with_published_after() {
+        // Given
+        let published_after = Timestamp { seconds: 1622480400, nanos: 0 };
+
+        // When
+        let q = PostQuery::builder().with_published_after(Some(published_after));
+
+        // Then
+        assert_eq!(1622480400, q.published_after.unwrap().seconds);
+    }
+
+    #[test]
+    fn post_query_with_published_before() {
+        // Given
+        let published_before = Timestamp { seconds: 1625072400, nanos: 0 };
+
+        // When
+        let q = PostQuery::builder().with_published_before(Some(published_before));
+
+        // Then
+        assert_eq!(1625072400, q.published_before.unwrap().seconds);
+    }
+
+    #[test]
+    fn post_query_with_content() {
+        // Given
+
+        // When
+        let q = PostQuery::builder().with_content(true);
+
+        // Then
+        assert!(q.content);
+    }
+
     #[test]
     fn post_query_with_offset() {
         // Given
@@ -330,4 +1002,17 @@ mod tests {
         // Then
         assert_eq!(6, q.limit);
     }
+
+    proptest! {
+        #[test]
+        fn round_trip_post(post in arbitrary::post()) {
+            // Given
+
+            // When
+            let result = Post::unmarshal_bson(&post.marshal_bson().unwrap()).unwrap();
+
+            // Then
+            prop_assert_eq!(post, result);
+        }
+    }
 }
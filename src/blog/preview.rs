@@ -0,0 +1,245 @@
+use std::error::Error;
+use std::fmt;
+use std::str::FromStr;
+
+use hmac::{Hmac, Mac};
+use mongodb::{bson::DateTime, bson::doc, bson::Document, bson::oid::ObjectId, Collection};
+use sha2::Sha256;
+
+/// A preview token which grants read access to a single post regardless of its status.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PreviewToken {
+    pub id: String,
+    pub post_id: String,
+    pub created_by: String,
+    pub created_at: DateTime,
+    pub expires_at: DateTime,
+    pub revoked_at: Option<DateTime>,
+}
+
+impl PreviewToken {
+    pub fn new(post_id: &str, created_by: &str, ttl_seconds: i64) -> Self {
+        let created_at = DateTime::now();
+
+        PreviewToken {
+            id: ObjectId::new().to_hex(),
+            post_id: post_id.to_owned(),
+            created_by: created_by.to_owned(),
+            created_at,
+            expires_at: DateTime::from_millis(created_at.timestamp_millis() + ttl_seconds * 1000),
+            revoked_at: None,
+        }
+    }
+
+    pub fn is_expired(&self) -> bool {
+        self.expires_at <= DateTime::now()
+    }
+
+    pub fn is_revoked(&self) -> bool {
+        self.revoked_at.is_some()
+    }
+}
+
+/// An error returned when a preview token cannot be verified.
+#[derive(Debug, PartialEq)]
+pub enum PreviewTokenError {
+    Malformed,
+    InvalidSignature,
+    Expired,
+}
+
+impl fmt::Display for PreviewTokenError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PreviewTokenError::Malformed => write!(f, "Malformed preview token"),
+            PreviewTokenError::InvalidSignature => write!(f, "Invalid preview token signature"),
+            PreviewTokenError::Expired => write!(f, "Preview token has expired"),
+        }
+    }
+}
+
+impl Error for PreviewTokenError {}
+
+/// Sign and verify preview tokens with HMAC-SHA256.
+///
+/// The signed string has a form of `<token id>.<expires at in milliseconds>.<hex signature>`,
+/// everything else is kept on the token document so it can be revoked.
+#[derive(Clone)]
+pub struct PreviewTokenSigner {
+    secret: Vec<u8>,
+}
+
+impl PreviewTokenSigner {
+    pub fn new(secret: &[u8]) -> Self {
+        PreviewTokenSigner { secret: secret.to_vec() }
+    }
+
+    pub fn sign(&self, token: &PreviewToken) -> String {
+        let payload = format!("{}.{}", token.id, token.expires_at.timestamp_millis());
+
+        format!("{}.{}", payload, hex::encode(self.mac(payload.as_bytes()).finalize().into_bytes()))
+    }
+
+    /// Verify the signed string and return the token id it refers to.
+    pub fn verify(&self, signed: &str) -> Result<String, PreviewTokenError> {
+        let parts: Vec<&str> = signed.split('.').collect();
+        if parts.len() != 3 {
+            return Err(PreviewTokenError::Malformed);
+        }
+
+        let expires_at = parts[1].parse::<i64>().map_err(|_| PreviewTokenError::Malformed)?;
+        let signature = hex::decode(parts[2]).map_err(|_| PreviewTokenError::Malformed)?;
+
+        self.mac(format!("{}.{}", parts[0], parts[1]).as_bytes())
+            .verify_slice(&signature)
+            .map_err(|_| PreviewTokenError::InvalidSignature)?;
+
+        if expires_at <= DateTime::now().timestamp_millis() {
+            return Err(PreviewTokenError::Expired);
+        }
+
+        Ok(parts[0].to_owned())
+    }
+
+    fn mac(&self, payload: &[u8]) -> Hmac<Sha256> {
+        let mut mac = Hmac::<Sha256>::new_from_slice(&self.secret)
+            .expect("HMAC can take key of any size");
+        mac.update(payload);
+        mac
+    }
+}
+
+/// A preview token repository definition.
+#[tonic::async_trait]
+pub trait PreviewTokenRepository: Send + Sync + 'static {
+    async fn create(&self, t: &PreviewToken) -> Result<(), Box<dyn std::error::Error>>;
+    async fn find_by_id(&self, id: &str) -> Result<Option<PreviewToken>, Box<dyn std::error::Error>>;
+    async fn revoke(&self, id: &str) -> Result<(), Box<dyn std::error::Error>>;
+    async fn record_access(&self, id: &str, remote_addr: &str) -> Result<(), Box<dyn std::error::Error>>;
+}
+
+/// An implementation of the PreviewTokenRepository specifies with MongoDB.
+pub struct MongoPreviewTokenRepository {
+    collection: Collection<Document>,
+}
+
+impl MongoPreviewTokenRepository {
+    pub fn new(collection: Collection<Document>) -> Self {
+        MongoPreviewTokenRepository { collection }
+    }
+}
+
+#[tonic::async_trait]
+impl PreviewTokenRepository for MongoPreviewTokenRepository {
+    async fn create(&self, t: &PreviewToken) -> Result<(), Box<dyn std::error::Error>> {
+        let document = doc! {
+            "_id": ObjectId::from_str(t.id.as_str())?,
+            "post": ObjectId::from_str(t.post_id.as_str())?,
+            "createdBy": t.created_by.as_str(),
+            "createdAt": t.created_at,
+            "expiresAt": t.expires_at,
+            "accesses": [],
+        };
+
+        self.collection.insert_one(document, None).await?;
+
+        Ok(())
+    }
+
+    async fn find_by_id(&self, id: &str) -> Result<Option<PreviewToken>, Box<dyn std::error::Error>> {
+        let filter = doc! {"_id": ObjectId::from_str(id)?};
+
+        if let Some(document) = self.collection.find_one(filter, None).await? {
+            return Ok(Some(PreviewToken {
+                id: document.get_object_id("_id")?.to_hex(),
+                post_id: document.get_object_id("post")?.to_hex(),
+                created_by: document.get_str("createdBy")?.to_owned(),
+                created_at: document.get_datetime("createdAt")?.to_owned(),
+                expires_at: document.get_datetime("expiresAt")?.to_owned(),
+                revoked_at: document.get_datetime("revokedAt").ok().map(|t| t.to_owned()),
+            }));
+        }
+
+        Ok(None)
+    }
+
+    async fn revoke(&self, id: &str) -> Result<(), Box<dyn std::error::Error>> {
+        self.collection
+            .update_one(
+                doc! {"_id": ObjectId::from_str(id)?},
+                doc! {"$set": {"revokedAt": DateTime::now()}},
+                None,
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    async fn record_access(&self, id: &str, remote_addr: &str) -> Result<(), Box<dyn std::error::Error>> {
+        self.collection
+            .update_one(
+                doc! {"_id": ObjectId::from_str(id)?},
+                doc! {"$push": {"accesses": {"accessedAt": DateTime::now(), "remoteAddr": remote_addr}}},
+                None,
+            )
+            .await?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::blog::preview::{PreviewToken, PreviewTokenError, PreviewTokenSigner};
+
+    #[test]
+    fn sign_and_verify_preview_token() {
+        // Given
+        let signer = PreviewTokenSigner::new(b"secret");
+        let token = PreviewToken::new("5b2863365c31b411b041995e", "github|1", 3600);
+
+        // When
+        let result = signer.verify(signer.sign(&token).as_str());
+
+        // Then
+        assert_eq!(Ok(token.id), result);
+    }
+
+    #[test]
+    fn verify_preview_token_with_different_secret() {
+        // Given
+        let token = PreviewToken::new("5b2863365c31b411b041995e", "github|1", 3600);
+        let signed = PreviewTokenSigner::new(b"secret").sign(&token);
+
+        // When
+        let result = PreviewTokenSigner::new(b"another-secret").verify(signed.as_str());
+
+        // Then
+        assert_eq!(Err(PreviewTokenError::InvalidSignature), result);
+    }
+
+    #[test]
+    fn verify_expired_preview_token() {
+        // Given
+        let signer = PreviewTokenSigner::new(b"secret");
+        let token = PreviewToken::new("5b2863365c31b411b041995e", "github|1", -1);
+
+        // When
+        let result = signer.verify(signer.sign(&token).as_str());
+
+        // Then
+        assert_eq!(Err(PreviewTokenError::Expired), result);
+    }
+
+    #[test]
+    fn verify_malformed_preview_token() {
+        // Given
+        let signer = PreviewTokenSigner::new(b"secret");
+
+        // When
+        let result = signer.verify("not-a-token");
+
+        // Then
+        assert_eq!(Err(PreviewTokenError::Malformed), result);
+    }
+}
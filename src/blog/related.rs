@@ -0,0 +1,170 @@
+use std::collections::{HashMap, HashSet};
+
+use myblog_proto_rust::myblog::proto::blog::Post;
+
+/// Score posts by how much they have in common with the given post.
+///
+/// Every shared category and tag contributes its inverse document frequency,
+/// so sharing a rare tag counts more than sharing a category which almost every post has.
+pub struct RelatedPostScorer {
+    total: u64,
+    frequencies: HashMap<String, u64>,
+    title_weight: f64,
+}
+
+impl RelatedPostScorer {
+    pub fn new(total: u64, frequencies: HashMap<String, u64>) -> Self {
+        RelatedPostScorer {
+            total,
+            frequencies,
+            title_weight: 0.0,
+        }
+    }
+
+    /// Add a similarity of the titles into the score, multiplied by the weight.
+    pub fn with_title_weight(mut self, title_weight: f64) -> Self {
+        self.title_weight = title_weight;
+        self
+    }
+
+    pub fn score(&self, post: &Post, candidate: &Post) -> f64 {
+        let taxonomies: HashSet<&str> = taxonomy_ids(post).collect();
+
+        let shared: f64 = taxonomy_ids(candidate)
+            .filter(|id| taxonomies.contains(id))
+            .map(|id| self.idf(id))
+            .sum();
+
+        if self.title_weight > 0.0 {
+            shared + self.title_weight * title_similarity(post.title.as_str(), candidate.title.as_str())
+        } else {
+            shared
+        }
+    }
+
+    /// Sort candidates by their score descending and return at most `limit` of them.
+    pub fn rank(&self, post: &Post, candidates: Vec<Post>, limit: usize) -> Vec<Post> {
+        let mut scored: Vec<(f64, Post)> = candidates
+            .into_iter()
+            .filter(|candidate| candidate.id != post.id)
+            .map(|candidate| (self.score(post, &candidate), candidate))
+            .collect();
+
+        // The sort is stable, candidates with the same score keep their original (most recent first) order
+        scored.sort_by(|a, b| b.0.partial_cmp(&a.0).unwrap_or(std::cmp::Ordering::Equal));
+
+        scored.into_iter().take(limit).map(|(_, candidate)| candidate).collect()
+    }
+
+    fn idf(&self, id: &str) -> f64 {
+        let frequency = self.frequencies.get(id).cloned().unwrap_or_default();
+
+        ((self.total as f64 + 1.0) / (frequency as f64 + 1.0)).ln() + 1.0
+    }
+}
+
+fn taxonomy_ids(post: &Post) -> impl Iterator<Item = &str> {
+    post.categories.iter().chain(post.tags.iter()).map(|taxonomy| taxonomy.id.as_str())
+}
+
+/// Return the Jaccard similarity of the lowercase words of both titles.
+pub fn title_similarity(a: &str, b: &str) -> f64 {
+    let words = |title: &str| -> HashSet<String> {
+        title
+            .split(|c: char| !c.is_alphanumeric())
+            .filter(|word| !word.is_empty())
+            .map(|word| word.to_lowercase())
+            .collect()
+    };
+    let (a, b) = (words(a), words(b));
+
+    if a.is_empty() || b.is_empty() {
+        return 0.0;
+    }
+
+    a.intersection(&b).count() as f64 / a.union(&b).count() as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use myblog_proto_rust::myblog::proto::blog::{Post, Taxonomy};
+
+    use crate::blog::related::{RelatedPostScorer, title_similarity};
+
+    fn post(id: &str, title: &str, tags: Vec<&str>) -> Post {
+        Post {
+            id: String::from(id),
+            title: String::from(title),
+            tags: tags
+                .into_iter()
+                .map(|tag| Taxonomy { id: String::from(tag), ..Default::default() })
+                .collect(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn rank_prefers_rare_taxonomies() {
+        // Given
+        let frequencies: HashMap<String, u64> = vec![(String::from("rust"), 2), (String::from("news"), 50)]
+            .into_iter()
+            .collect();
+        let scorer = RelatedPostScorer::new(100, frequencies);
+        let p = post("1", "Hello", vec!["rust", "news"]);
+        let candidates = vec![post("2", "A", vec!["news"]), post("3", "B", vec!["rust"])];
+
+        // When
+        let result = scorer.rank(&p, candidates, 5);
+
+        // Then
+        assert_eq!(vec!["3", "2"], result.iter().map(|p| p.id.as_str()).collect::<Vec<&str>>());
+    }
+
+    #[test]
+    fn rank_keeps_recent_order_of_equally_scored_posts() {
+        // Given
+        let frequencies: HashMap<String, u64> = vec![(String::from("rust"), 2), (String::from("news"), 50)]
+            .into_iter()
+            .collect();
+        let scorer = RelatedPostScorer::new(100, frequencies);
+        let p = post("1", "Hello", vec!["rust", "news"]);
+        let candidates = vec![
+            post("2", "A", vec!["news"]),
+            post("3", "B", vec!["rust", "news"]),
+            post("4", "C", vec!["news"]),
+        ];
+
+        // When
+        let result = scorer.rank(&p, candidates, 5);
+
+        // Then
+        assert_eq!(vec!["3", "2", "4"], result.iter().map(|p| p.id.as_str()).collect::<Vec<&str>>());
+    }
+
+    #[test]
+    fn rank_excludes_the_post_itself() {
+        // Given
+        let scorer = RelatedPostScorer::new(1, HashMap::new());
+        let p = post("1", "Hello", vec!["rust"]);
+
+        // When
+        let result = scorer.rank(&p, vec![p.clone()], 5);
+
+        // Then
+        assert!(result.is_empty());
+    }
+
+    #[test]
+    fn title_similarity_of_titles() {
+        // Given
+
+        // When
+
+        // Then
+        assert_eq!(1.0, title_similarity("Hello, World", "hello world"));
+        assert_eq!(0.5, title_similarity("Hello Rust", "Hello"));
+        assert_eq!(0.0, title_similarity("", "Hello"));
+    }
+}
@@ -0,0 +1,209 @@
+use std::collections::HashSet;
+use std::error::Error;
+use std::fmt;
+use std::str::FromStr;
+
+use mongodb::{bson::DateTime, bson::doc, bson::Document, bson::oid::ObjectId, Client, Collection};
+use myblog_proto_rust::myblog::proto::blog::{Post, Series, TaxonomyType};
+
+/// A series with the ordered IDs of its posts.
+pub struct SeriesDocument {
+    pub series: Series,
+    pub post_ids: Vec<String>,
+}
+
+/// An error returned when the new order of the series does not contain exactly its current posts.
+#[derive(Debug)]
+pub struct SeriesMismatchError {
+    pub id: String,
+}
+
+impl fmt::Display for SeriesMismatchError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "New order must contain exactly the current posts of series '{}'", self.id)
+    }
+}
+
+impl Error for SeriesMismatchError {}
+
+/// A series repository definition.
+#[tonic::async_trait]
+pub trait SeriesRepository: Send + Sync + 'static {
+    async fn find_by_id(&self, id: &str) -> Result<Option<SeriesDocument>, Box<dyn std::error::Error>>;
+    async fn find_by_post(&self, post_id: &str) -> Result<Option<SeriesDocument>, Box<dyn std::error::Error>>;
+    async fn reorder(&self, id: &str, post_ids: &[String]) -> Result<(), Box<dyn std::error::Error>>;
+}
+
+/// An implementation of the SeriesRepository specifies with MongoDB.
+///
+/// A series is stored in the taxonomies collection with the `Series` type and the ordered `posts` array.
+pub struct MongoSeriesRepository {
+    client: Client,
+    collection: Collection<Document>,
+}
+
+impl MongoSeriesRepository {
+    pub fn new(client: Client, collection: Collection<Document>) -> Self {
+        MongoSeriesRepository { client, collection }
+    }
+
+    async fn find_one(&self, filter: Document) -> Result<Option<SeriesDocument>, Box<dyn std::error::Error>> {
+        let mut filter = filter;
+        filter.insert("type", TaxonomyType::Series as i32);
+
+        if let Some(document) = self.collection.find_one(filter, None).await? {
+            return Ok(Some(SeriesDocument {
+                series: Series {
+                    id: document.get_object_id("_id")?.to_hex(),
+                    name: document.get_str("name")?.to_owned(),
+                    slug: document.get_str("slug")?.to_owned(),
+                    posts: vec![],
+                },
+                post_ids: document.get_array("posts")?
+                    .iter()
+                    .filter_map(|id| id.as_object_id())
+                    .map(|id| id.to_hex())
+                    .collect(),
+            }));
+        }
+
+        Ok(None)
+    }
+}
+
+#[tonic::async_trait]
+impl SeriesRepository for MongoSeriesRepository {
+    async fn find_by_id(&self, id: &str) -> Result<Option<SeriesDocument>, Box<dyn std::error::Error>> {
+        self.find_one(doc! {"_id": ObjectId::from_str(id)?}).await
+    }
+
+    async fn find_by_post(&self, post_id: &str) -> Result<Option<SeriesDocument>, Box<dyn std::error::Error>> {
+        self.find_one(doc! {"posts": ObjectId::from_str(post_id)?}).await
+    }
+
+    async fn reorder(&self, id: &str, post_ids: &[String]) -> Result<(), Box<dyn std::error::Error>> {
+        let filter = doc! {"_id": ObjectId::from_str(id)?, "type": TaxonomyType::Series as i32};
+        let posts = post_ids
+            .iter()
+            .map(|id| ObjectId::from_str(id.as_str()))
+            .collect::<Result<Vec<ObjectId>, _>>()?;
+        // A repeated post would otherwise pass the comparison below when another one is left out
+        if posts.iter().collect::<HashSet<&ObjectId>>().len() != posts.len() {
+            return Err(Box::new(SeriesMismatchError { id: id.to_owned() }));
+        }
+
+        // The order is validated and written within the same transaction, so concurrent changes
+        // to the series members cannot be overwritten by a stale order
+        let mut session = self.client.start_session(None).await?;
+        session.start_transaction(None).await?;
+
+        let current: HashSet<ObjectId> = match self.collection
+            .find_one_with_session(filter.clone(), None, &mut session)
+            .await?
+        {
+            Some(document) => document.get_array("posts")?.iter().filter_map(|id| id.as_object_id()).collect(),
+            _ => HashSet::new(),
+        };
+        if current.len() != posts.len() || posts.iter().any(|post| !current.contains(post)) {
+            session.abort_transaction().await?;
+            return Err(Box::new(SeriesMismatchError { id: id.to_owned() }));
+        }
+
+        self.collection
+            .update_one_with_session(
+                filter,
+                doc! {"$set": {"posts": posts, "updatedAt": DateTime::now()}},
+                None,
+                &mut session,
+            )
+            .await?;
+        session.commit_transaction().await?;
+
+        Ok(())
+    }
+}
+
+/// Sort the posts in the order of the IDs, the posts which are not listed are left out.
+pub fn order_by_ids(posts: Vec<Post>, ids: &[String]) -> Vec<Post> {
+    let mut posts = posts;
+
+    ids.iter()
+        .filter_map(|id| posts.iter().position(|post| &post.id == id).map(|i| posts.swap_remove(i)))
+        .collect()
+}
+
+/// Return the 1-based position of the post within the series members with its previous and next posts.
+pub fn position_of(members: &[Post], post_id: &str) -> Option<(u32, Option<Post>, Option<Post>)> {
+    let i = members.iter().position(|post| post.id == post_id)?;
+
+    Some((
+        i as u32 + 1,
+        if i > 0 { members.get(i - 1).cloned() } else { None },
+        members.get(i + 1).cloned(),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use myblog_proto_rust::myblog::proto::blog::Post;
+
+    use crate::blog::series::{order_by_ids, position_of};
+
+    fn posts(ids: Vec<&str>) -> Vec<Post> {
+        ids.into_iter()
+            .map(|id| Post { id: String::from(id), ..Default::default() })
+            .collect()
+    }
+
+    #[test]
+    fn order_posts_by_ids() {
+        // Given
+        let ids = vec![String::from("3"), String::from("1"), String::from("4"), String::from("2")];
+
+        // When
+        let result = order_by_ids(posts(vec!["1", "2", "3"]), &ids);
+
+        // Then
+        assert_eq!(vec!["3", "1", "2"], result.iter().map(|p| p.id.as_str()).collect::<Vec<&str>>());
+    }
+
+    #[test]
+    fn position_of_first_post() {
+        // Given
+        let members = posts(vec!["1", "2", "3"]);
+
+        // When
+        let (position, previous, next) = position_of(&members, "1").unwrap();
+
+        // Then
+        assert_eq!(1, position);
+        assert!(previous.is_none());
+        assert_eq!("2", next.unwrap().id);
+    }
+
+    #[test]
+    fn position_of_middle_post() {
+        // Given
+        let members = posts(vec!["1", "2", "3"]);
+
+        // When
+        let (position, previous, next) = position_of(&members, "2").unwrap();
+
+        // Then
+        assert_eq!(2, position);
+        assert_eq!("1", previous.unwrap().id);
+        assert_eq!("3", next.unwrap().id);
+    }
+
+    #[test]
+    fn position_of_non_member_post() {
+        // Given
+        let members = posts(vec!["1", "2", "3"]);
+
+        // When
+        let result = position_of(&members, "4");
+
+        // Then
+        assert!(result.is_none());
+    }
+}
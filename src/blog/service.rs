@@ -1,31 +1,282 @@
+use std::convert::TryFrom;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::SystemTime;
+
+use chrono_tz::Tz;
+use mongodb::bson::oid::ObjectId;
 use myblog_proto_rust::myblog::proto::blog::{
+    AssignPostCoAuthorsRequest,
+    AssignPostReviewersRequest,
+    AttachPostFileRequest,
     blog_service_server::BlogService,
+    CreatePreviewTokenRequest,
+    CreatePreviewTokenResponse,
+    CreateReviewCommentRequest,
+    CreateReviewCommentResponse,
+    DetachPostFileRequest,
+    GetAuthorRequest,
+    GetAuthorResponse,
+    GetPostBySlugRequest,
+    GetPostBySlugResponse,
+    GetPreviewPostRequest,
+    GetPreviewPostResponse,
+    GetTaxonomyBySlugRequest,
+    GetTaxonomyBySlugResponse,
+    ListArchivePublishedPostsRequest,
+    ListArchivePublishedPostsResponse,
+    ListArchivesResponse,
+    ListAuthorPublishedPostsRequest,
+    ListAuthorPublishedPostsResponse,
     ListCategoriesResponse,
     ListCategoryPublishedPostsRequest,
     ListCategoryPublishedPostsResponse,
+    ListPopularPostsRequest,
+    ListPopularPostsResponse,
+    ListPostAttachmentsRequest,
+    ListPostAttachmentsResponse,
+    ListPostsAwaitingReviewRequest,
+    ListPostsAwaitingReviewResponse,
     ListPublishedPostsRequest,
     ListPublishedPostsResponse,
+    ListRelatedPostsRequest,
+    ListRelatedPostsResponse,
+    ListReviewCommentsRequest,
+    ListReviewCommentsResponse,
+    ListSeriesPostsRequest,
+    ListSeriesPostsResponse,
     ListTagPublishedPostsRequest,
     ListTagPublishedPostsResponse,
+    Post,
     PostStatus,
+    RecordViewRequest,
+    ReorderPostAttachmentsRequest,
+    ReorderSeriesPostsRequest,
+    RevokePreviewTokenRequest,
+    SeriesPosition,
     TaxonomyType,
+    UpdatePostContentRequest,
+    UpdatePostContentResponse,
+    UpdatePostSlugRequest,
+    UpdatePostSlugResponse,
+    UpdatePostStatusRequest,
+    UpdatePostStatusResponse,
+    UpdateTaxonomySlugRequest,
+    UpdateTaxonomySlugResponse,
 };
+use myblog_proto_rust::myblog::proto::auth::User;
+use myblog_proto_rust::myblog::proto::discussion::Comment;
+use prost_types::Timestamp;
 use tonic::{Request, Response, Status};
 
+use crate::auth::{Claims, user::UserRepository};
 use crate::blog::{
-    post::{PostQuery, PostRepository},
+    archive,
+    post::{AttachmentMismatchError, PostQuery, PostRepository, StatusMismatchError},
+    preview::{PreviewToken, PreviewTokenRepository, PreviewTokenSigner},
+    related::RelatedPostScorer,
+    series::{self, SeriesMismatchError, SeriesRepository},
+    slug::{SlugConflictError, SlugLookup},
     taxonomy::{TaxonomyQuery, TaxonomyRepository},
+    toc,
+    view::{self, ViewCounter},
+    workflow,
+};
+use crate::discussion::{
+    comment::CommentRepository,
+    reaction::{self, ReactionRepository, ReactionTarget},
 };
+use crate::storage::file::FileRepository;
+
+/// A maximum number of candidates which are scored for the related posts.
+const RELATED_POST_CANDIDATES: u32 = 100;
+
+/// A weight of the title similarity when it is included in the related posts score.
+const RELATED_POST_TITLE_WEIGHT: f64 = 2.0;
+
+/// A default lifetime of the preview token, in seconds.
+const DEFAULT_PREVIEW_TOKEN_TTL: i64 = 7 * 24 * 60 * 60;
+
+/// A maximum lifetime of the preview token, in seconds.
+const MAX_PREVIEW_TOKEN_TTL: i64 = 30 * 24 * 60 * 60;
 
 pub struct MyBlogService {
+    file_repository: Box<dyn FileRepository>,
     post_repository: Box<dyn PostRepository>,
+    preview_token_repository: Box<dyn PreviewTokenRepository>,
+    reaction_repository: Box<dyn ReactionRepository>,
+    review_comment_repository: Box<dyn CommentRepository>,
+    series_repository: Box<dyn SeriesRepository>,
     taxonomy_repository: Box<dyn TaxonomyRepository>,
+    user_repository: Box<dyn UserRepository>,
+
+    preview_token_signer: PreviewTokenSigner,
+    view_counter: Arc<ViewCounter>,
+
+    time_zone: Tz,
 }
 
 impl MyBlogService {
     pub fn builder() -> MyBlogServiceBuilder {
         MyBlogServiceBuilder::default()
     }
+
+    /// Mark the reactions which the caller has made on each of the posts, if authenticated.
+    async fn mark_my_reactions(&self, claims: Option<Claims>, mut posts: Vec<Post>) -> Result<Vec<Post>, Status> {
+        if let Some(claims) = claims {
+            let ids: Vec<&str> = posts.iter().map(|post| post.id.as_str()).collect();
+            let my_reactions = self.reaction_repository
+                .find_user_reactions(ReactionTarget::Post, &ids, claims.sub.as_str()).await
+                .or_else(|err| Err(Status::internal(err.to_string())))?;
+
+            for post in posts.iter_mut() {
+                if let Some(reactions) = my_reactions.get(&post.id) {
+                    reaction::mark_reacted(&mut post.reactions, reactions);
+                }
+            }
+        }
+
+        Ok(posts)
+    }
+
+    /// Mark the reactions which the caller has made on each of the comments, if authenticated.
+    async fn mark_my_comment_reactions(&self, claims: Option<Claims>, mut comments: Vec<Comment>) -> Result<Vec<Comment>, Status> {
+        if let Some(claims) = claims {
+            let ids: Vec<&str> = comments.iter().map(|comment| comment.id.as_str()).collect();
+            let my_reactions = self.reaction_repository
+                .find_user_reactions(ReactionTarget::Comment, &ids, claims.sub.as_str()).await
+                .or_else(|err| Err(Status::internal(err.to_string())))?;
+
+            for comment in comments.iter_mut() {
+                if let Some(reactions) = my_reactions.get(&comment.id) {
+                    reaction::mark_reacted(&mut comment.reactions, reactions);
+                }
+            }
+        }
+
+        Ok(comments)
+    }
+
+    /// Return the posts of the series in order, only the published ones are visible to the readers.
+    async fn find_series_posts(&self, claims: &Option<Claims>, post_ids: &[String]) -> Result<Vec<Post>, Status> {
+        if post_ids.is_empty() {
+            return Ok(vec![]);
+        }
+
+        let mut q = PostQuery::builder()
+            .with_ids(post_ids.to_vec())
+            .with_limit(post_ids.len() as u32);
+        if !claims.as_ref().map_or(false, |claims| claims.has_permission("write:post")) {
+            q = q.with_status(PostStatus::Published);
+        }
+
+        match self.post_repository.find_all(&q).await {
+            Ok(posts) => Ok(series::order_by_ids(posts, post_ids)),
+            Err(e) => Err(Status::internal(e.to_string())),
+        }
+    }
+
+    /// Return the position of the post within its series with the previous and next posts, if any.
+    async fn find_series_position(&self, claims: &Option<Claims>, post: &Post) -> Result<Option<SeriesPosition>, Status> {
+        let document = match self.series_repository.find_by_post(post.id.as_str()).await {
+            Ok(Some(document)) => document,
+            Ok(None) => return Ok(None),
+            Err(e) => return Err(Status::internal(e.to_string())),
+        };
+        let members = self.find_series_posts(claims, &document.post_ids).await?;
+
+        Ok(series::position_of(&members, post.id.as_str()).map(|(position, previous, next)| SeriesPosition {
+            series: Some(document.series),
+            position,
+            total: members.len() as u32,
+            previous,
+            next,
+        }))
+    }
+
+    /// Return the post by its ID regardless of its status.
+    async fn find_post(&self, id: &str) -> Result<Post, Status> {
+        match self.post_repository.find_by_id(id).await {
+            Ok(Some(post)) => Ok(post),
+            Ok(None) => Err(Status::not_found("Post not found")),
+            Err(e) => Err(Status::internal(e.to_string())),
+        }
+    }
+
+    /// Return the post if the token subject is one of its authors with the write permission.
+    async fn find_writable_post(&self, claims: &Claims, id: &str) -> Result<Post, Status> {
+        if !claims.has_permission("write:post") {
+            return Err(Status::permission_denied("Forbidden"));
+        }
+        let post = self.find_post(id).await?;
+
+        let is_author = post.author.as_ref().map_or(false, |author| author.id == claims.sub)
+            || post.co_authors.iter().any(|co_author| co_author.id == claims.sub);
+        if !is_author {
+            return Err(Status::permission_denied("Not an author of the post"));
+        }
+
+        Ok(post)
+    }
+
+    /// Return the post if the token subject is one of its authors or assigned reviewers.
+    async fn find_reviewable_post(&self, claims: &Claims, id: &str) -> Result<Post, Status> {
+        if !claims.has_permission("write:post") && !claims.has_permission("review:post") {
+            return Err(Status::permission_denied("Forbidden"));
+        }
+        let post = self.find_post(id).await?;
+
+        let is_author = post.author.as_ref().map_or(false, |author| author.id == claims.sub)
+            || post.co_authors.iter().any(|co_author| co_author.id == claims.sub);
+        let is_reviewer = post.reviewers.iter().any(|reviewer| reviewer.id == claims.sub);
+        if !is_author && !is_reviewer {
+            return Err(Status::permission_denied("Not an author or a reviewer of the post"));
+        }
+
+        Ok(post)
+    }
+
+    /// Return the unique IDs of the users, or reject the request if any of them does not exist.
+    async fn find_user_ids(&self, field: &str, ids: &[String]) -> Result<Vec<String>, Status> {
+        let mut ids: Vec<&str> = ids.iter().map(String::as_str).collect();
+        ids.sort_unstable();
+        ids.dedup();
+
+        let users = self.user_repository.find_all_by_ids(&ids).await
+            .or_else(|err| Err(Status::internal(err.to_string())))?;
+        if let Some(missing) = ids.iter().find(|&&id| !users.iter().any(|user| user.id == id)) {
+            return Err(Status::invalid_argument(format!("Invalid '{}' field, user '{}' not found", field, missing)));
+        }
+
+        Ok(ids.into_iter().map(String::from).collect())
+    }
+
+    async fn list_attachments(&self, post_id: &str) -> Result<Response<ListPostAttachmentsResponse>, Status> {
+        match self.post_repository.find_post_attachments(post_id).await {
+            Ok(attachments) => Ok(Response::new(ListPostAttachmentsResponse { attachments })),
+            Err(e) => Err(Status::internal(e.to_string())),
+        }
+    }
+
+    /// Verify the signed preview token and return its stored counterpart.
+    async fn find_preview_token(&self, signed: &str) -> Result<PreviewToken, Status> {
+        let id = self.preview_token_signer.verify(signed)
+            .or_else(|err| Err(Status::unauthenticated(err.to_string())))?;
+
+        match self.preview_token_repository.find_by_id(id.as_str()).await {
+            Ok(Some(token)) => Ok(token),
+            Ok(None) => Err(Status::unauthenticated("Preview token not found")),
+            Err(e) => Err(Status::internal(e.to_string())),
+        }
+    }
+}
+
+/// Return the claims of the token subject or reject the unauthenticated request.
+fn authenticated<T>(request: &Request<T>) -> Result<Claims, Status> {
+    match request.extensions().get::<Claims>() {
+        Some(claims) => Ok(claims.clone()),
+        _ => Err(Status::unauthenticated("Forbidden")),
+    }
 }
 
 #[tonic::async_trait]
@@ -48,6 +299,7 @@ impl BlogService for MyBlogService {
         &self,
         request: Request<ListPublishedPostsRequest>,
     ) -> Result<Response<ListPublishedPostsResponse>, Status> {
+        let claims = request.extensions().get::<Claims>().cloned();
         let r = request.into_inner();
         let q = PostQuery::builder()
             .with_status(PostStatus::Published)
@@ -55,7 +307,9 @@ impl BlogService for MyBlogService {
             .with_limit(r.limit);
 
         match self.post_repository.find_all(&q).await {
-            Ok(posts) => Ok(Response::new(ListPublishedPostsResponse { posts })),
+            Ok(posts) => Ok(Response::new(ListPublishedPostsResponse {
+                posts: self.mark_my_reactions(claims, posts).await?,
+            })),
             Err(e) => Err(Status::internal(e.to_string())),
         }
     }
@@ -64,6 +318,7 @@ impl BlogService for MyBlogService {
         &self,
         request: Request<ListCategoryPublishedPostsRequest>,
     ) -> Result<Response<ListCategoryPublishedPostsResponse>, Status> {
+        let claims = request.extensions().get::<Claims>().cloned();
         let r = request.into_inner();
         let q = PostQuery::builder()
             .with_status(PostStatus::Published)
@@ -72,7 +327,9 @@ impl BlogService for MyBlogService {
             .with_limit(r.limit);
 
         match self.post_repository.find_all(&q).await {
-            Ok(posts) => Ok(Response::new(ListCategoryPublishedPostsResponse { posts })),
+            Ok(posts) => Ok(Response::new(ListCategoryPublishedPostsResponse {
+                posts: self.mark_my_reactions(claims, posts).await?,
+            })),
             Err(e) => Err(Status::internal(e.to_string())),
         }
     }
@@ -80,6 +337,7 @@ impl BlogService for MyBlogService {
     async fn list_tag_published_posts(
         &self, request: Request<ListTagPublishedPostsRequest>,
     ) -> Result<Response<ListTagPublishedPostsResponse>, Status> {
+        let claims = request.extensions().get::<Claims>().cloned();
         let r = request.into_inner();
         let q = PostQuery::builder()
             .with_status(PostStatus::Published)
@@ -88,7 +346,538 @@ impl BlogService for MyBlogService {
             .with_limit(r.limit);
 
         match self.post_repository.find_all(&q).await {
-            Ok(posts) => Ok(Response::new(ListTagPublishedPostsResponse { posts })),
+            Ok(posts) => Ok(Response::new(ListTagPublishedPostsResponse {
+                posts: self.mark_my_reactions(claims, posts).await?,
+            })),
+            Err(e) => Err(Status::internal(e.to_string())),
+        }
+    }
+
+    async fn list_author_published_posts(
+        &self,
+        request: Request<ListAuthorPublishedPostsRequest>,
+    ) -> Result<Response<ListAuthorPublishedPostsResponse>, Status> {
+        let claims = request.extensions().get::<Claims>().cloned();
+        let r = request.into_inner();
+        let q = PostQuery::builder()
+            .with_status(PostStatus::Published)
+            .with_author(r.author_id.as_str())
+            .with_offset(r.offset)
+            .with_limit(r.limit);
+
+        match self.post_repository.find_all(&q).await {
+            Ok(posts) => Ok(Response::new(ListAuthorPublishedPostsResponse {
+                posts: self.mark_my_reactions(claims, posts).await?,
+            })),
+            Err(e) => Err(Status::internal(e.to_string())),
+        }
+    }
+
+    async fn get_author(
+        &self,
+        request: Request<GetAuthorRequest>,
+    ) -> Result<Response<GetAuthorResponse>, Status> {
+        let r = request.into_inner();
+
+        let author = match self.user_repository.find_by_id(r.author_id.as_str()).await {
+            Ok(Some(author)) => Ok(author),
+            Ok(None) => Err(Status::not_found("Author not found")),
+            Err(e) => Err(Status::internal(e.to_string())),
+        }?;
+
+        // Only the users who have written a published post are authors, the others are just readers
+        match self.post_repository.count_published_by_author(author.id.as_str()).await {
+            Ok(0) => Err(Status::not_found("Author not found")),
+            Ok(published_post_count) => Ok(Response::new(GetAuthorResponse {
+                author: Some(author),
+                published_post_count: published_post_count as u32,
+            })),
+            Err(e) => Err(Status::internal(e.to_string())),
+        }
+    }
+
+    async fn list_related_posts(
+        &self,
+        request: Request<ListRelatedPostsRequest>,
+    ) -> Result<Response<ListRelatedPostsResponse>, Status> {
+        let r = request.into_inner();
+        let post = self.find_post(r.post_id.as_str()).await?;
+        if post.status != PostStatus::Published as i32 {
+            return Err(Status::not_found("Post not found"));
+        }
+        let limit = if r.limit == 0 { 5 } else { r.limit };
+
+        // A post without any category or tag has nothing in common with the others
+        if post.categories.is_empty() && post.tags.is_empty() {
+            return Ok(Response::new(ListRelatedPostsResponse { posts: vec![] }));
+        }
+
+        let q = PostQuery::builder()
+            .with_status(PostStatus::Published)
+            .with_taxonomies(post.categories.iter().chain(post.tags.iter()).map(|t| t.id.clone()).collect())
+            .with_exclude(post.id.as_str())
+            .with_limit(RELATED_POST_CANDIDATES);
+        let candidates = self.post_repository.find_all(&q).await
+            .or_else(|err| Err(Status::internal(err.to_string())))?;
+
+        let (total, frequencies) = self.post_repository.count_taxonomies().await
+            .or_else(|err| Err(Status::internal(err.to_string())))?;
+        let mut scorer = RelatedPostScorer::new(total, frequencies);
+        if r.include_title_similarity {
+            scorer = scorer.with_title_weight(RELATED_POST_TITLE_WEIGHT);
+        }
+        // The posts in the same category are candidates too, so they fill the list in their most recent order
+        // when only a few posts share any tag
+        Ok(Response::new(ListRelatedPostsResponse {
+            posts: scorer.rank(&post, candidates, limit as usize),
+        }))
+    }
+
+    async fn record_view(
+        &self,
+        request: Request<RecordViewRequest>,
+    ) -> Result<Response<()>, Status> {
+        let remote_addr = request.remote_addr().map(|addr| addr.ip().to_string()).unwrap_or_default();
+        let user_agent = request.metadata().get("user-agent")
+            .and_then(|user_agent| user_agent.to_str().ok())
+            .unwrap_or_default()
+            .to_owned();
+        let r = request.into_inner();
+
+        if ObjectId::from_str(r.post_id.as_str()).is_err() {
+            return Err(Status::invalid_argument("Invalid 'post_id' field"));
+        }
+
+        // The fingerprint is always derived here, a client could otherwise count as many visitors as it likes
+        let fingerprint = view::fingerprint(remote_addr.as_str(), user_agent.as_str());
+        self.view_counter.record(r.post_id.as_str(), fingerprint.as_str());
+
+        Ok(Response::new(()))
+    }
+
+    async fn list_popular_posts(
+        &self,
+        request: Request<ListPopularPostsRequest>,
+    ) -> Result<Response<ListPopularPostsResponse>, Status> {
+        let claims = request.extensions().get::<Claims>().cloned();
+        let r = request.into_inner();
+        let days = match r.days {
+            0 => Ok(7),
+            7 | 30 => Ok(r.days),
+            _ => Err(Status::invalid_argument("Field 'days' must be either 7 or 30")),
+        }?;
+        let q = PostQuery::builder()
+            .with_offset(r.offset)
+            .with_limit(r.limit);
+
+        match self.post_repository.find_popular(days, &q).await {
+            Ok(posts) => Ok(Response::new(ListPopularPostsResponse {
+                posts: self.mark_my_reactions(claims, posts).await?,
+            })),
+            Err(e) => Err(Status::internal(e.to_string())),
+        }
+    }
+
+    async fn list_archives(
+        &self,
+        _: Request<()>,
+    ) -> Result<Response<ListArchivesResponse>, Status> {
+        match self.post_repository.find_archives(self.time_zone.name()).await {
+            Ok(archives) => Ok(Response::new(ListArchivesResponse { archives })),
+            Err(e) => Err(Status::internal(e.to_string())),
+        }
+    }
+
+    async fn list_archive_published_posts(
+        &self,
+        request: Request<ListArchivePublishedPostsRequest>,
+    ) -> Result<Response<ListArchivePublishedPostsResponse>, Status> {
+        let claims = request.extensions().get::<Claims>().cloned();
+        let r = request.into_inner();
+        let month = u32::try_from(r.month).unwrap_or_default();
+        let (published_after, published_before) = match archive::month_range(&self.time_zone, r.year, month) {
+            Some(range) => Ok(range),
+            _ => Err(Status::invalid_argument("Invalid 'year' or 'month' field")),
+        }?;
+        let q = PostQuery::builder()
+            .with_status(PostStatus::Published)
+            .with_published_after(Some(published_after))
+            .with_published_before(Some(published_before))
+            .with_offset(r.offset)
+            .with_limit(r.limit);
+
+        match self.post_repository.find_all(&q).await {
+            Ok(posts) => Ok(Response::new(ListArchivePublishedPostsResponse {
+                posts: self.mark_my_reactions(claims, posts).await?,
+            })),
+            Err(e) => Err(Status::internal(e.to_string())),
+        }
+    }
+
+    async fn get_post_by_slug(
+        &self,
+        request: Request<GetPostBySlugRequest>,
+    ) -> Result<Response<GetPostBySlugResponse>, Status> {
+        let claims = request.extensions().get::<Claims>().cloned();
+        let r = request.into_inner();
+
+        let (post, moved) = match self.post_repository.find_by_slug(r.slug.as_str()).await {
+            Ok(SlugLookup::Found(post)) => Ok((post, false)),
+            Ok(SlugLookup::Moved(post)) => Ok((post, true)),
+            Ok(SlugLookup::NotFound) => Err(Status::not_found("Post not found")),
+            Err(e) => Err(Status::internal(e.to_string())),
+        }?;
+
+        // Drafts are not reachable by slug, even the retired ones
+        if post.status != PostStatus::Published as i32 {
+            return Err(Status::not_found("Post not found"));
+        }
+
+        let mut post = self.mark_my_reactions(claims.clone(), vec![post]).await?.remove(0);
+        post.table_of_contents = toc::table_of_contents(post.markdown.as_str());
+        post.series = self.find_series_position(&claims, &post).await?;
+
+        Ok(Response::new(GetPostBySlugResponse {
+            canonical_slug: post.slug.clone(),
+            moved,
+            post: Some(post),
+        }))
+    }
+
+    async fn list_series_posts(
+        &self,
+        request: Request<ListSeriesPostsRequest>,
+    ) -> Result<Response<ListSeriesPostsResponse>, Status> {
+        let claims = request.extensions().get::<Claims>().cloned();
+        let r = request.into_inner();
+
+        let document = match self.series_repository.find_by_id(r.series_id.as_str()).await {
+            Ok(Some(document)) => Ok(document),
+            Ok(None) => Err(Status::not_found("Series not found")),
+            Err(e) => Err(Status::internal(e.to_string())),
+        }?;
+
+        let mut series = document.series;
+        series.posts = self.find_series_posts(&claims, &document.post_ids).await?;
+
+        Ok(Response::new(ListSeriesPostsResponse { series: Some(series) }))
+    }
+
+    async fn reorder_series_posts(
+        &self,
+        request: Request<ReorderSeriesPostsRequest>,
+    ) -> Result<Response<()>, Status> {
+        let claims = authenticated(&request)?;
+        if !claims.has_permission("write:post") {
+            return Err(Status::permission_denied("Forbidden"));
+        }
+        let r = request.into_inner();
+
+        match self.series_repository.reorder(r.series_id.as_str(), &r.post_ids).await {
+            Ok(_) => Ok(Response::new(())),
+            Err(e) if e.is::<SeriesMismatchError>() => Err(Status::failed_precondition(e.to_string())),
+            Err(e) => Err(Status::internal(e.to_string())),
+        }
+    }
+
+    async fn get_taxonomy_by_slug(
+        &self,
+        request: Request<GetTaxonomyBySlugRequest>,
+    ) -> Result<Response<GetTaxonomyBySlugResponse>, Status> {
+        let r = request.into_inner();
+        let taxonomy_type = match TaxonomyType::from_i32(r.r#type) {
+            Some(taxonomy_type) => Ok(taxonomy_type),
+            _ => Err(Status::invalid_argument("Invalid 'type' field")),
+        }?;
+
+        let (taxonomy, moved) = match self.taxonomy_repository.find_by_slug(taxonomy_type, r.slug.as_str()).await {
+            Ok(SlugLookup::Found(taxonomy)) => Ok((taxonomy, false)),
+            Ok(SlugLookup::Moved(taxonomy)) => Ok((taxonomy, true)),
+            Ok(SlugLookup::NotFound) => Err(Status::not_found("Taxonomy not found")),
+            Err(e) => Err(Status::internal(e.to_string())),
+        }?;
+
+        Ok(Response::new(GetTaxonomyBySlugResponse {
+            canonical_slug: taxonomy.slug.clone(),
+            moved,
+            taxonomy: Some(taxonomy),
+        }))
+    }
+
+    async fn update_post_slug(
+        &self,
+        request: Request<UpdatePostSlugRequest>,
+    ) -> Result<Response<UpdatePostSlugResponse>, Status> {
+        let claims = authenticated(&request)?;
+        let r = request.into_inner();
+        if r.slug.is_empty() {
+            return Err(Status::invalid_argument("Missing required 'slug' field"));
+        }
+
+        let post = self.find_writable_post(&claims, r.post_id.as_str()).await?;
+
+        match self.post_repository.update_slug(post.id.as_str(), r.slug.as_str()).await {
+            Ok(true) => Ok(()),
+            Ok(false) => Err(Status::not_found("Post not found")),
+            Err(e) if e.is::<SlugConflictError>() => Err(Status::already_exists(e.to_string())),
+            Err(e) => Err(Status::internal(e.to_string())),
+        }?;
+
+        Ok(Response::new(UpdatePostSlugResponse {
+            post: Some(self.find_post(post.id.as_str()).await?),
+        }))
+    }
+
+    async fn update_taxonomy_slug(
+        &self,
+        request: Request<UpdateTaxonomySlugRequest>,
+    ) -> Result<Response<UpdateTaxonomySlugResponse>, Status> {
+        let claims = authenticated(&request)?;
+        if !claims.has_permission("write:taxonomy") {
+            return Err(Status::permission_denied("Forbidden"));
+        }
+        let r = request.into_inner();
+        if r.slug.is_empty() {
+            return Err(Status::invalid_argument("Missing required 'slug' field"));
+        }
+
+        match self.taxonomy_repository.update_slug(r.taxonomy_id.as_str(), r.slug.as_str()).await {
+            Ok(true) => Ok(()),
+            Ok(false) => Err(Status::not_found("Taxonomy not found")),
+            Err(e) if e.is::<SlugConflictError>() => Err(Status::already_exists(e.to_string())),
+            Err(e) => Err(Status::internal(e.to_string())),
+        }?;
+
+        match self.taxonomy_repository.find_by_id(r.taxonomy_id.as_str()).await {
+            Ok(taxonomy) => Ok(Response::new(UpdateTaxonomySlugResponse { taxonomy })),
+            Err(e) => Err(Status::internal(e.to_string())),
+        }
+    }
+
+    async fn create_preview_token(
+        &self,
+        request: Request<CreatePreviewTokenRequest>,
+    ) -> Result<Response<CreatePreviewTokenResponse>, Status> {
+        let claims = authenticated(&request)?;
+        let r = request.into_inner();
+
+        self.find_writable_post(&claims, r.post_id.as_str()).await?;
+
+        let ttl = match r.ttl_seconds {
+            ttl if ttl <= 0 => DEFAULT_PREVIEW_TOKEN_TTL,
+            ttl => ttl.min(MAX_PREVIEW_TOKEN_TTL),
+        };
+        let token = PreviewToken::new(r.post_id.as_str(), claims.sub.as_str(), ttl);
+
+        match self.preview_token_repository.create(&token).await {
+            Ok(_) => Ok(Response::new(CreatePreviewTokenResponse {
+                token: self.preview_token_signer.sign(&token),
+                expires_at: Some(Timestamp::from(SystemTime::from(token.expires_at))),
+            })),
+            Err(e) => Err(Status::internal(e.to_string())),
+        }
+    }
+
+    async fn revoke_preview_token(
+        &self,
+        request: Request<RevokePreviewTokenRequest>,
+    ) -> Result<Response<()>, Status> {
+        let claims = authenticated(&request)?;
+        let token = self.find_preview_token(request.into_inner().token.as_str()).await?;
+
+        // Any author of the post can revoke the tokens which have been shared by the others
+        self.find_writable_post(&claims, token.post_id.as_str()).await?;
+
+        match self.preview_token_repository.revoke(token.id.as_str()).await {
+            Ok(_) => Ok(Response::new(())),
+            Err(e) => Err(Status::internal(e.to_string())),
+        }
+    }
+
+    async fn get_preview_post(
+        &self,
+        request: Request<GetPreviewPostRequest>,
+    ) -> Result<Response<GetPreviewPostResponse>, Status> {
+        let remote_addr = request.remote_addr().map(|addr| addr.to_string()).unwrap_or_default();
+        let token = self.find_preview_token(request.into_inner().token.as_str()).await?;
+
+        if token.is_revoked() {
+            return Err(Status::permission_denied("Preview token has been revoked"));
+        }
+
+        self.preview_token_repository.record_access(token.id.as_str(), remote_addr.as_str()).await
+            .or_else(|err| Err(Status::internal(err.to_string())))?;
+
+        let mut post = self.find_post(token.post_id.as_str()).await?;
+        post.table_of_contents = toc::table_of_contents(post.markdown.as_str());
+
+        Ok(Response::new(GetPreviewPostResponse { post: Some(post) }))
+    }
+
+    async fn update_post_content(
+        &self,
+        request: Request<UpdatePostContentRequest>,
+    ) -> Result<Response<UpdatePostContentResponse>, Status> {
+        let claims = authenticated(&request)?;
+        let r = request.into_inner();
+
+        let post = self.find_writable_post(&claims, r.post_id.as_str()).await?;
+
+        self.post_repository.update_content(post.id.as_str(), r.markdown.as_str(), r.html.as_str()).await
+            .or_else(|err| Err(Status::internal(err.to_string())))?;
+
+        Ok(Response::new(UpdatePostContentResponse {
+            post: Some(self.find_post(post.id.as_str()).await?),
+        }))
+    }
+
+    async fn update_post_status(
+        &self,
+        request: Request<UpdatePostStatusRequest>,
+    ) -> Result<Response<UpdatePostStatusResponse>, Status> {
+        let claims = authenticated(&request)?;
+        let r = request.into_inner();
+        let status = match PostStatus::from_i32(r.status) {
+            Some(status) => Ok(status),
+            _ => Err(Status::invalid_argument("Invalid 'status' field")),
+        }?;
+
+        let post = self.find_post(r.post_id.as_str()).await?;
+        let current_status = PostStatus::from_i32(post.status).unwrap_or_default();
+        // Only the authors can submit, withdraw or return their own post, the other transitions are left to the
+        // reviewers and the publishers
+        if workflow::required_permission(current_status, status) == Some("write:post") {
+            self.find_writable_post(&claims, post.id.as_str()).await?;
+        }
+        let reviewers: Vec<String> = post.reviewers.iter().map(|r| r.id.clone()).collect();
+
+        workflow::check_transition(&claims, &reviewers, current_status, status)
+            .or_else(|err| Err(Status::permission_denied(err.to_string())))?;
+
+        let published_at = match status {
+            PostStatus::Published => Some(r.published_at.unwrap_or_else(|| Timestamp::from(SystemTime::now()))),
+            PostStatus::Scheduled => match r.published_at {
+                Some(published_at) if published_at.seconds > Timestamp::from(SystemTime::now()).seconds => {
+                    Ok(Some(published_at))
+                }
+                _ => Err(Status::invalid_argument("Scheduled post requires 'published_at' in the future")),
+            }?,
+            _ => None,
+        };
+
+        match self.post_repository.update_status(post.id.as_str(), current_status, status, published_at).await {
+            Ok(_) => Ok(()),
+            Err(e) if e.is::<StatusMismatchError>() => Err(Status::failed_precondition(e.to_string())),
+            Err(e) => Err(Status::internal(e.to_string())),
+        }?;
+
+        Ok(Response::new(UpdatePostStatusResponse {
+            post: Some(self.find_post(post.id.as_str()).await?),
+        }))
+    }
+
+    async fn assign_post_reviewers(
+        &self,
+        request: Request<AssignPostReviewersRequest>,
+    ) -> Result<Response<()>, Status> {
+        let claims = authenticated(&request)?;
+        let r = request.into_inner();
+
+        let post = self.find_writable_post(&claims, r.post_id.as_str()).await?;
+
+        // The authors cannot review their own post
+        let mut reviewers = self.find_user_ids("reviewers", &r.reviewers).await?;
+        reviewers.retain(|reviewer| {
+            !post.author.as_ref().map_or(false, |author| &author.id == reviewer)
+                && !post.co_authors.iter().any(|co_author| &co_author.id == reviewer)
+        });
+
+        match self.post_repository.update_reviewers(post.id.as_str(), &reviewers).await {
+            Ok(_) => Ok(Response::new(())),
+            Err(e) => Err(Status::internal(e.to_string())),
+        }
+    }
+
+    async fn assign_post_co_authors(
+        &self,
+        request: Request<AssignPostCoAuthorsRequest>,
+    ) -> Result<Response<()>, Status> {
+        let claims = authenticated(&request)?;
+        let r = request.into_inner();
+
+        // Only the author can share the post, the co-authors cannot add or remove each other
+        let post = self.find_writable_post(&claims, r.post_id.as_str()).await?;
+        if !post.author.as_ref().map_or(false, |author| author.id == claims.sub) {
+            return Err(Status::permission_denied("Not the author of the post"));
+        }
+
+        let mut co_authors = self.find_user_ids("co_authors", &r.co_authors).await?;
+        co_authors.retain(|co_author| co_author != &claims.sub);
+
+        match self.post_repository.update_co_authors(post.id.as_str(), &co_authors).await {
+            Ok(_) => Ok(Response::new(())),
+            Err(e) => Err(Status::internal(e.to_string())),
+        }
+    }
+
+    async fn list_posts_awaiting_review(
+        &self,
+        request: Request<ListPostsAwaitingReviewRequest>,
+    ) -> Result<Response<ListPostsAwaitingReviewResponse>, Status> {
+        let claims = authenticated(&request)?;
+        let r = request.into_inner();
+        let q = PostQuery::builder()
+            .with_status(PostStatus::InReview)
+            .with_reviewer(claims.sub.as_str())
+            .with_offset(r.offset)
+            .with_limit(r.limit);
+
+        match self.post_repository.find_all(&q).await {
+            Ok(posts) => Ok(Response::new(ListPostsAwaitingReviewResponse { posts })),
+            Err(e) => Err(Status::internal(e.to_string())),
+        }
+    }
+
+    async fn create_review_comment(
+        &self,
+        request: Request<CreateReviewCommentRequest>,
+    ) -> Result<Response<CreateReviewCommentResponse>, Status> {
+        let claims = authenticated(&request)?;
+        let r = request.into_inner();
+        let mut comment = match r.comment {
+            Some(comment) => Ok(comment),
+            _ => Err(Status::invalid_argument("Missing required 'comment' field")),
+        }?;
+
+        let post = self.find_reviewable_post(&claims, r.post_id.as_str()).await?;
+
+        let mut user = User::default();
+        user.id = claims.sub;
+        comment.author = Some(user);
+        comment.created_at = Some(Timestamp::from(SystemTime::now()));
+
+        self.review_comment_repository.create(&mut comment).await
+            .or_else(|err| Err(Status::internal(err.to_string())))?;
+
+        match self.post_repository.push_review_comment(post.id.as_str(), comment.id.as_str()).await {
+            Ok(_) => Ok(Response::new(CreateReviewCommentResponse { comment: Some(comment) })),
+            Err(e) => Err(Status::internal(e.to_string())),
+        }
+    }
+
+    async fn list_review_comments(
+        &self,
+        request: Request<ListReviewCommentsRequest>,
+    ) -> Result<Response<ListReviewCommentsResponse>, Status> {
+        let claims = authenticated(&request)?;
+        let r = request.into_inner();
+
+        let post = self.find_reviewable_post(&claims, r.post_id.as_str()).await?;
+
+        match self.post_repository.find_review_comments(post.id.as_str()).await {
+            Ok(comments) => Ok(Response::new(ListReviewCommentsResponse {
+                comments: self.mark_my_comment_reactions(Some(claims), comments).await?,
+            })),
             Err(e) => Err(Status::internal(e.to_string())),
         }
     }
@@ -112,58 +901,580 @@ impl BlogService for MyBlogService {
     //     }
     // }
 
-    // async fn list_post_attachments(
-    //     &self,
-    //     _request: Request<ListPostAttachmentsRequest>,
-    // ) -> Result<Response<ListPostAttachmentsResponse>, Status> {
-    //     Ok(Response::new(ListPostAttachmentsResponse {
-    //         attachments: vec![],
-    //     }))
-    // }
+    async fn list_post_attachments(
+        &self,
+        request: Request<ListPostAttachmentsRequest>,
+    ) -> Result<Response<ListPostAttachmentsResponse>, Status> {
+        let claims = request.extensions().get::<Claims>().cloned();
+        let post_id = request.into_inner().post_id;
+
+        // Attachments of the unpublished posts are only visible to their authors
+        let post = self.find_post(post_id.as_str()).await?;
+        if post.status != PostStatus::Published as i32 {
+            match claims {
+                Some(claims) => self.find_writable_post(&claims, post_id.as_str()).await.map(|_| ()),
+                _ => Err(Status::not_found("Post not found")),
+            }?;
+        }
+
+        self.list_attachments(post_id.as_str()).await
+    }
+
+    async fn attach_post_file(
+        &self,
+        request: Request<AttachPostFileRequest>,
+    ) -> Result<Response<ListPostAttachmentsResponse>, Status> {
+        let claims = authenticated(&request)?;
+        let r = request.into_inner();
+
+        let post = self.find_writable_post(&claims, r.post_id.as_str()).await?;
+        match self.file_repository.find_by_id(r.file_id.as_str()).await {
+            Ok(Some(_)) => Ok(()),
+            Ok(None) => Err(Status::not_found("File not found")),
+            Err(e) => Err(Status::internal(e.to_string())),
+        }?;
+
+        // The garbage collector only deletes the files which are still marked, so the mark is cleared first
+        self.file_repository.unmark_orphan(r.file_id.as_str()).await
+            .or_else(|err| Err(Status::internal(err.to_string())))?;
+        self.post_repository.push_attachment(post.id.as_str(), r.file_id.as_str()).await
+            .or_else(|err| Err(Status::internal(err.to_string())))?;
+
+        self.list_attachments(post.id.as_str()).await
+    }
+
+    async fn detach_post_file(
+        &self,
+        request: Request<DetachPostFileRequest>,
+    ) -> Result<Response<ListPostAttachmentsResponse>, Status> {
+        let claims = authenticated(&request)?;
+        let r = request.into_inner();
+
+        let post = self.find_writable_post(&claims, r.post_id.as_str()).await?;
+
+        self.post_repository.pull_attachment(post.id.as_str(), r.file_id.as_str()).await
+            .or_else(|err| Err(Status::internal(err.to_string())))?;
+
+        self.list_attachments(post.id.as_str()).await
+    }
+
+    async fn reorder_post_attachments(
+        &self,
+        request: Request<ReorderPostAttachmentsRequest>,
+    ) -> Result<Response<ListPostAttachmentsResponse>, Status> {
+        let claims = authenticated(&request)?;
+        let r = request.into_inner();
+
+        let post = self.find_writable_post(&claims, r.post_id.as_str()).await?;
+
+        match self.post_repository.reorder_attachments(post.id.as_str(), &r.file_ids).await {
+            Ok(_) => Ok(()),
+            Err(e) if e.is::<AttachmentMismatchError>() => Err(Status::failed_precondition(e.to_string())),
+            Err(e) => Err(Status::internal(e.to_string())),
+        }?;
+
+        self.list_attachments(post.id.as_str()).await
+    }
 }
 
 #[derive(Default)]
 pub struct MyBlogServiceBuilder {
     /* Repositories */
+    file_repository: Option<Box<dyn FileRepository>>,
     post_repository: Option<Box<dyn PostRepository>>,
+    preview_token_repository: Option<Box<dyn PreviewTokenRepository>>,
+    reaction_repository: Option<Box<dyn ReactionRepository>>,
+    review_comment_repository: Option<Box<dyn CommentRepository>>,
+    series_repository: Option<Box<dyn SeriesRepository>>,
     taxonomy_repository: Option<Box<dyn TaxonomyRepository>>,
+    user_repository: Option<Box<dyn UserRepository>>,
+
+    /* Signers */
+    preview_token_signer: Option<PreviewTokenSigner>,
+
+    /* Counters */
+    view_counter: Option<Arc<ViewCounter>>,
+
+    /* Options */
+    time_zone: Option<Tz>,
 }
 
 impl MyBlogServiceBuilder {
+    pub fn with_file_repository(mut self, repository: Box<dyn FileRepository>) -> Self {
+        self.file_repository = Some(repository);
+        self
+    }
+
     pub fn with_post_repository(mut self, repository: Box<dyn PostRepository>) -> Self {
         self.post_repository = Some(repository);
         self
     }
 
+    pub fn with_preview_token_repository(mut self, repository: Box<dyn PreviewTokenRepository>) -> Self {
+        self.preview_token_repository = Some(repository);
+        self
+    }
+
+    pub fn with_reaction_repository(mut self, repository: Box<dyn ReactionRepository>) -> Self {
+        self.reaction_repository = Some(repository);
+        self
+    }
+
+    pub fn with_review_comment_repository(mut self, repository: Box<dyn CommentRepository>) -> Self {
+        self.review_comment_repository = Some(repository);
+        self
+    }
+
+    pub fn with_series_repository(mut self, repository: Box<dyn SeriesRepository>) -> Self {
+        self.series_repository = Some(repository);
+        self
+    }
+
     pub fn with_taxonomy_repository(mut self, repository: Box<dyn TaxonomyRepository>) -> Self {
         self.taxonomy_repository = Some(repository);
         self
     }
 
+    pub fn with_user_repository(mut self, repository: Box<dyn UserRepository>) -> Self {
+        self.user_repository = Some(repository);
+        self
+    }
+
+    pub fn with_preview_token_signer(mut self, signer: PreviewTokenSigner) -> Self {
+        self.preview_token_signer = Some(signer);
+        self
+    }
+
+    pub fn with_view_counter(mut self, counter: Arc<ViewCounter>) -> Self {
+        self.view_counter = Some(counter);
+        self
+    }
+
+    pub fn with_time_zone(mut self, time_zone: Tz) -> Self {
+        self.time_zone = Some(time_zone);
+        self
+    }
+
     pub fn build(self) -> MyBlogService {
         MyBlogService {
+            file_repository: self.file_repository.unwrap(),
             post_repository: self.post_repository.unwrap(),
+            preview_token_repository: self.preview_token_repository.unwrap(),
+            reaction_repository: self.reaction_repository.unwrap(),
+            review_comment_repository: self.review_comment_repository.unwrap(),
+            series_repository: self.series_repository.unwrap(),
             taxonomy_repository: self.taxonomy_repository.unwrap(),
+            user_repository: self.user_repository.unwrap(),
+            preview_token_signer: self.preview_token_signer.unwrap(),
+            view_counter: self.view_counter.unwrap(),
+            time_zone: self.time_zone.unwrap_or(Tz::UTC),
         }
     }
 }
 
 #[cfg(test)]
 mod tests {
-    // use myblog_proto_rust::myblog::proto::blog::Post;
-    // 
-    // use crate::blog::post::PostRepository;
-    // 
-    // struct MockPostRepository {}
-    // 
-    // impl PostRepository for MockPostRepository {
-    //     async fn find_by_id(&self, id: &str) -> Result<Option<Post>, Box<dyn std::error::Error>> {
-    //         return Ok(None);
-    //     }
-    // }
-    // 
-    // #[test]
-    // fn my_blog_service_list_categories() {
-    //     // When
-    // }
-}
\ No newline at end of file
+    use std::collections::{HashMap, HashSet};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use mongodb::bson::DateTime;
+    use myblog_proto_rust::myblog::proto::{
+        auth::{User, UserBan},
+        blog::{
+            Archive,
+            AssignPostReviewersRequest,
+            blog_service_server::BlogService,
+            Post,
+            PostStatus,
+            Taxonomy,
+            TaxonomyType,
+            UpdatePostStatusRequest,
+        },
+        discussion::Comment,
+        storage::File,
+    };
+    use prost_types::Timestamp;
+    use tonic::{Code, Request};
+
+    use crate::auth::{access::Ban, Claims, user::{SyncedProfile, UserQuery, UserRepository}};
+    use crate::blog::{
+        post::{PostQuery, PostRepository},
+        preview::{PreviewToken, PreviewTokenRepository, PreviewTokenSigner},
+        series::{SeriesDocument, SeriesRepository},
+        service::MyBlogService,
+        slug::SlugLookup,
+        taxonomy::{TaxonomyQuery, TaxonomyRepository},
+        view::ViewCounter,
+    };
+    use crate::discussion::{
+        comment::CommentRepository,
+        reaction::{ReactionRepository, ReactionTarget},
+    };
+    use crate::storage::file::FileRepository;
+
+    /// A post repository which only holds a single post and fails on any write.
+    struct MockPostRepository {
+        post: Post,
+    }
+
+    #[tonic::async_trait]
+    impl PostRepository for MockPostRepository {
+        async fn find_by_id(&self, id: &str) -> Result<Option<Post>, Box<dyn std::error::Error>> {
+            Ok(Some(self.post.clone()).filter(|post| post.id == id))
+        }
+
+        async fn find_all(&self, _: &PostQuery) -> Result<Vec<Post>, Box<dyn std::error::Error>> {
+            Ok(vec![self.post.clone()])
+        }
+
+        async fn find_archives(&self, _: &str) -> Result<Vec<Archive>, Box<dyn std::error::Error>> {
+            unimplemented!()
+        }
+
+        async fn count_published_by_author(&self, _: &str) -> Result<u64, Box<dyn std::error::Error>> {
+            unimplemented!()
+        }
+
+        async fn count_taxonomies(&self) -> Result<(u64, HashMap<String, u64>), Box<dyn std::error::Error>> {
+            unimplemented!()
+        }
+
+        async fn find_popular(&self, _: u32, _: &PostQuery) -> Result<Vec<Post>, Box<dyn std::error::Error>> {
+            unimplemented!()
+        }
+
+        async fn find_published_ids(&self) -> Result<HashSet<String>, Box<dyn std::error::Error>> {
+            unimplemented!()
+        }
+
+        async fn find_by_slug(&self, _: &str) -> Result<SlugLookup<Post>, Box<dyn std::error::Error>> {
+            unimplemented!()
+        }
+
+        async fn update_slug(&self, _: &str, _: &str) -> Result<bool, Box<dyn std::error::Error>> {
+            unimplemented!()
+        }
+
+        async fn update_content(&self, _: &str, _: &str, _: &str) -> Result<(), Box<dyn std::error::Error>> {
+            unimplemented!()
+        }
+
+        async fn backfill_content_summaries(&self) -> Result<u64, Box<dyn std::error::Error>> {
+            unimplemented!()
+        }
+
+        async fn update_status(&self, _: &str, _: PostStatus, _: PostStatus, _: Option<Timestamp>) -> Result<(), Box<dyn std::error::Error>> {
+            unimplemented!()
+        }
+
+        async fn update_reviewers(&self, _: &str, _: &[String]) -> Result<(), Box<dyn std::error::Error>> {
+            unimplemented!()
+        }
+
+        async fn update_co_authors(&self, _: &str, _: &[String]) -> Result<(), Box<dyn std::error::Error>> {
+            unimplemented!()
+        }
+
+        async fn publish_scheduled(&self) -> Result<u64, Box<dyn std::error::Error>> {
+            unimplemented!()
+        }
+
+        async fn reassign_author(&self, _: &str, _: &str) -> Result<(), Box<dyn std::error::Error>> {
+            unimplemented!()
+        }
+
+        async fn find_review_comments(&self, _: &str) -> Result<Vec<Comment>, Box<dyn std::error::Error>> {
+            unimplemented!()
+        }
+
+        async fn push_review_comment(&self, _: &str, _: &str) -> Result<(), Box<dyn std::error::Error>> {
+            unimplemented!()
+        }
+
+        async fn find_post_attachments(&self, _: &str) -> Result<Vec<File>, Box<dyn std::error::Error>> {
+            unimplemented!()
+        }
+
+        async fn push_attachment(&self, _: &str, _: &str) -> Result<(), Box<dyn std::error::Error>> {
+            unimplemented!()
+        }
+
+        async fn pull_attachment(&self, _: &str, _: &str) -> Result<(), Box<dyn std::error::Error>> {
+            unimplemented!()
+        }
+
+        async fn reorder_attachments(&self, _: &str, _: &[String]) -> Result<(), Box<dyn std::error::Error>> {
+            unimplemented!()
+        }
+
+        async fn find_referenced_files(&self) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+            unimplemented!()
+        }
+    }
+
+    /// A repository which is not expected to be called by the test.
+    struct UnusedRepository;
+
+    #[tonic::async_trait]
+    impl FileRepository for UnusedRepository {
+        async fn create(&self, _: &File) -> Result<(), Box<dyn std::error::Error>> {
+            unimplemented!()
+        }
+
+        async fn find_by_id(&self, _: &str) -> Result<Option<File>, Box<dyn std::error::Error>> {
+            unimplemented!()
+        }
+
+        async fn find_by_slug(&self, _: &str) -> Result<Option<File>, Box<dyn std::error::Error>> {
+            unimplemented!()
+        }
+
+        async fn find_by_sha256(&self, _: &str, _: &str, _: &str) -> Result<Option<File>, Box<dyn std::error::Error>> {
+            unimplemented!()
+        }
+
+        async fn sum_size_by_uploader(&self, _: &str) -> Result<u64, Box<dyn std::error::Error>> {
+            unimplemented!()
+        }
+
+        async fn count_by_path(&self, _: &str) -> Result<u64, Box<dyn std::error::Error>> {
+            unimplemented!()
+        }
+
+        async fn mark_orphans(&self, _: &[String]) -> Result<u64, Box<dyn std::error::Error>> {
+            unimplemented!()
+        }
+
+        async fn find_orphans(&self, _: &[String], _: DateTime) -> Result<Vec<File>, Box<dyn std::error::Error>> {
+            unimplemented!()
+        }
+
+        async fn find_all_paths(&self) -> Result<HashSet<String>, Box<dyn std::error::Error>> {
+            unimplemented!()
+        }
+
+        async fn unmark_orphan(&self, _: &str) -> Result<(), Box<dyn std::error::Error>> {
+            unimplemented!()
+        }
+
+        async fn delete(&self, _: &str) -> Result<(), Box<dyn std::error::Error>> {
+            unimplemented!()
+        }
+
+        async fn delete_orphan(&self, _: &str, _: DateTime) -> Result<bool, Box<dyn std::error::Error>> {
+            unimplemented!()
+        }
+    }
+
+    #[tonic::async_trait]
+    impl PreviewTokenRepository for UnusedRepository {
+        async fn create(&self, _: &PreviewToken) -> Result<(), Box<dyn std::error::Error>> {
+            unimplemented!()
+        }
+
+        async fn find_by_id(&self, _: &str) -> Result<Option<PreviewToken>, Box<dyn std::error::Error>> {
+            unimplemented!()
+        }
+
+        async fn revoke(&self, _: &str) -> Result<(), Box<dyn std::error::Error>> {
+            unimplemented!()
+        }
+
+        async fn record_access(&self, _: &str, _: &str) -> Result<(), Box<dyn std::error::Error>> {
+            unimplemented!()
+        }
+    }
+
+    #[tonic::async_trait]
+    impl ReactionRepository for UnusedRepository {
+        async fn toggle(&self, _: ReactionTarget, _: &str, _: &str, _: &str) -> Result<bool, Box<dyn std::error::Error>> {
+            unimplemented!()
+        }
+
+        async fn find_user_reactions(&self, _: ReactionTarget, _: &[&str], _: &str) -> Result<HashMap<String, Vec<String>>, Box<dyn std::error::Error>> {
+            unimplemented!()
+        }
+
+        async fn delete_by_user(&self, _: &str) -> Result<(), Box<dyn std::error::Error>> {
+            unimplemented!()
+        }
+    }
+
+    #[tonic::async_trait]
+    impl CommentRepository for UnusedRepository {
+        async fn create(&self, _: &mut Comment) -> Result<(), Box<dyn std::error::Error>> {
+            unimplemented!()
+        }
+
+        async fn reassign_author(&self, _: &str, _: &str) -> Result<(), Box<dyn std::error::Error>> {
+            unimplemented!()
+        }
+
+        async fn set_hidden_by_author(&self, _: &str, _: bool) -> Result<(), Box<dyn std::error::Error>> {
+            unimplemented!()
+        }
+    }
+
+    #[tonic::async_trait]
+    impl SeriesRepository for UnusedRepository {
+        async fn find_by_id(&self, _: &str) -> Result<Option<SeriesDocument>, Box<dyn std::error::Error>> {
+            unimplemented!()
+        }
+
+        async fn find_by_post(&self, _: &str) -> Result<Option<SeriesDocument>, Box<dyn std::error::Error>> {
+            unimplemented!()
+        }
+
+        async fn reorder(&self, _: &str, _: &[String]) -> Result<(), Box<dyn std::error::Error>> {
+            unimplemented!()
+        }
+    }
+
+    #[tonic::async_trait]
+    impl TaxonomyRepository for UnusedRepository {
+        async fn find_by_id(&self, _: &str) -> Result<Option<Taxonomy>, Box<dyn std::error::Error>> {
+            unimplemented!()
+        }
+
+        async fn find_all(&self, _: TaxonomyQuery) -> Result<Vec<Taxonomy>, Box<dyn std::error::Error>> {
+            unimplemented!()
+        }
+
+        async fn find_all_by_ids(&self, _: &Vec<&str>) -> Result<Vec<Taxonomy>, Box<dyn std::error::Error>> {
+            unimplemented!()
+        }
+
+        async fn find_by_slug(&self, _: TaxonomyType, _: &str) -> Result<SlugLookup<Taxonomy>, Box<dyn std::error::Error>> {
+            unimplemented!()
+        }
+
+        async fn update_slug(&self, _: &str, _: &str) -> Result<bool, Box<dyn std::error::Error>> {
+            unimplemented!()
+        }
+    }
+
+    #[tonic::async_trait]
+    impl UserRepository for UnusedRepository {
+        async fn create(&self, _: &User) -> Result<(), Box<dyn std::error::Error>> {
+            unimplemented!()
+        }
+
+        async fn find_by_id(&self, _: &str) -> Result<Option<User>, Box<dyn std::error::Error>> {
+            unimplemented!()
+        }
+
+        async fn find_synced_profile(&self, _: &str) -> Result<SyncedProfile, Box<dyn std::error::Error>> {
+            unimplemented!()
+        }
+
+        async fn find_all(&self, _: &UserQuery) -> Result<Vec<User>, Box<dyn std::error::Error>> {
+            unimplemented!()
+        }
+
+        async fn find_all_by_ids(&self, _: &[&str]) -> Result<Vec<User>, Box<dyn std::error::Error>> {
+            unimplemented!()
+        }
+
+        async fn find_access_entries(
+            &self,
+        ) -> Result<(HashMap<String, Ban>, HashMap<String, Vec<String>>), Box<dyn std::error::Error>> {
+            unimplemented!()
+        }
+
+        async fn update(&self, _: &User) -> Result<(), Box<dyn std::error::Error>> {
+            unimplemented!()
+        }
+
+        async fn update_roles(&self, _: &str, _: &[String]) -> Result<(), Box<dyn std::error::Error>> {
+            unimplemented!()
+        }
+
+        async fn ban(&self, _: &str, _: &UserBan) -> Result<(), Box<dyn std::error::Error>> {
+            unimplemented!()
+        }
+
+        async fn unban(&self, _: &str) -> Result<(), Box<dyn std::error::Error>> {
+            unimplemented!()
+        }
+
+        async fn upsert(&self, _: &User, _: &SyncedProfile) -> Result<(), Box<dyn std::error::Error>> {
+            unimplemented!()
+        }
+
+        async fn delete(&self, _: &str) -> Result<(), Box<dyn std::error::Error>> {
+            unimplemented!()
+        }
+    }
+
+    fn new_service(post: Post) -> MyBlogService {
+        MyBlogService::builder()
+            .with_file_repository(Box::from(UnusedRepository))
+            .with_post_repository(Box::from(MockPostRepository { post }))
+            .with_preview_token_repository(Box::from(UnusedRepository))
+            .with_reaction_repository(Box::from(UnusedRepository))
+            .with_review_comment_repository(Box::from(UnusedRepository))
+            .with_series_repository(Box::from(UnusedRepository))
+            .with_taxonomy_repository(Box::from(UnusedRepository))
+            .with_user_repository(Box::from(UnusedRepository))
+            .with_preview_token_signer(PreviewTokenSigner::new(b"secret"))
+            .with_view_counter(Arc::new(ViewCounter::new(Duration::from_secs(60))))
+            .build()
+    }
+
+    fn new_draft_post() -> Post {
+        Post {
+            id: String::from("5b2863365c31b411b041995e"),
+            status: PostStatus::Draft as i32,
+            author: Some(User { id: String::from("github|1"), ..Default::default() }),
+            ..Default::default()
+        }
+    }
+
+    fn new_request<T>(message: T, sub: &str) -> Request<T> {
+        let mut request = Request::new(message);
+        request.extensions_mut().insert(Claims {
+            sub: String::from(sub),
+            permissions: vec![String::from("write:post")],
+            name: None,
+            picture: None,
+            email: None,
+            email_verified: None,
+        });
+
+        request
+    }
+
+    #[tokio::test]
+    async fn submit_post_of_another_author() {
+        // Given
+        let service = new_service(new_draft_post());
+        let request = new_request(UpdatePostStatusRequest {
+            post_id: String::from("5b2863365c31b411b041995e"),
+            status: PostStatus::InReview as i32,
+            ..Default::default()
+        }, "github|2");
+
+        // When
+        let result = service.update_post_status(request).await;
+
+        // Then
+        assert_eq!(Code::PermissionDenied, result.unwrap_err().code());
+    }
+
+    #[tokio::test]
+    async fn assign_reviewers_to_post_of_another_author() {
+        // Given
+        let service = new_service(new_draft_post());
+        let request = new_request(AssignPostReviewersRequest {
+            post_id: String::from("5b2863365c31b411b041995e"),
+            reviewers: vec![String::from("github|2")],
+        }, "github|2");
+
+        // When
+        let result = service.assign_post_reviewers(request).await;
+
+        // Then
+        assert_eq!(Code::PermissionDenied, result.unwrap_err().code());
+    }
+}
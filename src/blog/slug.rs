@@ -0,0 +1,217 @@
+use std::error::Error;
+use std::fmt;
+use std::str::FromStr;
+
+use mongodb::{bson::Bson, bson::DateTime, bson::doc, bson::Document, bson::oid::ObjectId, Collection, IndexModel};
+use mongodb::error::{ErrorKind, WriteFailure};
+use mongodb::options::IndexOptions;
+use tokio_stream::StreamExt;
+
+/// A result of looking up a resource by one of its slugs.
+#[derive(Debug, PartialEq)]
+pub enum SlugLookup<T> {
+    /// The given slug is the current slug of the resource.
+    Found(T),
+    /// The given slug has been retired, the resource is now reachable under its canonical slug.
+    Moved(T),
+    NotFound,
+}
+
+/// An error returned when a slug is already taken by another resource, either currently or historically.
+#[derive(Debug)]
+pub struct SlugConflictError {
+    pub slug: String,
+}
+
+impl fmt::Display for SlugConflictError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Slug '{}' is already taken", self.slug)
+    }
+}
+
+impl Error for SlugConflictError {}
+
+/// Return a new slug history after the resource has changed its slug from `current` to `new`.
+///
+/// Changing back to a retired slug removes it from the history, so it becomes the current slug again.
+pub fn retire_slug(history: &[String], current: &str, new: &str) -> Vec<String> {
+    let mut result: Vec<String> = history
+        .iter()
+        .filter(|slug| slug.as_str() != new && slug.as_str() != current)
+        .cloned()
+        .collect();
+
+    if current != new && !current.is_empty() {
+        result.push(current.to_owned());
+    }
+
+    result
+}
+
+/// Return a filter that matches any document of the type which is currently or was previously using the slug.
+///
+/// The slugs are only unique within the same `type`, the documents without any type (e.g. the posts) share one.
+pub(crate) fn slug_filter(r#type: impl Into<Bson>, slug: &str) -> Document {
+    doc! {"type": r#type.into(), "$or": [{"slug": slug}, {"previousSlugs": slug}]}
+}
+
+/// Create the unique indexes of the current and retired slugs within each type.
+///
+/// The documents without any retired slug are left out, so they do not collide on the missing field.
+/// An index is not created while the existing documents already share a slug, so the service can still start
+/// and the duplicates can be renamed, the slug changes are checked for conflicts either way.
+pub async fn create_indexes(collection: &Collection<Document>) -> Result<(), Box<dyn Error>> {
+    let mut indexes = vec![];
+    for field in ["slug", "previousSlugs"].iter() {
+        let duplicates = find_duplicates(collection, field).await?;
+        if !duplicates.is_empty() {
+            eprintln!(
+                "not creating the unique index of '{}' on '{}', duplicate slugs: {}",
+                field,
+                collection.name(),
+                duplicates.join(", "),
+            );
+            continue;
+        }
+
+        indexes.push(
+            IndexModel::builder()
+                .keys(doc! {"type": 1, *field: 1})
+                .options(
+                    IndexOptions::builder()
+                        .unique(true)
+                        .partial_filter_expression(doc! {*field: {"$type": "string"}})
+                        .build(),
+                )
+                .build(),
+        );
+    }
+
+    if !indexes.is_empty() {
+        collection.create_indexes(indexes, None).await?;
+    }
+
+    Ok(())
+}
+
+/// Return the slugs of the field which are used by more than one document of the same type.
+async fn find_duplicates(collection: &Collection<Document>, field: &str) -> Result<Vec<String>, Box<dyn Error>> {
+    let pipeline = vec![
+        doc! {"$match": {field: {"$type": "string"}}},
+        doc! {"$unwind": format!("${}", field)},
+        doc! {"$group": {"_id": {"type": "$type", "slug": format!("${}", field)}, "ids": {"$addToSet": "$_id"}}},
+        doc! {"$match": {"ids.1": {"$exists": true}}},
+    ];
+
+    let mut cursor = collection.aggregate(pipeline, None).await?;
+    let mut result = vec![];
+
+    while let Some(document) = cursor.try_next().await? {
+        result.push(document.get_document("_id")?.get_str("slug")?.to_owned());
+    }
+
+    Ok(result)
+}
+
+/// Change the slug of the document while keeping the retired slug in its `previousSlugs` history.
+///
+/// Return false if there is no document with the ID.
+pub(crate) async fn update_slug(
+    collection: &Collection<Document>,
+    id: &str,
+    slug: &str,
+) -> Result<bool, Box<dyn Error>> {
+    let object_id = ObjectId::from_str(id)?;
+
+    let document = match collection.find_one(doc! {"_id": object_id}, None).await? {
+        Some(document) => document,
+        _ => return Ok(false),
+    };
+
+    let mut filter = slug_filter(document.get("type").cloned().unwrap_or(Bson::Null), slug);
+    filter.insert("_id", doc! {"$ne": object_id});
+    if collection.count_documents(filter, None).await? > 0 {
+        return Err(Box::new(SlugConflictError { slug: slug.to_owned() }));
+    }
+
+    let history: Vec<String> = match document.get_array("previousSlugs") {
+        Ok(slugs) => slugs.iter().filter_map(|s| s.as_str()).map(String::from).collect(),
+        _ => vec![],
+    };
+    let previous_slugs = retire_slug(&history, document.get_str("slug")?, slug);
+
+    // The unique indexes reject a concurrent change which has taken the same slug since it was checked
+    match collection
+        .update_one(
+            doc! {"_id": object_id},
+            doc! {"$set": {"slug": slug, "previousSlugs": previous_slugs, "updatedAt": DateTime::now()}},
+            None,
+        )
+        .await
+    {
+        Ok(result) => Ok(result.matched_count > 0),
+        Err(e) if is_duplicate_key(&e) => Err(Box::new(SlugConflictError { slug: slug.to_owned() })),
+        Err(e) => Err(Box::new(e)),
+    }
+}
+
+/// Return true if the write has been rejected by a unique index.
+fn is_duplicate_key(err: &mongodb::error::Error) -> bool {
+    matches!(*err.kind, ErrorKind::Write(WriteFailure::WriteError(ref e)) if e.code == 11000)
+}
+
+#[cfg(test)]
+mod tests {
+    use mongodb::bson::{Bson, doc};
+
+    use crate::blog::slug::{retire_slug, slug_filter};
+
+    #[test]
+    fn retire_slug_pushes_current_slug() {
+        // Given
+        let history = vec![String::from("hello-world-1")];
+
+        // When
+        let result = retire_slug(&history, "hello-world-2", "hello-world-3");
+
+        // Then
+        assert_eq!(vec!["hello-world-1", "hello-world-2"], result);
+    }
+
+    #[test]
+    fn retire_slug_restores_retired_slug() {
+        // Given
+        let history = vec![String::from("hello-world-1"), String::from("hello-world-2")];
+
+        // When
+        let result = retire_slug(&history, "hello-world-3", "hello-world-1");
+
+        // Then
+        assert_eq!(vec!["hello-world-2", "hello-world-3"], result);
+    }
+
+    #[test]
+    fn retire_slug_with_unchanged_slug() {
+        // Given
+        let history = vec![String::from("hello-world-1")];
+
+        // When
+        let result = retire_slug(&history, "hello-world-2", "hello-world-2");
+
+        // Then
+        assert_eq!(vec!["hello-world-1"], result);
+    }
+
+    #[test]
+    fn slug_filter_is_scoped_by_type() {
+        // Given
+
+        // When
+        let taxonomy = slug_filter(1, "rust");
+        let post = slug_filter(Bson::Null, "rust");
+
+        // Then
+        assert_eq!(doc! {"type": 1, "$or": [{"slug": "rust"}, {"previousSlugs": "rust"}]}, taxonomy);
+        assert_eq!(doc! {"type": Bson::Null, "$or": [{"slug": "rust"}, {"previousSlugs": "rust"}]}, post);
+    }
+}
@@ -5,7 +5,8 @@ use mongodb::options::FindOptions;
 use myblog_proto_rust::myblog::proto::blog::{Taxonomy, TaxonomyType};
 use tokio_stream::StreamExt;
 
-use crate::encoding::bson::Unmarshaler;
+use crate::blog::slug::{self, SlugLookup};
+use crate::encoding::bson::{ListingMode, Marshaler, Unmarshaler};
 
 /// A taxonomy repository definition.
 #[tonic::async_trait]
@@ -13,6 +14,8 @@ pub trait TaxonomyRepository: Send + Sync + 'static {
     async fn find_by_id(&self, id: &str) -> Result<Option<Taxonomy>, Box<dyn std::error::Error>>;
     async fn find_all(&self, q: TaxonomyQuery) -> Result<Vec<Taxonomy>, Box<dyn std::error::Error>>;
     async fn find_all_by_ids(&self, ids: &Vec<&str>) -> Result<Vec<Taxonomy>, Box<dyn std::error::Error>>;
+    async fn find_by_slug(&self, taxonomy_type: TaxonomyType, slug: &str) -> Result<SlugLookup<Taxonomy>, Box<dyn std::error::Error>>;
+    async fn update_slug(&self, id: &str, slug: &str) -> Result<bool, Box<dyn std::error::Error>>;
 }
 
 /// A taxonomy query builder.
@@ -38,11 +41,18 @@ impl TaxonomyQuery {
 /// An implementation of the TaxonomyRepository specifies with MongoDB.
 pub struct MongoTaxonomyRepository {
     collection: Collection<Document>,
+    listing_mode: ListingMode,
 }
 
 impl MongoTaxonomyRepository {
     pub fn new(collection: Collection<Document>) -> Self {
-        MongoTaxonomyRepository { collection }
+        MongoTaxonomyRepository { collection, listing_mode: ListingMode::default() }
+    }
+
+    /// Set how the listings of the taxonomies treat the documents which cannot be un-marshaled.
+    pub fn with_listing_mode(mut self, listing_mode: ListingMode) -> Self {
+        self.listing_mode = listing_mode;
+        self
     }
 }
 
@@ -69,7 +79,9 @@ impl TaxonomyRepository for MongoTaxonomyRepository {
         let mut result: Vec<Taxonomy> = vec![];
 
         while let Some(document) = cursor.try_next().await? {
-            result.push(Taxonomy::unmarshal_bson(&document)?);
+            if let Some(taxonomy) = self.listing_mode.unmarshal(&document)? {
+                result.push(taxonomy);
+            }
         }
 
         Ok(result)
@@ -86,25 +98,66 @@ impl TaxonomyRepository for MongoTaxonomyRepository {
         let mut result: Vec<Taxonomy> = vec![];
 
         while let Some(document) = cursor.try_next().await? {
-            result.push(Taxonomy::unmarshal_bson(&document)?);
+            if let Some(taxonomy) = self.listing_mode.unmarshal(&document)? {
+                result.push(taxonomy);
+            }
         }
 
         Ok(result)
     }
+
+    async fn find_by_slug(
+        &self,
+        taxonomy_type: TaxonomyType,
+        slug: &str,
+    ) -> Result<SlugLookup<Taxonomy>, Box<dyn std::error::Error>> {
+        let filter = slug::slug_filter(taxonomy_type as i32, slug);
+
+        if let Some(document) = self.collection.find_one(filter, None).await? {
+            let taxonomy = Taxonomy::unmarshal_bson(&document)?;
+
+            return if taxonomy.slug == slug {
+                Ok(SlugLookup::Found(taxonomy))
+            } else {
+                Ok(SlugLookup::Moved(taxonomy))
+            };
+        }
+
+        Ok(SlugLookup::NotFound)
+    }
+
+    async fn update_slug(&self, id: &str, slug: &str) -> Result<bool, Box<dyn std::error::Error>> {
+        slug::update_slug(&self.collection, id, slug).await
+    }
+}
+
+#[derive(Marshaler, Unmarshaler)]
+#[bson(remote = "Taxonomy")]
+struct TaxonomySchema {
+    #[bson(rename = "_id", object_id)]
+    id: String,
+    name: String,
+    slug: String,
+    r#type: i32,
 }
 
-impl Unmarshaler for Taxonomy {
-    fn unmarshal_bson(
-        document: &Document,
-    ) -> Result<Self, mongodb::bson::document::ValueAccessError>
-        where
-            Self: Sized,
-    {
-        Ok(Taxonomy {
-            id: document.get_object_id("_id")?.to_hex(),
-            name: document.get_str("name")?.to_owned(),
-            slug: document.get_str("slug")?.to_owned(),
-            r#type: document.get_i32("type")?.to_owned(),
-        })
+#[cfg(test)]
+mod tests {
+    use myblog_proto_rust::myblog::proto::blog::Taxonomy;
+    use proptest::prelude::*;
+
+    use crate::encoding::bson::{arbitrary, Marshaler, Unmarshaler};
+
+    proptest! {
+        #[test]
+        fn round_trip_taxonomy(taxonomy in arbitrary::taxonomy()) {
+            // Given
+
+            // When
+            let result = Taxonomy::unmarshal_bson(&taxonomy.marshal_bson().unwrap()).unwrap();
+
+            // Then
+            prop_assert_eq!(taxonomy, result);
+        }
     }
 }
@@ -0,0 +1,194 @@
+use std::collections::HashSet;
+
+use myblog_proto_rust::myblog::proto::blog::Heading;
+use pulldown_cmark::{Event, Options, Parser, Tag};
+use unicode_normalization::char::is_combining_mark;
+
+/// Generate unique anchor ids for the headings in the same way as the GitHub slugger,
+/// which is what the markdown renderer of the frontend uses.
+#[derive(Default)]
+pub struct Slugger {
+    seen: HashSet<String>,
+}
+
+impl Slugger {
+    /// Mark the id as taken, so none of the generated slugs collides with it.
+    pub fn reserve(&mut self, id: &str) {
+        self.seen.insert(id.to_owned());
+    }
+
+    pub fn slug(&mut self, text: &str) -> String {
+        let mut base: String = text
+            .trim()
+            .to_lowercase()
+            .chars()
+            .filter_map(|c| match c {
+                '-' | '_' => Some(c),
+                c if c.is_whitespace() => Some('-'),
+                c if c.is_ascii() => if c.is_ascii_alphanumeric() { Some(c) } else { None },
+                // Letters, numbers and marks of the non-Latin scripts (e.g. Thai vowels) are kept
+                c if c.is_alphanumeric() || is_combining_mark(c) => Some(c),
+                _ => None,
+            })
+            .collect();
+        if base.is_empty() {
+            base = String::from("section");
+        }
+
+        let mut slug = base.clone();
+        let mut n = 0;
+        while self.seen.contains(&slug) {
+            n += 1;
+            slug = format!("{}-{}", base, n);
+        }
+
+        self.seen.insert(slug.clone());
+        slug
+    }
+}
+
+/// Parse the headings in the markdown and return them as a nested table of contents.
+pub fn table_of_contents(markdown: &str) -> Vec<Heading> {
+    let mut parsed: Vec<(u32, Option<String>, String)> = vec![];
+    let mut current: Option<(u32, Option<String>, String)> = None;
+
+    for event in Parser::new_ext(markdown, Options::ENABLE_HEADING_ATTRIBUTES) {
+        match event {
+            Event::Start(Tag::Heading(level, id, _)) => {
+                current = Some((level as u32, id.map(String::from), String::new()))
+            }
+            Event::Text(t) | Event::Code(t) => {
+                if let Some((_, _, text)) = current.as_mut() {
+                    text.push_str(&t);
+                }
+            }
+            Event::End(Tag::Heading(..)) => parsed.extend(current.take()),
+            _ => (),
+        }
+    }
+
+    // An explicit `{#id}` attribute always wins over the generated one, even of an earlier heading
+    let mut slugger = Slugger::default();
+    for (_, id, _) in parsed.iter() {
+        if let Some(id) = id {
+            slugger.reserve(id.as_str());
+        }
+    }
+    let headings: Vec<Heading> = parsed
+        .into_iter()
+        .map(|(level, id, text)| Heading {
+            id: match id {
+                Some(id) => id,
+                _ => slugger.slug(text.as_str()),
+            },
+            text,
+            level,
+            children: vec![],
+        })
+        .collect();
+
+    nest(&headings, &mut 0, 0)
+}
+
+fn nest(headings: &[Heading], i: &mut usize, parent_level: u32) -> Vec<Heading> {
+    let mut result: Vec<Heading> = vec![];
+
+    while *i < headings.len() && headings[*i].level > parent_level {
+        let mut heading = headings[*i].clone();
+        *i += 1;
+
+        heading.children = nest(headings, i, heading.level);
+        result.push(heading);
+    }
+
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::blog::toc::{Slugger, table_of_contents};
+
+    #[test]
+    fn slug_of_heading_text() {
+        // Given
+        let mut slugger = Slugger::default();
+
+        // When
+        let result = slugger.slug("Hello, World! (Part 1)");
+
+        // Then
+        assert_eq!("hello-world-part-1", result);
+    }
+
+    #[test]
+    fn slug_of_duplicate_heading_texts() {
+        // Given
+        let mut slugger = Slugger::default();
+
+        // When
+        let results = vec![slugger.slug("Setup"), slugger.slug("Setup"), slugger.slug("Setup-1")];
+
+        // Then
+        assert_eq!(vec!["setup", "setup-1", "setup-1-1"], results);
+    }
+
+    #[test]
+    fn slug_of_thai_heading_text() {
+        // Given
+        let mut slugger = Slugger::default();
+
+        // When
+        let result = slugger.slug("ติดตั้ง Rust บน macOS");
+
+        // Then
+        assert_eq!("ติดตั้ง-rust-บน-macos", result);
+    }
+
+    #[test]
+    fn slug_of_punctuation_only_heading_text() {
+        // Given
+        let mut slugger = Slugger::default();
+
+        // When
+        let result = slugger.slug("???");
+
+        // Then
+        assert_eq!("section", result);
+    }
+
+    #[test]
+    fn generated_slug_does_not_take_explicit_id() {
+        // Given
+        let markdown = "## Setup\n\n## Installation {#setup}\n";
+
+        // When
+        let result = table_of_contents(markdown);
+
+        // Then
+        assert_eq!(
+            vec!["setup-1", "setup"],
+            result.iter().map(|h| h.id.as_str()).collect::<Vec<&str>>(),
+        );
+    }
+
+    #[test]
+    fn nested_table_of_contents() {
+        // Given
+        let markdown = "# Title\n\n## Install\n\n### Linux\n\n### macOS\n\n## Usage {#how-to-use}\n";
+
+        // When
+        let result = table_of_contents(markdown);
+
+        // Then
+        assert_eq!(1, result.len());
+        assert_eq!("title", result[0].id);
+        assert_eq!(
+            vec!["install", "how-to-use"],
+            result[0].children.iter().map(|h| h.id.as_str()).collect::<Vec<&str>>(),
+        );
+        assert_eq!(
+            vec!["linux", "macos"],
+            result[0].children[0].children.iter().map(|h| h.id.as_str()).collect::<Vec<&str>>(),
+        );
+    }
+}
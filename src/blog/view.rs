@@ -0,0 +1,298 @@
+use std::collections::{HashMap, HashSet};
+use std::net::SocketAddr;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
+
+use mongodb::{bson::DateTime, bson::doc, bson::Document, bson::oid::ObjectId, Collection};
+use mongodb::options::UpdateOptions;
+use sha2::{Digest, Sha256};
+use warp::{Filter, http::StatusCode, Rejection, Reply};
+
+use crate::blog::post::PostRepository;
+
+/// A number of milliseconds in a day which is the bucket size of the view counts.
+const DAY_IN_MILLIS: i64 = 24 * 60 * 60 * 1000;
+
+/// Count post views in memory, de-duplicated by visitor fingerprint within a time window.
+///
+/// The counts are kept per post and per day until they are drained and flushed into the repository.
+/// Only the views of the cached published posts are counted, so the unknown post IDs are not kept at all.
+pub struct ViewCounter {
+    window: Duration,
+    published: RwLock<HashSet<String>>,
+    seen: Mutex<HashMap<(String, String), Instant>>,
+    pending: Mutex<HashMap<(String, i64), u64>>,
+}
+
+impl ViewCounter {
+    pub fn new(window: Duration) -> Self {
+        ViewCounter {
+            window,
+            published: RwLock::new(HashSet::new()),
+            seen: Mutex::new(HashMap::new()),
+            pending: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Replace the IDs of the published posts whose views are counted.
+    pub fn set_published(&self, post_ids: HashSet<String>) {
+        *self.published.write().unwrap() = post_ids;
+    }
+
+    /// Replace the IDs of the published posts with the latest ones from the repository.
+    pub async fn refresh(&self, repository: &dyn PostRepository) -> Result<(), Box<dyn std::error::Error>> {
+        self.set_published(repository.find_published_ids().await?);
+
+        Ok(())
+    }
+
+    /// Record a view of the post by the visitor and return true if it has been counted.
+    pub fn record(&self, post_id: &str, fingerprint: &str) -> bool {
+        if !self.published.read().unwrap().contains(post_id) {
+            return false;
+        }
+
+        let now = Instant::now();
+        let key = (post_id.to_owned(), fingerprint.to_owned());
+
+        {
+            let mut seen = self.seen.lock().unwrap();
+            if let Some(last_seen) = seen.get(&key) {
+                if now.duration_since(*last_seen) < self.window {
+                    return false;
+                }
+            }
+            seen.insert(key, now);
+        }
+
+        let day = DateTime::now().timestamp_millis() / DAY_IN_MILLIS * DAY_IN_MILLIS;
+        *self.pending.lock().unwrap().entry((post_id.to_owned(), day)).or_insert(0) += 1;
+
+        true
+    }
+
+    /// Take all pending counts out of the counter and forget visitors whose window has passed.
+    pub fn drain(&self) -> HashMap<(String, i64), u64> {
+        let window = self.window;
+        self.seen.lock().unwrap().retain(|_, last_seen| last_seen.elapsed() < window);
+
+        std::mem::take(&mut *self.pending.lock().unwrap())
+    }
+
+    /// Put the counts back into the counter, e.g. after a failed flush.
+    pub fn restore(&self, counts: HashMap<(String, i64), u64>) {
+        let mut pending = self.pending.lock().unwrap();
+
+        for (key, count) in counts {
+            *pending.entry(key).or_insert(0) += count;
+        }
+    }
+
+    /// Write all pending counts into the repository.
+    pub async fn flush(&self, repository: &dyn ViewRepository) -> Result<(), Box<dyn std::error::Error>> {
+        let mut counts = self.drain();
+        if counts.is_empty() {
+            return Ok(());
+        }
+
+        // Only the counts which have not been applied are put back, the applied ones would be counted twice
+        if let Err(e) = repository.increment(&mut counts).await {
+            self.restore(counts);
+            return Err(e);
+        }
+
+        Ok(())
+    }
+}
+
+/// Return an anonymous visitor fingerprint derived from the remote address and the user agent.
+pub fn fingerprint(remote_addr: &str, user_agent: &str) -> String {
+    hex::encode(Sha256::digest(format!("{}|{}", remote_addr, user_agent).as_bytes()))
+}
+
+/// Return the HTTP route for recording a post view, i.e. `POST /posts/{id}/views`.
+pub fn routes(counter: Arc<ViewCounter>) -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+    warp::post()
+        .and(warp::path!("posts" / String / "views"))
+        .and(warp::addr::remote())
+        .and(warp::header::optional::<String>("user-agent"))
+        .map(move |post_id: String, remote_addr: Option<SocketAddr>, user_agent: Option<String>| {
+            if ObjectId::from_str(post_id.as_str()).is_err() {
+                return StatusCode::BAD_REQUEST;
+            }
+
+            let remote_addr = remote_addr.map(|addr| addr.ip().to_string()).unwrap_or_default();
+            counter.record(
+                post_id.as_str(),
+                fingerprint(remote_addr.as_str(), user_agent.unwrap_or_default().as_str()).as_str(),
+            );
+
+            StatusCode::NO_CONTENT
+        })
+}
+
+/// A view repository definition.
+#[tonic::async_trait]
+pub trait ViewRepository: Send + Sync + 'static {
+    /// Add the counts into the stored views, each count is removed from the map once it has been applied.
+    async fn increment(&self, counts: &mut HashMap<(String, i64), u64>) -> Result<(), Box<dyn std::error::Error>>;
+}
+
+/// An implementation of the ViewRepository specifies with MongoDB.
+pub struct MongoViewRepository {
+    collection: Collection<Document>,
+}
+
+impl MongoViewRepository {
+    pub fn new(collection: Collection<Document>) -> Self {
+        MongoViewRepository { collection }
+    }
+}
+
+#[tonic::async_trait]
+impl ViewRepository for MongoViewRepository {
+    async fn increment(&self, counts: &mut HashMap<(String, i64), u64>) -> Result<(), Box<dyn std::error::Error>> {
+        let keys: Vec<(String, i64)> = counts.keys().cloned().collect();
+
+        for key in keys {
+            let (post_id, day) = &key;
+            self.collection
+                .update_one(
+                    doc! {"post": ObjectId::from_str(post_id.as_str())?, "day": DateTime::from_millis(*day)},
+                    doc! {"$inc": {"count": counts[&key] as i64}},
+                    UpdateOptions::builder().upsert(true).build(),
+                )
+                .await?;
+            counts.remove(&key);
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{HashMap, HashSet};
+    use std::sync::Mutex;
+    use std::time::Duration;
+
+    use crate::blog::view::{fingerprint, ViewCounter, ViewRepository};
+
+    /// A view repository which applies the first count and fails on the next one.
+    struct FailingViewRepository {
+        applied: Mutex<Vec<String>>,
+    }
+
+    #[tonic::async_trait]
+    impl ViewRepository for FailingViewRepository {
+        async fn increment(&self, counts: &mut HashMap<(String, i64), u64>) -> Result<(), Box<dyn std::error::Error>> {
+            let key = counts.keys().next().cloned().unwrap();
+            counts.remove(&key);
+            self.applied.lock().unwrap().push(key.0);
+
+            Err(Box::from("connection reset"))
+        }
+    }
+
+    fn new_view_counter(window: Duration) -> ViewCounter {
+        let counter = ViewCounter::new(window);
+        counter.set_published(
+            vec!["5b2863365c31b411b041995e", "5b2863365c31b411b041995f"]
+                .into_iter()
+                .map(String::from)
+                .collect::<HashSet<String>>(),
+        );
+
+        counter
+    }
+
+    #[test]
+    fn record_view_de_duplicates_visitor() {
+        // Given
+        let counter = new_view_counter(Duration::from_secs(60));
+
+        // When
+        let first = counter.record("5b2863365c31b411b041995e", "visitor-1");
+        let second = counter.record("5b2863365c31b411b041995e", "visitor-1");
+        let third = counter.record("5b2863365c31b411b041995e", "visitor-2");
+
+        // Then
+        assert!(first);
+        assert!(!second);
+        assert!(third);
+        assert_eq!(vec![2], counter.drain().values().cloned().collect::<Vec<u64>>());
+    }
+
+    #[test]
+    fn record_view_after_window_has_passed() {
+        // Given
+        let counter = new_view_counter(Duration::from_secs(0));
+
+        // When
+        counter.record("5b2863365c31b411b041995e", "visitor-1");
+        let result = counter.record("5b2863365c31b411b041995e", "visitor-1");
+
+        // Then
+        assert!(result);
+    }
+
+    #[test]
+    fn record_view_of_unpublished_post() {
+        // Given
+        let counter = new_view_counter(Duration::from_secs(60));
+
+        // When
+        let result = counter.record("5b2863365c31b411b0419960", "visitor-1");
+
+        // Then
+        assert!(!result);
+        assert!(counter.drain().is_empty());
+    }
+
+    #[test]
+    fn restore_drained_views() {
+        // Given
+        let counter = new_view_counter(Duration::from_secs(60));
+        counter.record("5b2863365c31b411b041995e", "visitor-1");
+
+        // When
+        let counts = counter.drain();
+        counter.restore(counts);
+
+        // Then
+        assert_eq!(vec![1], counter.drain().values().cloned().collect::<Vec<u64>>());
+        assert!(counter.drain().is_empty());
+    }
+
+    #[tokio::test]
+    async fn flush_restores_only_unapplied_views() {
+        // Given
+        let counter = new_view_counter(Duration::from_secs(60));
+        counter.record("5b2863365c31b411b041995e", "visitor-1");
+        counter.record("5b2863365c31b411b041995f", "visitor-1");
+        let repository = FailingViewRepository { applied: Mutex::new(vec![]) };
+
+        // When
+        let result = counter.flush(&repository).await;
+
+        // Then
+        assert!(result.is_err());
+        let pending = counter.drain();
+        assert_eq!(1, pending.len());
+        assert!(pending.keys().all(|(post_id, _)| !repository.applied.lock().unwrap().contains(post_id)));
+    }
+
+    #[test]
+    fn fingerprint_is_stable() {
+        // Given
+
+        // When
+        let a = fingerprint("127.0.0.1", "curl/7.79.1");
+        let b = fingerprint("127.0.0.1", "curl/7.79.1");
+
+        // Then
+        assert_eq!(a, b);
+        assert_ne!(a, fingerprint("127.0.0.2", "curl/7.79.1"));
+    }
+}
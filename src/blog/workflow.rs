@@ -0,0 +1,144 @@
+use std::error::Error;
+use std::fmt;
+
+use myblog_proto_rust::myblog::proto::blog::PostStatus;
+
+use crate::auth::Claims;
+
+/// An error returned when the post cannot be moved to the requested status.
+#[derive(Debug, PartialEq)]
+pub enum WorkflowError {
+    /// The transition is not part of the editorial pipeline.
+    InvalidTransition(PostStatus, PostStatus),
+    /// The transition requires a permission which the token subject does not have.
+    MissingPermission(&'static str),
+    /// Only the assigned reviewers are allowed to approve or reject the post.
+    NotAssignedReviewer,
+}
+
+impl fmt::Display for WorkflowError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WorkflowError::InvalidTransition(from, to) => {
+                write!(f, "Cannot move post from {:?} to {:?}", from, to)
+            }
+            WorkflowError::MissingPermission(permission) => {
+                write!(f, "Missing required '{}' permission", permission)
+            }
+            WorkflowError::NotAssignedReviewer => write!(f, "Not an assigned reviewer of the post"),
+        }
+    }
+}
+
+impl Error for WorkflowError {}
+
+/// Return a permission which is required to move the post from one status to another,
+/// or `None` if the transition is not part of the editorial pipeline.
+///
+/// Draft → In Review → Approved → Published (or Scheduled → Published)
+pub fn required_permission(from: PostStatus, to: PostStatus) -> Option<&'static str> {
+    match (from, to) {
+        (PostStatus::Draft, PostStatus::InReview) => Some("write:post"),
+        (PostStatus::InReview, PostStatus::Draft) => Some("write:post"),
+        (PostStatus::InReview, PostStatus::Approved) => Some("review:post"),
+        (PostStatus::Approved, PostStatus::InReview) => Some("review:post"),
+        (PostStatus::Approved, PostStatus::Published) => Some("publish:post"),
+        (PostStatus::Approved, PostStatus::Scheduled) => Some("publish:post"),
+        (PostStatus::Scheduled, PostStatus::Approved) => Some("publish:post"),
+        (PostStatus::Scheduled, PostStatus::Published) => Some("publish:post"),
+        (PostStatus::Published, PostStatus::Draft) => Some("publish:post"),
+        _ => None,
+    }
+}
+
+/// Check whether the token subject is allowed to move the post from one status to another.
+pub fn check_transition(
+    claims: &Claims,
+    reviewers: &[String],
+    from: PostStatus,
+    to: PostStatus,
+) -> Result<(), WorkflowError> {
+    let permission = match required_permission(from, to) {
+        Some(permission) => Ok(permission),
+        _ => Err(WorkflowError::InvalidTransition(from, to)),
+    }?;
+
+    if !claims.has_permission(permission) {
+        return Err(WorkflowError::MissingPermission(permission));
+    }
+
+    // Review decisions are made by the assigned reviewers only
+    if permission == "review:post" && !reviewers.iter().any(|r| r == &claims.sub) {
+        return Err(WorkflowError::NotAssignedReviewer);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use myblog_proto_rust::myblog::proto::blog::PostStatus;
+
+    use crate::auth::Claims;
+    use crate::blog::workflow::{check_transition, required_permission, WorkflowError};
+
+    fn claims(sub: &str, permissions: Vec<&str>) -> Claims {
+        Claims {
+            sub: String::from(sub),
+            permissions: permissions.into_iter().map(String::from).collect(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn required_permission_of_editorial_pipeline() {
+        // Given
+
+        // When
+
+        // Then
+        assert_eq!(Some("write:post"), required_permission(PostStatus::Draft, PostStatus::InReview));
+        assert_eq!(Some("review:post"), required_permission(PostStatus::InReview, PostStatus::Approved));
+        assert_eq!(Some("publish:post"), required_permission(PostStatus::Approved, PostStatus::Published));
+        assert_eq!(Some("publish:post"), required_permission(PostStatus::Approved, PostStatus::Scheduled));
+        assert_eq!(None, required_permission(PostStatus::Draft, PostStatus::Published));
+    }
+
+    #[test]
+    fn check_transition_with_missing_permission() {
+        // Given
+        let claims = claims("github|1", vec!["write:post"]);
+
+        // When
+        let result = check_transition(&claims, &[], PostStatus::Approved, PostStatus::Published);
+
+        // Then
+        assert_eq!(Err(WorkflowError::MissingPermission("publish:post")), result);
+    }
+
+    #[test]
+    fn check_transition_by_unassigned_reviewer() {
+        // Given
+        let claims = claims("github|1", vec!["review:post"]);
+        let reviewers = vec![String::from("github|2")];
+
+        // When
+        let result = check_transition(&claims, &reviewers, PostStatus::InReview, PostStatus::Approved);
+
+        // Then
+        assert_eq!(Err(WorkflowError::NotAssignedReviewer), result);
+    }
+
+    #[test]
+    fn check_transition_by_assigned_reviewer() {
+        // Given
+        let claims = claims("github|2", vec!["review:post"]);
+        let reviewers = vec![String::from("github|2")];
+
+        // When
+        let result = check_transition(&claims, &reviewers, PostStatus::InReview, PostStatus::Approved);
+
+        // Then
+        assert_eq!(Ok(()), result);
+    }
+}
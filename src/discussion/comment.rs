@@ -1,17 +1,18 @@
-use std::str::FromStr;
-use std::time::SystemTime;
-
-use mongodb::{bson::DateTime, bson::doc, bson::Document, bson::oid::ObjectId, Collection};
+use mongodb::{bson::doc, bson::Document, bson::oid::ObjectId, Collection};
 use myblog_proto_rust::myblog::proto::auth::User;
-use myblog_proto_rust::myblog::proto::discussion::Comment;
+use myblog_proto_rust::myblog::proto::discussion::{Comment, ReactionCount};
 use prost_types::Timestamp;
 
+use crate::discussion::reaction::unmarshal_reaction_counts;
 use crate::encoding::bson::{Marshaler, Unmarshaler};
 
 // A comment repository definition.
 #[tonic::async_trait]
 pub trait CommentRepository: Send + Sync + 'static {
     async fn create(&self, c: &mut Comment) -> Result<(), Box<dyn std::error::Error>>;
+    async fn reassign_author(&self, from: &str, to: &str) -> Result<(), Box<dyn std::error::Error>>;
+    /// Hide or reveal all comments of the author, the hidden comments must be left out of every listing.
+    async fn set_hidden_by_author(&self, author: &str, hidden: bool) -> Result<(), Box<dyn std::error::Error>>;
 }
 
 /// An implementation of the CommentRepository specifies with MongoDB.
@@ -36,64 +37,87 @@ impl CommentRepository for MongoCommentRepository {
 
         Ok(())
     }
-}
 
-impl Marshaler for Comment {
-    fn marshal_bson(&self) -> Result<Document, mongodb::bson::oid::Error> {
-        let mut document = doc! {
-            "_id": ObjectId::from_str(self.id.as_str())?,
-            "status": self.status,
-            "text": self.text.as_str(),
-            "author": self.author.as_ref().unwrap().id.as_str(),
-            "children": self.children
-                .iter()
-                .map(|c| ObjectId::from_str(c.id.as_str()))
-                .collect::<Result<Vec<ObjectId>, _>>()?,
-            "createdAt": DateTime::from_millis(self.created_at.as_ref().unwrap().seconds * 1000),
+    async fn reassign_author(&self, from: &str, to: &str) -> Result<(), Box<dyn std::error::Error>> {
+        self.collection
+            .update_many(doc! {"author": from}, doc! {"$set": {"author": to}}, None)
+            .await?;
+
+        Ok(())
+    }
+
+    async fn set_hidden_by_author(&self, author: &str, hidden: bool) -> Result<(), Box<dyn std::error::Error>> {
+        let update = if hidden {
+            doc! {"$set": {"hidden": true}}
+        } else {
+            doc! {"$unset": {"hidden": ""}}
         };
 
-        if self.parent.is_some() {
-            document.insert(
-                "parent",
-                ObjectId::from_str(self.parent.as_ref().unwrap().id.as_str())?,
-            );
-        }
-        if self.updated_at.is_some() {
-            document.insert(
-                "updatedAt",
-                DateTime::from_millis(self.updated_at.as_ref().unwrap().seconds * 1000),
-            );
-        }
+        self.collection.update_many(doc! {"author": author}, update, None).await?;
 
-        Ok(document)
+        Ok(())
     }
 }
 
-impl Unmarshaler for Comment {
-    fn unmarshal_bson(
-        document: &Document,
-    ) -> Result<Self, mongodb::bson::document::ValueAccessError>
-        where
-            Self: Sized,
-    {
-        Ok(Comment {
-            id: document.get_object_id("_id")?.to_hex(),
-            status: document.get_i32("status")?.to_owned(),
-            text: document.get_str("text")?.to_owned(),
-            author: Some(
-                document
-                    .get_document("author")
-                    .and_then(|author| User::unmarshal_bson(author))?,
-            ),
-            parent: None,
-            children: vec![],
-            created_at: Some(document.get_datetime("createdAt").and_then(|created_at| {
-                Ok(Timestamp::from(SystemTime::from(created_at.to_owned())))
-            })?),
-            updated_at: match document.get_datetime("updatedAt") {
-                Ok(updated_at) => Some(Timestamp::from(SystemTime::from(updated_at.to_owned()))),
-                _ => None,
-            },
-        })
+/// A schema of the stored comment, the author, parent and children are only stored as their IDs.
+#[derive(Marshaler, Unmarshaler)]
+#[bson(remote = "Comment")]
+struct CommentSchema {
+    #[bson(rename = "_id", object_id)]
+    id: String,
+    status: i32,
+    text: String,
+    #[bson(reference)]
+    author: Option<User>,
+    #[bson(reference, object_id, optional)]
+    parent: Option<Box<Comment>>,
+    #[bson(reference, object_id, optional)]
+    children: Vec<Comment>,
+    #[bson(read_with = "unmarshal_reaction_counts")]
+    reactions: Vec<ReactionCount>,
+    #[bson(timestamp)]
+    created_at: Option<Timestamp>,
+    #[bson(timestamp, optional)]
+    updated_at: Option<Timestamp>,
+}
+
+#[cfg(test)]
+mod tests {
+    use mongodb::bson::{DateTime, doc, oid::ObjectId};
+    use myblog_proto_rust::myblog::proto::discussion::Comment;
+    use proptest::prelude::*;
+
+    use crate::encoding::bson::{arbitrary, Marshaler, Unmarshaler};
+
+    #[test]
+    fn unmarshal_author_id() {
+        // Given
+        let document = doc! {
+            "_id": ObjectId::new(),
+            "status": 0,
+            "text": "Hello",
+            "author": "github|1",
+            "createdAt": DateTime::now(),
+        };
+
+        // When
+        let result = Comment::unmarshal_bson(&document).unwrap();
+
+        // Then
+        assert_eq!("github|1", result.author.unwrap().id);
+        assert!(result.children.is_empty());
+    }
+
+    proptest! {
+        #[test]
+        fn round_trip_comment(comment in arbitrary::comment()) {
+            // Given
+
+            // When
+            let result = Comment::unmarshal_bson(&comment.marshal_bson().unwrap()).unwrap();
+
+            // Then
+            prop_assert_eq!(comment, result);
+        }
     }
 }
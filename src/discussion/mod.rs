@@ -1,2 +1,3 @@
 pub mod comment;
+pub mod reaction;
 pub mod service;
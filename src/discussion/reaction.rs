@@ -0,0 +1,285 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::str::FromStr;
+
+use mongodb::{bson::DateTime, bson::doc, bson::Document, bson::oid::ObjectId, Collection, IndexModel};
+use mongodb::options::{IndexOptions, UpdateOptions};
+use myblog_proto_rust::myblog::proto::{blog::PostStatus, discussion::ReactionCount};
+use tokio_stream::StreamExt;
+
+/// A kind of resource which can be reacted on.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ReactionTarget {
+    Post,
+    Comment,
+}
+
+impl ReactionTarget {
+    fn as_str(&self) -> &'static str {
+        match self {
+            ReactionTarget::Post => "post",
+            ReactionTarget::Comment => "comment",
+        }
+    }
+}
+
+/// An error returned when the post or comment to react on does not exist or is not visible to the readers.
+#[derive(Debug)]
+pub struct TargetNotFoundError {
+    pub target: ReactionTarget,
+    pub id: String,
+}
+
+impl fmt::Display for TargetNotFoundError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?} '{}' not found", self.target, self.id)
+    }
+}
+
+impl Error for TargetNotFoundError {}
+
+/// A reaction repository definition.
+#[tonic::async_trait]
+pub trait ReactionRepository: Send + Sync + 'static {
+    /// Add the reaction of the user to the target, or remove it if it already exists.
+    /// Return true if the reaction has been added.
+    async fn toggle(&self, target: ReactionTarget, id: &str, reaction: &str, user: &str) -> Result<bool, Box<dyn std::error::Error>>;
+    /// Return the reactions of the user, keyed by the target ID.
+    async fn find_user_reactions(&self, target: ReactionTarget, ids: &[&str], user: &str) -> Result<HashMap<String, Vec<String>>, Box<dyn std::error::Error>>;
+    /// Remove all reactions of the user and take them off the counts of their targets.
+    async fn delete_by_user(&self, user: &str) -> Result<(), Box<dyn std::error::Error>>;
+}
+
+/// An implementation of the ReactionRepository specifies with MongoDB.
+///
+/// Every reaction is stored as its own document, while the per-reaction counts are denormalized
+/// into the `reactionCounts` field of the post or comment so that listings can read them for free.
+pub struct MongoReactionRepository {
+    collection: Collection<Document>,
+    posts: Collection<Document>,
+    comments: Collection<Document>,
+}
+
+impl MongoReactionRepository {
+    pub fn new(
+        collection: Collection<Document>,
+        posts: Collection<Document>,
+        comments: Collection<Document>,
+    ) -> Self {
+        MongoReactionRepository { collection, posts, comments }
+    }
+
+    /// Create the unique index which allows each user only one of each reaction on the same target.
+    pub async fn create_indexes(&self) -> Result<(), Box<dyn std::error::Error>> {
+        let index = IndexModel::builder()
+            .keys(doc! {"targetType": 1, "target": 1, "reaction": 1, "user": 1})
+            .options(IndexOptions::builder().unique(true).build())
+            .build();
+
+        self.collection.create_index(index, None).await?;
+
+        Ok(())
+    }
+
+    fn target_collection(&self, target: ReactionTarget) -> &Collection<Document> {
+        match target {
+            ReactionTarget::Post => &self.posts,
+            ReactionTarget::Comment => &self.comments,
+        }
+    }
+
+    /// Return a filter that matches the target only if the readers can see it, i.e. a published post
+    /// or a comment which has not been hidden.
+    fn visible_filter(&self, target: ReactionTarget, id: ObjectId) -> Document {
+        match target {
+            ReactionTarget::Post => doc! {"_id": id, "status": PostStatus::Published as i32},
+            ReactionTarget::Comment => doc! {"_id": id, "hidden": {"$ne": true}},
+        }
+    }
+
+    async fn increment(
+        &self,
+        target: ReactionTarget,
+        id: ObjectId,
+        reaction: &str,
+        delta: i32,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let mut counts = Document::new();
+        counts.insert(format!("reactionCounts.{}", reaction), delta);
+
+        self.target_collection(target)
+            .update_one(doc! {"_id": id}, doc! {"$inc": counts}, None)
+            .await?;
+
+        Ok(())
+    }
+}
+
+#[tonic::async_trait]
+impl ReactionRepository for MongoReactionRepository {
+    async fn toggle(
+        &self,
+        target: ReactionTarget,
+        id: &str,
+        reaction: &str,
+        user: &str,
+    ) -> Result<bool, Box<dyn std::error::Error>> {
+        let object_id = ObjectId::from_str(id)?;
+        let visible = self.visible_filter(target, object_id);
+        if self.target_collection(target).count_documents(visible, None).await? == 0 {
+            return Err(Box::new(TargetNotFoundError { target, id: id.to_owned() }));
+        }
+
+        let filter = doc! {
+            "targetType": target.as_str(),
+            "target": object_id,
+            "reaction": reaction,
+            "user": user,
+        };
+
+        // The counts only change along with the reaction documents, so concurrent toggles of the same
+        // reaction cannot count it twice, the upsert on the unique index inserts at most one of them
+        if self.collection.delete_one(filter.clone(), None).await?.deleted_count > 0 {
+            self.increment(target, object_id, reaction, -1).await?;
+
+            return Ok(false);
+        }
+
+        let result = self.collection
+            .update_one(
+                filter,
+                doc! {"$setOnInsert": {"createdAt": DateTime::now()}},
+                UpdateOptions::builder().upsert(true).build(),
+            )
+            .await?;
+        if result.upserted_id.is_some() {
+            self.increment(target, object_id, reaction, 1).await?;
+        }
+
+        Ok(true)
+    }
+
+    async fn find_user_reactions(
+        &self,
+        target: ReactionTarget,
+        ids: &[&str],
+        user: &str,
+    ) -> Result<HashMap<String, Vec<String>>, Box<dyn std::error::Error>> {
+        let object_ids = ids
+            .iter()
+            .map(|id| ObjectId::from_str(id))
+            .collect::<Result<Vec<ObjectId>, _>>()?;
+        let filter = doc! {
+            "targetType": target.as_str(),
+            "target": {"$in": object_ids},
+            "user": user,
+        };
+
+        let mut cursor = self.collection.find(filter, None).await?;
+        let mut result: HashMap<String, Vec<String>> = HashMap::new();
+
+        while let Some(document) = cursor.try_next().await? {
+            result
+                .entry(document.get_object_id("target")?.to_hex())
+                .or_insert_with(Vec::new)
+                .push(document.get_str("reaction")?.to_owned());
+        }
+
+        Ok(result)
+    }
+
+    async fn delete_by_user(&self, user: &str) -> Result<(), Box<dyn std::error::Error>> {
+        let mut cursor = self.collection.find(doc! {"user": user}, None).await?;
+
+        while let Some(document) = cursor.try_next().await? {
+            let target = match document.get_str("targetType")? {
+                "comment" => ReactionTarget::Comment,
+                _ => ReactionTarget::Post,
+            };
+
+            // Each reaction is taken off the counts only by whoever deletes it, same as in the toggle
+            if self.collection.delete_one(doc! {"_id": document.get_object_id("_id")?}, None).await?.deleted_count > 0 {
+                self.increment(target, document.get_object_id("target")?, document.get_str("reaction")?, -1).await?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Read the denormalized reaction counts of the post or comment, sorted by the reaction name.
+pub fn unmarshal_reaction_counts(document: &Document) -> Vec<ReactionCount> {
+    let mut result: Vec<ReactionCount> = match document.get_document("reactionCounts") {
+        Ok(counts) => counts
+            .iter()
+            .filter_map(|(reaction, count)| {
+                count.as_i64().or_else(|| count.as_i32().map(i64::from)).map(|count| (reaction, count))
+            })
+            .filter(|(_, count)| *count > 0)
+            .map(|(reaction, count)| ReactionCount {
+                reaction: reaction.to_owned(),
+                count,
+                reacted: false,
+            })
+            .collect(),
+        _ => vec![],
+    };
+
+    result.sort_by(|a, b| a.reaction.cmp(&b.reaction));
+    result
+}
+
+/// Mark the reactions which the caller has made.
+pub fn mark_reacted(counts: &mut Vec<ReactionCount>, reactions: &[String]) {
+    for count in counts.iter_mut() {
+        count.reacted = reactions.contains(&count.reaction);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use mongodb::bson::doc;
+
+    use crate::discussion::reaction::{mark_reacted, unmarshal_reaction_counts};
+
+    #[test]
+    fn unmarshal_reaction_counts_skips_zero_counts() {
+        // Given
+        let document = doc! {"reactionCounts": {"wow": 2, "like": 5_i64, "sad": 0}};
+
+        // When
+        let result = unmarshal_reaction_counts(&document);
+
+        // Then
+        assert_eq!(
+            vec![("like", 5), ("wow", 2)],
+            result.iter().map(|c| (c.reaction.as_str(), c.count)).collect::<Vec<(&str, i64)>>(),
+        );
+    }
+
+    #[test]
+    fn unmarshal_reaction_counts_without_reactions() {
+        // Given
+        let document = doc! {};
+
+        // When
+        let result = unmarshal_reaction_counts(&document);
+
+        // Then
+        assert!(result.is_empty());
+    }
+
+    #[test]
+    fn mark_reacted_reactions() {
+        // Given
+        let mut counts = unmarshal_reaction_counts(&doc! {"reactionCounts": {"like": 1, "wow": 1}});
+
+        // When
+        mark_reacted(&mut counts, &[String::from("wow")]);
+
+        // Then
+        assert!(!counts[0].reacted);
+        assert!(counts[1].reacted);
+    }
+}
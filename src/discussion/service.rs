@@ -1,17 +1,27 @@
+use std::time::SystemTime;
+
 use myblog_proto_rust::myblog::proto::{
     auth::User,
     discussion::{
         CreateCommentRequest, CreateCommentResponse,
         discussion_service_server::DiscussionService,
+        ListReactionsResponse,
+        toggle_reaction_request::Target,
+        ToggleReactionRequest, ToggleReactionResponse,
     },
 };
+use prost_types::Timestamp;
 use tonic::{Request, Response, Status};
 
 use crate::auth::Claims;
 use crate::discussion::comment::CommentRepository;
+use crate::discussion::reaction::{ReactionRepository, ReactionTarget, TargetNotFoundError};
 
 pub struct MyDiscussionService {
     comment_repository: Box<dyn CommentRepository>,
+    reaction_repository: Box<dyn ReactionRepository>,
+
+    reactions: Vec<String>,
 }
 
 impl MyDiscussionService {
@@ -37,18 +47,57 @@ impl DiscussionService for MyDiscussionService {
         let mut user = User::default();
         user.id = sub;
         comment.author = Some(user);
+        comment.created_at = Some(Timestamp::from(SystemTime::now()));
 
         match self.comment_repository.create(&mut comment).await {
             Ok(_) => Ok(Response::new(CreateCommentResponse { comment: Some(comment) })),
             Err(e) => Err(Status::internal(e.to_string())),
         }
     }
+
+    async fn list_reactions(
+        &self,
+        _: Request<()>,
+    ) -> Result<Response<ListReactionsResponse>, Status> {
+        Ok(Response::new(ListReactionsResponse { reactions: self.reactions.clone() }))
+    }
+
+    async fn toggle_reaction(
+        &self,
+        request: Request<ToggleReactionRequest>,
+    ) -> Result<Response<ToggleReactionResponse>, Status> {
+        // Anonymous callers can see the reaction counts but cannot react
+        let sub = match request.extensions().get::<Claims>() {
+            Some(claims) => Ok(claims.sub.clone()),
+            _ => Err(Status::unauthenticated("Forbidden")),
+        }?;
+        let r = request.into_inner();
+
+        if !self.reactions.contains(&r.reaction) {
+            return Err(Status::invalid_argument(format!("Unsupported reaction '{}'", r.reaction)));
+        }
+        let (target, id) = match r.target {
+            Some(Target::PostId(id)) => Ok((ReactionTarget::Post, id)),
+            Some(Target::CommentId(id)) => Ok((ReactionTarget::Comment, id)),
+            _ => Err(Status::invalid_argument("Missing required 'target' field")),
+        }?;
+
+        match self.reaction_repository.toggle(target, id.as_str(), r.reaction.as_str(), sub.as_str()).await {
+            Ok(reacted) => Ok(Response::new(ToggleReactionResponse { reacted })),
+            Err(e) if e.is::<TargetNotFoundError>() => Err(Status::not_found(e.to_string())),
+            Err(e) => Err(Status::internal(e.to_string())),
+        }
+    }
 }
 
 #[derive(Default)]
 pub struct MyDiscussionServiceBuilder {
     /* Repository */
     comment_repository: Option<Box<dyn CommentRepository>>,
+    reaction_repository: Option<Box<dyn ReactionRepository>>,
+
+    /* Options */
+    reactions: Vec<String>,
 }
 
 impl MyDiscussionServiceBuilder {
@@ -57,9 +106,21 @@ impl MyDiscussionServiceBuilder {
         self
     }
 
+    pub fn with_reaction_repository(mut self, repository: Box<dyn ReactionRepository>) -> Self {
+        self.reaction_repository = Some(repository);
+        self
+    }
+
+    pub fn with_reactions(mut self, reactions: Vec<String>) -> Self {
+        self.reactions = reactions;
+        self
+    }
+
     pub fn build(self) -> MyDiscussionService {
         MyDiscussionService {
             comment_repository: self.comment_repository.unwrap(),
+            reaction_repository: self.reaction_repository.unwrap(),
+            reactions: self.reactions,
         }
     }
 }
\ No newline at end of file
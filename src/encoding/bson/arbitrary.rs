@@ -0,0 +1,189 @@
+//! Strategies of the messages for the property-based round-trip tests.
+
+use mongodb::bson::oid::ObjectId;
+use myblog_proto_rust::myblog::proto::{
+    auth::User,
+    blog::{Post, Taxonomy},
+    discussion::Comment,
+    storage::{File, ImageDerivative},
+};
+use proptest::collection::{hash_map, vec};
+use proptest::option;
+use proptest::prelude::*;
+use prost_types::Timestamp;
+
+/// A timestamp within the years 1 to 9999 at the millisecond precision which BSON keeps.
+pub fn timestamp() -> impl Strategy<Value = Timestamp> {
+    (-62_135_596_800i64..253_402_300_800, 0..1000i32)
+        .prop_map(|(seconds, millis)| Timestamp { seconds, nanos: millis * 1_000_000 })
+}
+
+pub fn object_id() -> impl Strategy<Value = String> {
+    any::<[u8; 12]>().prop_map(|bytes| ObjectId::from_bytes(bytes).to_hex())
+}
+
+pub fn text() -> impl Strategy<Value = String> {
+    "\\PC{0,20}"
+}
+
+/// An ID of the user as it is issued by the authentication server, e.g. `github|1`.
+pub fn user_id() -> impl Strategy<Value = String> {
+    "[a-z]{1,8}\\|[0-9]{1,10}"
+}
+
+/// A user as it is stored, the roles and ban are managed by their own updates and never marshaled.
+pub fn user() -> impl Strategy<Value = User> {
+    let profile = (user_id(), text(), text(), text(), any::<bool>(), text());
+    let timestamps = (timestamp(), option::of(timestamp()), option::of(timestamp()));
+
+    (profile, hash_map("[a-z]{1,8}", text(), 0..3), timestamps)
+        .prop_map(|(profile, social_links, timestamps)| {
+            let (id, display_name, profile_picture, email, email_verified, bio) = profile;
+            let (created_at, updated_at, last_login_at) = timestamps;
+
+            User {
+                id,
+                display_name,
+                profile_picture,
+                email,
+                email_verified,
+                bio,
+                social_links,
+                created_at: Some(created_at),
+                updated_at,
+                last_login_at,
+                ..Default::default()
+            }
+        })
+}
+
+pub fn taxonomy() -> impl Strategy<Value = Taxonomy> {
+    (object_id(), text(), text(), 0..2i32).prop_map(|(id, name, slug, r#type)| Taxonomy { id, name, slug, r#type })
+}
+
+fn derivative() -> impl Strategy<Value = ImageDerivative> {
+    (text(), text(), text(), text(), 1..10_000u32, 1..10_000u32).prop_map(
+        |(slug, uploaded_file_path, url, mime_type, width, height)| ImageDerivative {
+            slug,
+            uploaded_file_path,
+            url,
+            mime_type,
+            width,
+            height,
+        },
+    )
+}
+
+/// A file which only has the dimensions, a placeholder and the derivatives if it is an image.
+pub fn file() -> impl Strategy<Value = File> {
+    let names = (object_id(), text(), text(), text(), text());
+    let location = (text(), text(), text());
+    let image = option::of((1..10_000u32, 1..10_000u32, text(), vec(derivative(), 0..3)));
+    let metadata = (text(), 0..i64::MAX as u64, user_id());
+    let timestamps = (timestamp(), option::of(timestamp()));
+
+    (names, location, any::<bool>(), image, metadata, timestamps)
+        .prop_map(|(names, location, private, image, metadata, timestamps)| {
+            let (id, file_name, slug, uploaded_file_path, mime_type) = names;
+            let (provider, region, bucket) = location;
+            let (width, height, blurhash, derivatives) = image.unwrap_or_default();
+            let (sha256, size, uploaded_by) = metadata;
+            let (uploaded_at, modified_at) = timestamps;
+
+            File {
+                id,
+                file_name,
+                slug,
+                uploaded_file_path,
+                mime_type,
+                provider,
+                region,
+                bucket,
+                private,
+                width,
+                height,
+                blurhash,
+                derivatives,
+                sha256,
+                size,
+                uploaded_by,
+                uploaded_at: Some(uploaded_at),
+                modified_at,
+                ..Default::default()
+            }
+        })
+}
+
+/// A post whose references only carry their IDs, since only the IDs are stored.
+pub fn post() -> impl Strategy<Value = Post> {
+    let reference = |id| User { id, ..Default::default() };
+    let content = (text(), text(), text(), 0..i32::MAX as u32, 0..i32::MAX as u32);
+    let references = (
+        user_id().prop_map(reference),
+        vec(user_id().prop_map(reference), 0..3),
+        vec(user_id().prop_map(reference), 0..3),
+        vec(object_id().prop_map(|id| Taxonomy { id, ..Default::default() }), 0..3),
+        vec(object_id().prop_map(|id| Taxonomy { id, ..Default::default() }), 0..3),
+        option::of(object_id().prop_map(|id| File { id, ..Default::default() })),
+    );
+    let timestamps = (option::of(timestamp()), timestamp(), option::of(timestamp()));
+
+    (object_id(), text(), text(), any::<i32>(), content, references, timestamps)
+        .prop_map(|(id, title, slug, status, content, references, timestamps)| {
+            let (markdown, html, excerpt, word_count, reading_time) = content;
+            let (author, co_authors, reviewers, categories, tags, featured_image) = references;
+            let (published_at, created_at, updated_at) = timestamps;
+
+            Post {
+                id,
+                title,
+                slug,
+                status,
+                markdown,
+                html,
+                excerpt,
+                word_count,
+                reading_time,
+                published_at,
+                author: Some(author),
+                co_authors,
+                reviewers,
+                categories,
+                tags,
+                featured_image,
+                created_at: Some(created_at),
+                updated_at,
+                ..Default::default()
+            }
+        })
+}
+
+/// A comment whose author, parent and children only carry their IDs, and which has no reactions since they are
+/// counted by their own updates.
+pub fn comment() -> impl Strategy<Value = Comment> {
+    let reference = |id| Comment { id, ..Default::default() };
+    let references = (
+        user_id().prop_map(|id| User { id, ..Default::default() }),
+        option::of(object_id().prop_map(reference).prop_map(Box::new)),
+        vec(object_id().prop_map(reference), 0..3),
+    );
+    let timestamps = (timestamp(), option::of(timestamp()));
+
+    (object_id(), any::<i32>(), text(), references, timestamps)
+        .prop_map(|(id, status, text, references, timestamps)| {
+            let (author, parent, children) = references;
+            let (created_at, updated_at) = timestamps;
+
+            Comment {
+                id,
+                status,
+                text,
+                author: Some(author),
+                parent,
+                children,
+                created_at: Some(created_at),
+                updated_at,
+                ..Default::default()
+            }
+        })
+}
@@ -1,15 +1,253 @@
-use mongodb::bson::Document;
+use std::error::Error;
+use std::fmt;
+
+use mongodb::bson::{Bson, DateTime, Document};
+use mongodb::bson::document::ValueAccessError;
+use prost_types::Timestamp;
+
+pub use myblog_api_derive::{Marshaler, Unmarshaler};
+
+#[cfg(test)]
+pub mod arbitrary;
+pub mod value;
+
+/// An error returned when the message cannot be marshaled into a document.
+#[derive(Debug)]
+pub enum MarshalError {
+    /// The ID is not a valid ObjectId.
+    InvalidObjectId(mongodb::bson::oid::Error),
+    /// The field is required by the document but unset on the message.
+    MissingField(&'static str),
+}
+
+impl fmt::Display for MarshalError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MarshalError::InvalidObjectId(e) => write!(f, "Invalid ObjectId: {}", e),
+            MarshalError::MissingField(field) => write!(f, "Missing required '{}' field", field),
+        }
+    }
+}
+
+impl Error for MarshalError {}
+
+impl From<mongodb::bson::oid::Error> for MarshalError {
+    fn from(e: mongodb::bson::oid::Error) -> Self {
+        MarshalError::InvalidObjectId(e)
+    }
+}
+
+/// An error returned when the document cannot be un-marshaled into a message.
+#[derive(Debug, PartialEq)]
+pub struct DecodeError {
+    /// The name of the message, e.g. `Post`.
+    pub type_name: &'static str,
+    /// The `_id` of the document if it has one.
+    pub id: Option<String>,
+    /// The keys from the document down to the value which failed, e.g. `derivatives.1.width`.
+    pub path: Vec<String>,
+    pub kind: ValueAccessError,
+}
+
+impl DecodeError {
+    /// Prepend the key of the field, or the index of the array element, which contains the failed value.
+    pub fn at(mut self, key: impl ToString) -> Self {
+        self.path.insert(0, key.to_string());
+        self
+    }
+
+    /// Attribute the error to the message of the document, so an error of an embedded message ends up
+    /// attributed to the outermost one.
+    pub fn of(mut self, type_name: &'static str, document: &Document) -> Self {
+        self.type_name = type_name;
+        self.id = document.get("_id").map(|id| match id {
+            Bson::ObjectId(id) => id.to_hex(),
+            Bson::String(id) => id.to_owned(),
+            id => id.to_string(),
+        });
+        self
+    }
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Failed to decode {}", self.type_name)?;
+        if let Some(id) = &self.id {
+            write!(f, " '{}'", id)?;
+        }
+        if !self.path.is_empty() {
+            write!(f, " at '{}'", self.path.join("."))?;
+        }
+        write!(f, ": {}", self.kind)
+    }
+}
+
+impl Error for DecodeError {}
+
+impl From<ValueAccessError> for DecodeError {
+    fn from(kind: ValueAccessError) -> Self {
+        DecodeError { type_name: "", id: None, path: vec![], kind }
+    }
+}
 
 /// Provide a MongoDB specific marshaling function.
 pub trait Marshaler {
-    fn marshal_bson(&self) -> Result<Document, mongodb::bson::oid::Error>;
+    fn marshal_bson(&self) -> Result<Document, MarshalError>;
 }
 
 /// Provide a MongoDB specific un-marshaling function.
 pub trait Unmarshaler {
-    fn unmarshal_bson(
-        document: &Document,
-    ) -> Result<Self, mongodb::bson::document::ValueAccessError>
+    fn unmarshal_bson(document: &Document) -> Result<Self, DecodeError>
         where
             Self: Sized;
 }
+
+/// How a listing treats the documents which cannot be un-marshaled.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum ListingMode {
+    /// Fail the whole listing on the first bad document.
+    #[default]
+    Strict,
+    /// Log and skip the bad documents, so one of them does not take down the whole page.
+    Tolerant,
+}
+
+impl ListingMode {
+    /// Un-marshal the listed document, it is `None` if the document is bad and skipped.
+    pub fn unmarshal<T: Unmarshaler>(self, document: &Document) -> Result<Option<T>, DecodeError> {
+        match T::unmarshal_bson(document) {
+            Ok(message) => Ok(Some(message)),
+            Err(e) if self == ListingMode::Tolerant => {
+                eprintln!("skipped a bad document: {}", e);
+                Ok(None)
+            }
+            Err(e) => Err(e),
+        }
+    }
+}
+
+/// Convert the protobuf timestamp to the BSON date time, which keeps the millisecond precision.
+pub fn to_datetime(t: &Timestamp) -> DateTime {
+    DateTime::from_millis(
+        t.seconds
+            .saturating_mul(1000)
+            .saturating_add((t.nanos as i64).div_euclid(1_000_000)),
+    )
+}
+
+/// Convert the BSON date time to the protobuf timestamp.
+pub fn to_timestamp(dt: &DateTime) -> Timestamp {
+    let millis = dt.timestamp_millis();
+
+    Timestamp {
+        seconds: millis.div_euclid(1000),
+        nanos: (millis.rem_euclid(1000) * 1_000_000) as i32,
+    }
+}
+
+/// Read the required date time of the document as a timestamp.
+pub fn get_timestamp(document: &Document, key: &str) -> Result<Timestamp, ValueAccessError> {
+    document.get_datetime(key).map(to_timestamp)
+}
+
+/// Read the optional date time of the document as a timestamp, it is only `None` if the field is missing,
+/// a value of another type is still an error.
+pub fn get_optional_timestamp(document: &Document, key: &str) -> Result<Option<Timestamp>, ValueAccessError> {
+    match document.get_datetime(key) {
+        Ok(dt) => Ok(Some(to_timestamp(dt))),
+        Err(ValueAccessError::NotPresent) => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+/// Read a referenced document which is either embedded, after a `$lookup`, or only stored as its ID.
+pub fn unmarshal_reference<T: Unmarshaler>(
+    value: &Bson,
+    from_id: impl Fn(String) -> T,
+) -> Result<T, DecodeError> {
+    match value {
+        Bson::Document(document) => T::unmarshal_bson(document),
+        Bson::String(id) => Ok(from_id(id.to_owned())),
+        Bson::ObjectId(id) => Ok(from_id(id.to_hex())),
+        _ => Err(ValueAccessError::UnexpectedType.into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use mongodb::bson::{DateTime, doc, oid::ObjectId};
+    use mongodb::bson::document::ValueAccessError;
+    use myblog_proto_rust::myblog::proto::blog::Taxonomy;
+    use proptest::prelude::*;
+    use prost_types::Timestamp;
+
+    use crate::encoding::bson::{get_optional_timestamp, ListingMode, to_datetime, to_timestamp};
+
+    #[test]
+    fn convert_timestamp_before_epoch() {
+        // Given
+        let t = Timestamp { seconds: -2, nanos: 500_000_000 };
+
+        // When
+        let result = to_datetime(&t);
+
+        // Then
+        assert_eq!(-1500, result.timestamp_millis());
+        assert_eq!(t, to_timestamp(&result));
+    }
+
+    #[test]
+    fn get_missing_and_mistyped_optional_timestamps() {
+        // Given
+        let document = doc! {"updatedAt": "yesterday", "publishedAt": DateTime::from_millis(1500)};
+
+        // When
+
+        // Then
+        assert_eq!(Ok(None), get_optional_timestamp(&document, "deletedAt"));
+        assert_eq!(Err(ValueAccessError::UnexpectedType), get_optional_timestamp(&document, "updatedAt"));
+        assert_eq!(
+            Ok(Some(Timestamp { seconds: 1, nanos: 500_000_000 })),
+            get_optional_timestamp(&document, "publishedAt"),
+        );
+    }
+
+    #[test]
+    fn skip_bad_document_of_tolerant_listing() {
+        // Given
+        let document = doc! {"_id": ObjectId::new(), "name": "Rust", "slug": "rust"};
+
+        // When
+
+        // Then
+        assert_eq!(Ok(None), ListingMode::Tolerant.unmarshal::<Taxonomy>(&document));
+        assert_eq!(vec!["type"], ListingMode::Strict.unmarshal::<Taxonomy>(&document).unwrap_err().path);
+    }
+
+    proptest! {
+        #[test]
+        fn round_trip_datetime(millis in -62_135_596_800_000i64..253_402_300_800_000) {
+            // Given
+            let dt = DateTime::from_millis(millis);
+
+            // When
+            let result = to_datetime(&to_timestamp(&dt));
+
+            // Then
+            prop_assert_eq!(dt, result);
+        }
+
+        #[test]
+        fn truncate_timestamp_to_millis(seconds in -62_135_596_800i64..253_402_300_800, nanos in 0..1_000_000_000i32) {
+            // Given
+            let t = Timestamp { seconds, nanos };
+
+            // When
+            let result = to_timestamp(&to_datetime(&t));
+
+            // Then
+            prop_assert_eq!(seconds, result.seconds);
+            prop_assert_eq!(nanos / 1_000_000 * 1_000_000, result.nanos);
+        }
+    }
+}
@@ -0,0 +1,124 @@
+//! Conversions of the field values which the derived Marshaler and Unmarshaler are generated with.
+
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::str::FromStr;
+
+use mongodb::bson::Bson;
+use mongodb::bson::document::ValueAccessError;
+use mongodb::bson::oid::ObjectId;
+use prost_types::Timestamp;
+
+use crate::encoding::bson::{DecodeError, MarshalError, Marshaler, to_timestamp, Unmarshaler};
+
+/// A field value which is stored as is.
+pub trait BsonValue: Sized {
+    fn to_bson(&self) -> Bson;
+
+    fn from_bson(value: &Bson) -> Result<Self, ValueAccessError>;
+}
+
+impl BsonValue for String {
+    fn to_bson(&self) -> Bson {
+        Bson::String(self.to_owned())
+    }
+
+    fn from_bson(value: &Bson) -> Result<Self, ValueAccessError> {
+        value.as_str().map(str::to_owned).ok_or(ValueAccessError::UnexpectedType)
+    }
+}
+
+impl BsonValue for bool {
+    fn to_bson(&self) -> Bson {
+        Bson::Boolean(*self)
+    }
+
+    fn from_bson(value: &Bson) -> Result<Self, ValueAccessError> {
+        value.as_bool().ok_or(ValueAccessError::UnexpectedType)
+    }
+}
+
+/// Read either an Int32 or an Int64, the `$set` of an update may have stored the other one.
+fn get_integer(value: &Bson) -> Result<i64, ValueAccessError> {
+    match value {
+        Bson::Int32(n) => Ok(*n as i64),
+        Bson::Int64(n) => Ok(*n),
+        _ => Err(ValueAccessError::UnexpectedType),
+    }
+}
+
+impl BsonValue for i32 {
+    fn to_bson(&self) -> Bson {
+        Bson::Int32(*self)
+    }
+
+    fn from_bson(value: &Bson) -> Result<Self, ValueAccessError> {
+        i32::try_from(get_integer(value)?).map_err(|_| ValueAccessError::UnexpectedType)
+    }
+}
+
+impl BsonValue for u32 {
+    fn to_bson(&self) -> Bson {
+        Bson::Int64(*self as i64)
+    }
+
+    fn from_bson(value: &Bson) -> Result<Self, ValueAccessError> {
+        u32::try_from(get_integer(value)?).map_err(|_| ValueAccessError::UnexpectedType)
+    }
+}
+
+impl BsonValue for i64 {
+    fn to_bson(&self) -> Bson {
+        Bson::Int64(*self)
+    }
+
+    fn from_bson(value: &Bson) -> Result<Self, ValueAccessError> {
+        get_integer(value)
+    }
+}
+
+impl BsonValue for u64 {
+    fn to_bson(&self) -> Bson {
+        Bson::Int64(*self as i64)
+    }
+
+    fn from_bson(value: &Bson) -> Result<Self, ValueAccessError> {
+        u64::try_from(get_integer(value)?).map_err(|_| ValueAccessError::UnexpectedType)
+    }
+}
+
+impl BsonValue for HashMap<String, String> {
+    fn to_bson(&self) -> Bson {
+        Bson::Document(self.iter().map(|(key, value)| (key.to_owned(), Bson::String(value.to_owned()))).collect())
+    }
+
+    fn from_bson(value: &Bson) -> Result<Self, ValueAccessError> {
+        value
+            .as_document()
+            .ok_or(ValueAccessError::UnexpectedType)?
+            .iter()
+            .map(|(key, value)| Ok((key.to_owned(), String::from_bson(value)?)))
+            .collect()
+    }
+}
+
+pub fn object_id_to_bson(id: &str) -> Result<Bson, MarshalError> {
+    Ok(Bson::ObjectId(ObjectId::from_str(id)?))
+}
+
+pub fn object_id_from_bson(value: &Bson) -> Result<String, ValueAccessError> {
+    value.as_object_id().map(|id| id.to_hex()).ok_or(ValueAccessError::UnexpectedType)
+}
+
+pub fn timestamp_from_bson(value: &Bson) -> Result<Timestamp, ValueAccessError> {
+    value.as_datetime().map(to_timestamp).ok_or(ValueAccessError::UnexpectedType)
+}
+
+pub fn embedded_to_bson<T: Marshaler>(message: &T) -> Result<Bson, MarshalError> {
+    message.marshal_bson().map(Bson::Document)
+}
+
+pub fn embedded_from_bson<T: Unmarshaler>(value: &Bson) -> Result<T, DecodeError> {
+    T::unmarshal_bson(value.as_document().ok_or(ValueAccessError::UnexpectedType)?)
+}
+
@@ -0,0 +1,154 @@
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::path::{Component, Path};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use bytes::Bytes;
+use mongodb::bson::DateTime;
+use sha2::{Digest, Sha256};
+use tokio::io::{AsyncRead, ReadBuf};
+use tokio_stream::Stream;
+
+/// A stream of the object content.
+pub type ByteStream = Pin<Box<dyn Stream<Item = io::Result<Bytes>> + Send>>;
+
+/// An error returned when the object path would escape the storage root.
+#[derive(Debug)]
+pub struct InvalidPathError {
+    pub path: String,
+}
+
+impl fmt::Display for InvalidPathError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Invalid object path '{}'", self.path)
+    }
+}
+
+impl Error for InvalidPathError {}
+
+/// An object which is kept in the storage backend.
+#[derive(Clone, Debug, PartialEq)]
+pub struct StoredObject {
+    pub path: String,
+    pub modified_at: DateTime,
+}
+
+/// A storage backend definition which keeps the uploaded objects.
+#[tonic::async_trait]
+pub trait StorageBackend: Send + Sync + 'static {
+    /// Return the provider name which is recorded on the `File` documents.
+    fn provider(&self) -> &str;
+    fn region(&self) -> &str;
+    fn bucket(&self) -> &str;
+
+    /// Write the object to the path by reading the body until the end.
+    async fn put(
+        &self,
+        path: &str,
+        mime_type: &str,
+        body: &mut (dyn AsyncRead + Send + Unpin),
+    ) -> Result<(), Box<dyn std::error::Error>>;
+    /// Return the size of the object in bytes.
+    async fn size(&self, path: &str) -> Result<u64, Box<dyn std::error::Error>>;
+    /// Read the whole object, or only the inclusive byte range of it.
+    async fn get(&self, path: &str, range: Option<(u64, u64)>) -> Result<ByteStream, Box<dyn std::error::Error>>;
+    async fn delete(&self, path: &str) -> Result<(), Box<dyn std::error::Error>>;
+    /// Return all objects which are kept in the backend.
+    async fn list(&self) -> Result<Vec<StoredObject>, Box<dyn std::error::Error>>;
+}
+
+/// Check that the object path is relative and never refers to its parents.
+pub fn check_path(path: &str) -> Result<(), InvalidPathError> {
+    let valid = !path.is_empty()
+        && Path::new(path).components().all(|component| matches!(component, Component::Normal(_)));
+
+    if valid {
+        return Ok(());
+    }
+
+    Err(InvalidPathError { path: path.to_owned() })
+}
+
+/// A reader which computes the SHA-256 digest of everything read through it,
+/// so the content can be hashed while it is streamed to the backend.
+pub struct HashingReader<R> {
+    inner: R,
+    hasher: Sha256,
+    bytes_read: u64,
+}
+
+impl<R: AsyncRead + Unpin> HashingReader<R> {
+    pub fn new(inner: R) -> Self {
+        HashingReader { inner, hasher: Sha256::new(), bytes_read: 0 }
+    }
+
+    pub fn bytes_read(&self) -> u64 {
+        self.bytes_read
+    }
+
+    /// Return the hex-encoded digest of the content which has been read.
+    pub fn finish(self) -> String {
+        hex::encode(self.hasher.finalize())
+    }
+}
+
+impl<R: AsyncRead + Unpin> AsyncRead for HashingReader<R> {
+    fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        let filled = buf.filled().len();
+        let poll = Pin::new(&mut self.inner).poll_read(cx, buf);
+
+        if let Poll::Ready(Ok(())) = poll {
+            self.hasher.update(&buf.filled()[filled..]);
+            self.bytes_read += (buf.filled().len() - filled) as u64;
+        }
+
+        poll
+    }
+}
+
+/// Return the hex-encoded SHA-256 digest of the content.
+pub fn sha256(data: &[u8]) -> String {
+    hex::encode(Sha256::digest(data))
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::AsyncReadExt;
+
+    use crate::storage::backend::{check_path, HashingReader, sha256};
+
+    #[test]
+    fn check_relative_path() {
+        // Given
+
+        // When
+
+        // Then
+        assert!(check_path("2022/03/screenshot.png").is_ok());
+        assert!(check_path("../etc/passwd").is_err());
+        assert!(check_path("/etc/passwd").is_err());
+        assert!(check_path("").is_err());
+    }
+
+    #[tokio::test]
+    async fn hash_while_reading() {
+        // Given
+        let data = b"hello world".repeat(1000);
+        let mut reader = HashingReader::new(data.as_slice());
+
+        // When
+        let mut output: Vec<u8> = vec![];
+        reader.read_to_end(&mut output).await.unwrap();
+
+        // Then
+        assert_eq!(data, output);
+        assert_eq!(data.len() as u64, reader.bytes_read());
+        assert_eq!(sha256(&data), reader.finish());
+        assert_eq!(
+            "b94d27b9934d3e08a52e52d7da7dabfac484efe37a5380ee9088f7ace2efcde9",
+            sha256(b"hello world"),
+        );
+    }
+}
@@ -1,40 +1,367 @@
-use std::time::SystemTime;
+use std::collections::HashSet;
+use std::str::FromStr;
 
-use mongodb::bson::Document;
-use myblog_proto_rust::myblog::proto::storage::File;
+use mongodb::{bson::DateTime, bson::doc, bson::Document, bson::oid::ObjectId, Collection};
+use mongodb::options::FindOptions;
+use myblog_proto_rust::myblog::proto::storage::{File, ImageDerivative};
 use prost_types::Timestamp;
+use tokio_stream::StreamExt;
+use unicode_normalization::char::is_combining_mark;
 
-use crate::encoding::bson::Unmarshaler;
-
-impl Unmarshaler for File {
-    fn unmarshal_bson(
-        document: &Document,
-    ) -> Result<Self, mongodb::bson::document::ValueAccessError>
-    where
-        Self: Sized,
-    {
-        Ok(File {
-            id: document.get_object_id("_id")?.to_hex(),
-            file_name: document.get_str("fileName")?.to_owned(),
-            slug: document.get_str("slug")?.to_owned(),
-            uploaded_file_path: document.get_str("uploadedFilePath")?.to_owned(),
-            mime_type: document.get_str("mimeType")?.to_owned(),
-            provider: document.get_str("provider")?.to_owned(),
-            region: document.get_str("region")?.to_owned(),
-            bucket: document.get_str("bucket")?.to_owned(),
-            uploaded_at: None,
-            // uploaded_at: Some(
-            //     document
-            //         .get_datetime("uploadedAt")
-            //         .and_then(|uploaded_at| {
-            //             Ok(Timestamp::from(SystemTime::from(uploaded_at.to_owned())))
-            //         })?,
-            // ),
-            modified_at: None,
-            // modified_at: match document.get_datetime("modifiedAt") {
-            //     Ok(modified_at) => Some(Timestamp::from(SystemTime::from(modified_at.to_owned()))),
-            //     _ => None,
-            // },
-        })
+use crate::encoding::bson::{Marshaler, Unmarshaler};
+
+/// A file repository definition.
+#[tonic::async_trait]
+pub trait FileRepository: Send + Sync + 'static {
+    async fn create(&self, f: &File) -> Result<(), Box<dyn std::error::Error>>;
+    async fn find_by_id(&self, id: &str) -> Result<Option<File>, Box<dyn std::error::Error>>;
+    /// Find the file by its own slug or the slug of one of its derivatives.
+    async fn find_by_slug(&self, slug: &str) -> Result<Option<File>, Box<dyn std::error::Error>>;
+    /// Find a file of the content digest in the bucket whose objects can be shared, the orphaned files are skipped
+    /// since their objects may be collected at any time.
+    async fn find_by_sha256(&self, provider: &str, bucket: &str, sha256: &str) -> Result<Option<File>, Box<dyn std::error::Error>>;
+    /// Return the total size of the files which the user has uploaded, the shared objects count for every file.
+    async fn sum_size_by_uploader(&self, sub: &str) -> Result<u64, Box<dyn std::error::Error>>;
+    /// Return a number of the files which share the object at the path.
+    async fn count_by_path(&self, path: &str) -> Result<u64, Box<dyn std::error::Error>>;
+    /// Mark the files which are not referenced as orphaned since now, and unmark the referenced ones.
+    async fn mark_orphans(&self, referenced: &[String]) -> Result<u64, Box<dyn std::error::Error>>;
+    /// Return the files which have been orphaned since before the time and are still not referenced.
+    async fn find_orphans(&self, referenced: &[String], before: DateTime) -> Result<Vec<File>, Box<dyn std::error::Error>>;
+    /// Return the object paths of all files including their derivatives.
+    async fn find_all_paths(&self) -> Result<HashSet<String>, Box<dyn std::error::Error>>;
+    /// Clear the orphan mark of the file which is about to be referenced, so it is not collected meanwhile.
+    async fn unmark_orphan(&self, id: &str) -> Result<(), Box<dyn std::error::Error>>;
+    async fn delete(&self, id: &str) -> Result<(), Box<dyn std::error::Error>>;
+    /// Delete the file only if it is still orphaned since before the time, return true if it has been deleted.
+    async fn delete_orphan(&self, id: &str, before: DateTime) -> Result<bool, Box<dyn std::error::Error>>;
+}
+
+/// An implementation of the FileRepository specifies with MongoDB.
+pub struct MongoFileRepository {
+    collection: Collection<Document>,
+}
+
+impl MongoFileRepository {
+    pub fn new(collection: Collection<Document>) -> Self {
+        MongoFileRepository { collection }
+    }
+}
+
+#[tonic::async_trait]
+impl FileRepository for MongoFileRepository {
+    async fn create(&self, f: &File) -> Result<(), Box<dyn std::error::Error>> {
+        self.collection.insert_one(f.marshal_bson()?, None).await?;
+
+        Ok(())
+    }
+
+    async fn find_by_id(&self, id: &str) -> Result<Option<File>, Box<dyn std::error::Error>> {
+        let filter = doc! {"_id": ObjectId::from_str(id)?};
+
+        if let Some(document) = self.collection.find_one(filter, None).await? {
+            return Ok(Some(File::unmarshal_bson(&document)?));
+        }
+
+        Ok(None)
+    }
+
+    async fn find_by_slug(&self, slug: &str) -> Result<Option<File>, Box<dyn std::error::Error>> {
+        let filter = doc! {"$or": [{"slug": slug}, {"derivatives.slug": slug}]};
+
+        if let Some(document) = self.collection.find_one(filter, None).await? {
+            return Ok(Some(File::unmarshal_bson(&document)?));
+        }
+
+        Ok(None)
+    }
+
+    async fn find_by_sha256(&self, provider: &str, bucket: &str, sha256: &str) -> Result<Option<File>, Box<dyn std::error::Error>> {
+        let filter = doc! {
+            "provider": provider,
+            "bucket": bucket,
+            "sha256": sha256,
+            "orphanedAt": {"$exists": false},
+        };
+
+        if let Some(document) = self.collection.find_one(filter, None).await? {
+            return Ok(Some(File::unmarshal_bson(&document)?));
+        }
+
+        Ok(None)
+    }
+
+    async fn sum_size_by_uploader(&self, sub: &str) -> Result<u64, Box<dyn std::error::Error>> {
+        let mut cursor = self.collection
+            .aggregate(
+                vec![
+                    doc! {"$match": {"uploadedBy": sub}},
+                    doc! {"$group": {"_id": null, "size": {"$sum": "$size"}}},
+                ],
+                None,
+            )
+            .await?;
+
+        match cursor.try_next().await? {
+            Some(document) => Ok(document.get_i64("size").unwrap_or_default() as u64),
+            _ => Ok(0),
+        }
+    }
+
+    async fn count_by_path(&self, path: &str) -> Result<u64, Box<dyn std::error::Error>> {
+        Ok(self.collection.count_documents(doc! {"uploadedFilePath": path}, None).await?)
+    }
+
+    async fn mark_orphans(&self, referenced: &[String]) -> Result<u64, Box<dyn std::error::Error>> {
+        let referenced = to_object_ids(referenced)?;
+
+        self.collection
+            .update_many(
+                doc! {"_id": {"$in": referenced.clone()}, "orphanedAt": {"$exists": true}},
+                doc! {"$unset": {"orphanedAt": ""}},
+                None,
+            )
+            .await?;
+        let result = self.collection
+            .update_many(
+                doc! {"_id": {"$nin": referenced}, "orphanedAt": {"$exists": false}},
+                doc! {"$set": {"orphanedAt": DateTime::now()}},
+                None,
+            )
+            .await?;
+
+        Ok(result.modified_count)
+    }
+
+    async fn find_orphans(&self, referenced: &[String], before: DateTime) -> Result<Vec<File>, Box<dyn std::error::Error>> {
+        let filter = doc! {"_id": {"$nin": to_object_ids(referenced)?}, "orphanedAt": {"$lt": before}};
+
+        let mut cursor = self.collection.find(filter, None).await?;
+        let mut result: Vec<File> = vec![];
+
+        while let Some(document) = cursor.try_next().await? {
+            result.push(File::unmarshal_bson(&document)?);
+        }
+
+        Ok(result)
+    }
+
+    async fn find_all_paths(&self) -> Result<HashSet<String>, Box<dyn std::error::Error>> {
+        let options = FindOptions::builder()
+            .projection(doc! {"uploadedFilePath": 1, "derivatives.uploadedFilePath": 1})
+            .build();
+
+        let mut cursor = self.collection.find(doc! {}, options).await?;
+        let mut result: HashSet<String> = HashSet::new();
+
+        while let Some(document) = cursor.try_next().await? {
+            result.insert(document.get_str("uploadedFilePath")?.to_owned());
+
+            if let Ok(derivatives) = document.get_array("derivatives") {
+                for derivative in derivatives.iter().filter_map(|derivative| derivative.as_document()) {
+                    result.insert(derivative.get_str("uploadedFilePath")?.to_owned());
+                }
+            }
+        }
+
+        Ok(result)
+    }
+
+    async fn unmark_orphan(&self, id: &str) -> Result<(), Box<dyn std::error::Error>> {
+        self.collection
+            .update_one(doc! {"_id": ObjectId::from_str(id)?}, doc! {"$unset": {"orphanedAt": ""}}, None)
+            .await?;
+
+        Ok(())
+    }
+
+    async fn delete(&self, id: &str) -> Result<(), Box<dyn std::error::Error>> {
+        self.collection.delete_one(doc! {"_id": ObjectId::from_str(id)?}, None).await?;
+
+        Ok(())
+    }
+
+    async fn delete_orphan(&self, id: &str, before: DateTime) -> Result<bool, Box<dyn std::error::Error>> {
+        let result = self.collection
+            .delete_one(doc! {"_id": ObjectId::from_str(id)?, "orphanedAt": {"$lt": before}}, None)
+            .await?;
+
+        Ok(result.deleted_count > 0)
+    }
+}
+
+fn to_object_ids(ids: &[String]) -> Result<Vec<ObjectId>, mongodb::bson::oid::Error> {
+    ids.iter().map(|id| ObjectId::from_str(id.as_str())).collect()
+}
+
+/// Return the slug of the image derivative of the width, e.g. `{id}-my-screenshot-640w.webp`.
+pub fn derivative_slug(slug: &str, width: u32, extension: &str) -> String {
+    let stem = match slug.rfind('.') {
+        Some(i) => &slug[..i],
+        _ => slug,
+    };
+
+    format!("{}-{}w.{}", stem, width, extension)
+}
+
+/// Return a unique slug of the uploaded file which keeps its extension, e.g. `{id}-my-screenshot.png`.
+pub fn file_slug(id: &str, file_name: &str) -> String {
+    let (stem, extension) = match file_name.rfind('.') {
+        Some(i) if i > 0 => (&file_name[..i], Some(&file_name[i + 1..])),
+        _ => (file_name, None),
+    };
+    let slugify = |s: &str| {
+        s.to_lowercase()
+            .split(|c: char| !(c.is_alphanumeric() || is_combining_mark(c)))
+            .filter(|part| !part.is_empty())
+            .collect::<Vec<&str>>()
+            .join("-")
+    };
+
+    let mut slug = id.to_owned();
+    let stem = slugify(stem);
+    if !stem.is_empty() {
+        slug.push('-');
+        slug.push_str(stem.as_str());
+    }
+    if let Some(extension) = extension.map(slugify).filter(|extension| !extension.is_empty()) {
+        slug.push('.');
+        slug.push_str(extension.as_str());
+    }
+
+    slug
+}
+
+/// A schema of the stored file, only the images have the dimensions, a placeholder and the derivatives.
+#[derive(Marshaler, Unmarshaler)]
+#[bson(remote = "File")]
+struct FileSchema {
+    #[bson(rename = "_id", object_id)]
+    id: String,
+    file_name: String,
+    slug: String,
+    uploaded_file_path: String,
+    mime_type: String,
+    provider: String,
+    region: String,
+    bucket: String,
+    #[bson(optional)]
+    private: bool,
+    #[bson(optional)]
+    sha256: String,
+    #[bson(optional)]
+    size: u64,
+    #[bson(optional)]
+    uploaded_by: String,
+    #[bson(optional, skip_if_default)]
+    width: u32,
+    #[bson(optional, skip_if_default)]
+    height: u32,
+    #[bson(optional, skip_if_default)]
+    blurhash: String,
+    #[bson(embedded, optional, skip_if_default)]
+    derivatives: Vec<ImageDerivative>,
+    #[bson(timestamp)]
+    uploaded_at: Option<Timestamp>,
+    #[bson(timestamp, optional)]
+    modified_at: Option<Timestamp>,
+}
+
+#[derive(Marshaler, Unmarshaler)]
+#[bson(remote = "ImageDerivative")]
+struct ImageDerivativeSchema {
+    #[bson(optional)]
+    slug: String,
+    #[bson(optional)]
+    uploaded_file_path: String,
+    #[bson(optional)]
+    url: String,
+    #[bson(optional)]
+    mime_type: String,
+    #[bson(optional)]
+    width: u32,
+    #[bson(optional)]
+    height: u32,
+}
+
+#[cfg(test)]
+mod tests {
+    use mongodb::bson::{DateTime, doc, oid::ObjectId};
+    use myblog_proto_rust::myblog::proto::storage::File;
+    use proptest::prelude::*;
+
+    use crate::encoding::bson::{arbitrary, Marshaler, Unmarshaler};
+    use crate::storage::file::{derivative_slug, file_slug};
+
+    #[test]
+    fn slug_of_file_name() {
+        // Given
+        let id = "5b2863365c31b411b041995e";
+
+        // When
+
+        // Then
+        assert_eq!("5b2863365c31b411b041995e-my-screenshot-1.png", file_slug(id, "My Screenshot (1).PNG"));
+        assert_eq!("5b2863365c31b411b041995e-ภาพหน้าจอ.jpg", file_slug(id, "ภาพหน้าจอ.jpg"));
+        assert_eq!("5b2863365c31b411b041995e-bashrc", file_slug(id, ".bashrc"));
+        assert_eq!("5b2863365c31b411b041995e", file_slug(id, "???"));
+    }
+
+    #[test]
+    fn slug_of_derivative() {
+        // Given
+        let slug = "5b2863365c31b411b041995e-my-screenshot.png";
+
+        // When
+        let result = derivative_slug(slug, 640, "webp");
+
+        // Then
+        assert_eq!("5b2863365c31b411b041995e-my-screenshot-640w.webp", result);
+    }
+
+    #[test]
+    fn decode_error_of_derivative_field() {
+        // Given
+        let id = ObjectId::new();
+        let document = doc! {
+            "_id": id,
+            "fileName": "My Screenshot.png",
+            "slug": "my-screenshot.png",
+            "uploadedFilePath": "my-screenshot.png",
+            "mimeType": "image/png",
+            "provider": "local",
+            "region": "",
+            "bucket": "uploads",
+            "derivatives": [{"width": 640}, {"width": "wide"}],
+            "uploadedAt": DateTime::now(),
+        };
+
+        // When
+        let result = File::unmarshal_bson(&document).unwrap_err();
+
+        // Then
+        assert_eq!("File", result.type_name);
+        assert_eq!(Some(id.to_hex()), result.id);
+        assert_eq!(vec!["derivatives", "1", "width"], result.path);
+        assert_eq!(
+            format!(
+                "Failed to decode File '{}' at 'derivatives.1.width': field does not have the expected type",
+                id.to_hex(),
+            ),
+            result.to_string(),
+        );
+    }
+
+    proptest! {
+        #[test]
+        fn round_trip_file(file in arbitrary::file()) {
+            // Given
+
+            // When
+            let result = File::unmarshal_bson(&file.marshal_bson().unwrap()).unwrap();
+
+            // Then
+            prop_assert_eq!(file, result);
+        }
     }
 }
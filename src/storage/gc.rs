@@ -0,0 +1,175 @@
+use std::collections::HashSet;
+use std::sync::Arc;
+use std::time::Duration;
+
+use mongodb::bson::DateTime;
+use myblog_proto_rust::myblog::proto::storage::File;
+
+use crate::blog::post::PostRepository;
+use crate::storage::backend::{StorageBackend, StoredObject};
+use crate::storage::file::FileRepository;
+use crate::storage::usage::UsageRepository;
+
+/// A result of the garbage collection, or what would be collected on a dry run.
+#[derive(Default)]
+pub struct GcReport {
+    /// A number of the files which have just been found unreferenced.
+    pub marked: u64,
+    /// The files which have been unreferenced for longer than the grace period.
+    pub orphaned_files: Vec<File>,
+    /// The backend objects which no file describes.
+    pub orphaned_objects: Vec<String>,
+    /// The objects or files which could not be deleted, they are retried on the next run.
+    pub failures: Vec<String>,
+}
+
+/// Collect the files which are no longer referenced by any post, and the objects which no file describes.
+///
+/// A file is first marked as orphaned and only deleted once it has stayed unreferenced for the grace period,
+/// so the files which are uploaded but not yet attached to a post are kept.
+pub struct GarbageCollector {
+    file_repository: Box<dyn FileRepository>,
+    post_repository: Box<dyn PostRepository>,
+    usage_repository: Box<dyn UsageRepository>,
+    storage_backend: Arc<dyn StorageBackend>,
+    grace_period: Duration,
+}
+
+impl GarbageCollector {
+    pub fn new(
+        file_repository: Box<dyn FileRepository>,
+        post_repository: Box<dyn PostRepository>,
+        usage_repository: Box<dyn UsageRepository>,
+        storage_backend: Arc<dyn StorageBackend>,
+        grace_period: Duration,
+    ) -> Self {
+        GarbageCollector { file_repository, post_repository, usage_repository, storage_backend, grace_period }
+    }
+
+    /// Run the garbage collection, nothing is marked or deleted on a dry run.
+    pub async fn run(&self, dry_run: bool) -> Result<GcReport, Box<dyn std::error::Error>> {
+        let mut report = GcReport::default();
+        let cutoff = DateTime::from_millis(
+            DateTime::now().timestamp_millis() - self.grace_period.as_millis() as i64,
+        );
+
+        let referenced = self.post_repository.find_referenced_files().await?;
+        if !dry_run {
+            report.marked = self.file_repository.mark_orphans(&referenced).await?;
+        }
+        report.orphaned_files = self.file_repository.find_orphans(&referenced, cutoff).await?;
+
+        // Objects are listed before the paths are read, so an upload which completes in between is never collected
+        let objects = self.storage_backend.list().await?;
+        let known_paths = self.file_repository.find_all_paths().await?;
+        report.orphaned_objects = orphaned_objects(&objects, &known_paths, cutoff);
+
+        if dry_run {
+            return Ok(report);
+        }
+
+        for file in report.orphaned_files.iter() {
+            // The metadata goes first and only if the file is still orphaned, so a file which has been attached
+            // since it was found is kept along with its objects
+            match self.file_repository.delete_orphan(file.id.as_str(), cutoff).await {
+                Ok(true) => (),
+                Ok(false) => continue,
+                Err(e) => {
+                    report.failures.push(format!("{}: {}", file.id, e));
+                    continue;
+                }
+            }
+            if let Err(e) = self.usage_repository.release(file.uploaded_by.as_str(), file.size).await {
+                report.failures.push(format!("{}: {}", file.uploaded_by, e));
+            }
+
+            // The objects may be shared by the files of the same content, they are only deleted with the last one
+            let references = match self.file_repository.count_by_path(file.uploaded_file_path.as_str()).await {
+                Ok(references) => references,
+                Err(e) => {
+                    report.failures.push(format!("{}: {}", file.id, e));
+                    continue;
+                }
+            };
+
+            // The objects which fail to be deleted are described by no file anymore, so they are collected
+            // as orphaned objects on a later run
+            let paths = if references > 0 { vec![] } else { file_paths(file) };
+            for path in paths {
+                if let Err(e) = self.storage_backend.delete(path.as_str()).await {
+                    report.failures.push(format!("{}: {}", path, e));
+                }
+            }
+        }
+        for path in report.orphaned_objects.iter() {
+            if let Err(e) = self.storage_backend.delete(path.as_str()).await {
+                report.failures.push(format!("{}: {}", path, e));
+            }
+        }
+
+        Ok(report)
+    }
+}
+
+/// Return the object paths of the file and all of its derivatives.
+pub fn file_paths(file: &File) -> Vec<String> {
+    let mut result = vec![file.uploaded_file_path.clone()];
+    result.extend(file.derivatives.iter().map(|derivative| derivative.uploaded_file_path.clone()));
+    result
+}
+
+/// Return the paths of the objects which were modified before the cutoff and no file describes.
+pub fn orphaned_objects(objects: &[StoredObject], known_paths: &HashSet<String>, cutoff: DateTime) -> Vec<String> {
+    objects
+        .iter()
+        .filter(|object| object.modified_at < cutoff && !known_paths.contains(&object.path))
+        .map(|object| object.path.clone())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use mongodb::bson::DateTime;
+    use myblog_proto_rust::myblog::proto::storage::{File, ImageDerivative};
+
+    use crate::storage::backend::StoredObject;
+    use crate::storage::gc::{file_paths, orphaned_objects};
+
+    #[test]
+    fn orphaned_objects_past_cutoff() {
+        // Given
+        let objects = vec![
+            StoredObject { path: String::from("2022/03/a.png"), modified_at: DateTime::from_millis(1000) },
+            StoredObject { path: String::from("2022/03/b.png"), modified_at: DateTime::from_millis(1000) },
+            StoredObject { path: String::from("2022/03/c.png"), modified_at: DateTime::from_millis(3000) },
+        ];
+        let known_paths: HashSet<String> = vec![String::from("2022/03/a.png")].into_iter().collect();
+
+        // When
+        let result = orphaned_objects(&objects, &known_paths, DateTime::from_millis(2000));
+
+        // Then
+        assert_eq!(vec!["2022/03/b.png"], result);
+    }
+
+    #[test]
+    fn paths_of_file_with_derivatives() {
+        // Given
+        let file = File {
+            uploaded_file_path: String::from("2022/03/a.png"),
+            derivatives: vec![ImageDerivative {
+                uploaded_file_path: String::from("2022/03/a-320w.webp"),
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+
+        // When
+        let result = file_paths(&file);
+
+        // Then
+        assert_eq!(vec!["2022/03/a.png", "2022/03/a-320w.webp"], result);
+    }
+}
@@ -0,0 +1,175 @@
+use std::io::Cursor;
+
+use image::{DynamicImage, GenericImageView, ImageOutputFormat};
+use image::imageops::FilterType;
+
+/// A quality of the lossy encoded images, in the range of 0 to 100.
+const QUALITY: u8 = 85;
+
+/// A width of the thumbnail from which the blurhash is computed, the placeholder is blurry anyway.
+const BLURHASH_WIDTH: u32 = 32;
+
+/// An image which has been encoded to one of the supported formats.
+pub struct EncodedImage {
+    pub width: u32,
+    pub height: u32,
+    pub mime_type: &'static str,
+    pub extension: &'static str,
+    pub data: Vec<u8>,
+}
+
+/// An uploaded image with the metadata stripped and its derivative sizes.
+pub struct ProcessedImage {
+    pub original: EncodedImage,
+    pub derivatives: Vec<EncodedImage>,
+    pub blurhash: String,
+}
+
+/// Return true if the derivatives can be produced from the image of the MIME type.
+///
+/// GIF images are left as is, since re-encoding would drop their animation.
+pub fn is_processable(mime_type: &str) -> bool {
+    matches!(mime_type, "image/jpeg" | "image/png" | "image/webp")
+}
+
+/// Read the EXIF orientation of the image, 1 (no transformation) is returned if there is none.
+pub fn exif_orientation(data: &[u8]) -> u32 {
+    exif::Reader::new()
+        .read_from_container(&mut Cursor::new(data))
+        .ok()
+        .and_then(|exif| {
+            exif.get_field(exif::Tag::Orientation, exif::In::PRIMARY)
+                .and_then(|field| field.value.get_uint(0))
+        })
+        .unwrap_or(1)
+}
+
+/// Transform the image, so it is displayed upright without its EXIF orientation.
+pub fn apply_orientation(img: DynamicImage, orientation: u32) -> DynamicImage {
+    match orientation {
+        2 => img.fliph(),
+        3 => img.rotate180(),
+        4 => img.flipv(),
+        5 => img.rotate90().fliph(),
+        6 => img.rotate90(),
+        7 => img.rotate270().fliph(),
+        8 => img.rotate270(),
+        _ => img,
+    }
+}
+
+fn encode(img: &DynamicImage, mime_type: &str) -> Result<EncodedImage, Box<dyn std::error::Error + Send + Sync>> {
+    let mut data: Vec<u8> = vec![];
+    let (mime_type, extension) = match mime_type {
+        "image/png" => {
+            img.write_to(&mut Cursor::new(&mut data), ImageOutputFormat::Png)?;
+            ("image/png", "png")
+        }
+        "image/webp" => {
+            let rgba = DynamicImage::ImageRgba8(img.to_rgba8());
+            data = webp::Encoder::from_image(&rgba)?.encode(QUALITY as f32).to_vec();
+            ("image/webp", "webp")
+        }
+        _ => {
+            img.write_to(&mut Cursor::new(&mut data), ImageOutputFormat::Jpeg(QUALITY))?;
+            ("image/jpeg", "jpg")
+        }
+    };
+
+    Ok(EncodedImage { width: img.width(), height: img.height(), mime_type, extension, data })
+}
+
+/// Decode the image, apply its orientation and re-encode it without the metadata,
+/// then produce the derivatives of the widths, which are smaller than the image, in its own format and WebP.
+pub fn process(
+    data: &[u8],
+    mime_type: &str,
+    widths: &[u32],
+) -> Result<ProcessedImage, Box<dyn std::error::Error + Send + Sync>> {
+    let img = apply_orientation(image::load_from_memory(data)?, exif_orientation(data));
+
+    let mut derivatives: Vec<EncodedImage> = vec![];
+    for &width in widths.iter().filter(|&&width| width < img.width()) {
+        let resized = img.resize(width, u32::MAX, FilterType::Lanczos3);
+
+        if mime_type != "image/webp" {
+            derivatives.push(encode(&resized, mime_type)?);
+        }
+        derivatives.push(encode(&resized, "image/webp")?);
+    }
+
+    let thumbnail = img.resize(BLURHASH_WIDTH, u32::MAX, FilterType::Triangle);
+    let blurhash = blurhash::encode(4, 3, thumbnail.width(), thumbnail.height(), &thumbnail.to_rgba8().into_raw());
+
+    Ok(ProcessedImage { original: encode(&img, mime_type)?, derivatives, blurhash })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use image::{DynamicImage, GenericImageView, ImageOutputFormat, Rgb, RgbImage};
+
+    use crate::storage::image::{apply_orientation, exif_orientation, is_processable, process};
+
+    fn png(width: u32, height: u32) -> Vec<u8> {
+        let mut data: Vec<u8> = vec![];
+        DynamicImage::ImageRgb8(RgbImage::from_pixel(width, height, Rgb([255, 0, 0])))
+            .write_to(&mut Cursor::new(&mut data), ImageOutputFormat::Png)
+            .unwrap();
+        data
+    }
+
+    #[test]
+    fn processable_mime_types() {
+        // Given
+
+        // When
+
+        // Then
+        assert!(is_processable("image/jpeg"));
+        assert!(!is_processable("image/gif"));
+        assert!(!is_processable("application/pdf"));
+    }
+
+    #[test]
+    fn apply_rotation_orientation() {
+        // Given
+        let img = DynamicImage::ImageRgb8(RgbImage::new(2, 1));
+
+        // When
+        let result = apply_orientation(img, 6);
+
+        // Then
+        assert_eq!((1, 2), result.dimensions());
+    }
+
+    #[test]
+    fn exif_orientation_without_exif() {
+        // Given
+        let data = png(1, 1);
+
+        // When
+        let result = exif_orientation(&data);
+
+        // Then
+        assert_eq!(1, result);
+    }
+
+    #[test]
+    fn process_image_derivatives() {
+        // Given
+        let data = png(800, 400);
+
+        // When
+        let result = process(&data, "image/png", &[320, 640, 1280]).unwrap();
+
+        // Then
+        assert_eq!((800, 400), (result.original.width, result.original.height));
+        assert_eq!(
+            vec![(320, 160, "image/png"), (320, 160, "image/webp"), (640, 320, "image/png"), (640, 320, "image/webp")],
+            result.derivatives.iter().map(|d| (d.width, d.height, d.mime_type)).collect::<Vec<(u32, u32, &str)>>(),
+        );
+        assert!(!result.blurhash.is_empty());
+    }
+}
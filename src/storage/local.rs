@@ -0,0 +1,158 @@
+use std::path::PathBuf;
+
+use mongodb::bson::{DateTime, oid::ObjectId};
+use std::io::SeekFrom;
+
+use tokio::fs;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncSeekExt};
+use tokio_util::io::ReaderStream;
+
+use crate::storage::backend::{ByteStream, check_path, StorageBackend, StoredObject};
+
+/// An implementation of the StorageBackend specifies with the local filesystem.
+pub struct LocalStorageBackend {
+    root: PathBuf,
+    root_name: String,
+}
+
+impl LocalStorageBackend {
+    pub fn new(root: PathBuf) -> Self {
+        let root_name = root.to_string_lossy().into_owned();
+
+        LocalStorageBackend { root, root_name }
+    }
+}
+
+#[tonic::async_trait]
+impl StorageBackend for LocalStorageBackend {
+    fn provider(&self) -> &str {
+        "local"
+    }
+
+    fn region(&self) -> &str {
+        ""
+    }
+
+    fn bucket(&self) -> &str {
+        self.root_name.as_str()
+    }
+
+    async fn put(
+        &self,
+        path: &str,
+        _mime_type: &str,
+        body: &mut (dyn AsyncRead + Send + Unpin),
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        check_path(path)?;
+        let destination = self.root.join(path);
+        if let Some(parent) = destination.parent() {
+            fs::create_dir_all(parent).await?;
+        }
+
+        // The object is written aside and renamed, so a partial upload is never visible at its path
+        let partial = destination.with_file_name(format!(".{}.partial", ObjectId::new().to_hex()));
+        let mut file = fs::File::create(&partial).await?;
+        if let Err(e) = tokio::io::copy(body, &mut file).await {
+            fs::remove_file(&partial).await.ok();
+            return Err(Box::new(e));
+        }
+        file.sync_all().await?;
+        fs::rename(&partial, &destination).await?;
+
+        Ok(())
+    }
+
+    async fn size(&self, path: &str) -> Result<u64, Box<dyn std::error::Error>> {
+        check_path(path)?;
+
+        Ok(fs::metadata(self.root.join(path)).await?.len())
+    }
+
+    async fn get(&self, path: &str, range: Option<(u64, u64)>) -> Result<ByteStream, Box<dyn std::error::Error>> {
+        check_path(path)?;
+        let mut file = fs::File::open(self.root.join(path)).await?;
+
+        match range {
+            Some((start, end)) => {
+                file.seek(SeekFrom::Start(start)).await?;
+                Ok(Box::pin(ReaderStream::new(file.take(end - start + 1))))
+            }
+            _ => Ok(Box::pin(ReaderStream::new(file))),
+        }
+    }
+
+    async fn delete(&self, path: &str) -> Result<(), Box<dyn std::error::Error>> {
+        check_path(path)?;
+
+        match fs::remove_file(self.root.join(path)).await {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(Box::new(e)),
+            _ => Ok(()),
+        }
+    }
+
+    async fn list(&self) -> Result<Vec<StoredObject>, Box<dyn std::error::Error>> {
+        let mut result: Vec<StoredObject> = vec![];
+        let mut directories = vec![self.root.clone()];
+
+        while let Some(directory) = directories.pop() {
+            let mut entries = match fs::read_dir(&directory).await {
+                Ok(entries) => entries,
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+                Err(e) => return Err(Box::new(e)),
+            };
+
+            while let Some(entry) = entries.next_entry().await? {
+                let metadata = entry.metadata().await?;
+                if metadata.is_dir() {
+                    directories.push(entry.path());
+                    continue;
+                }
+
+                let path = entry.path();
+                let relative = path.strip_prefix(&self.root)?
+                    .components()
+                    .map(|component| component.as_os_str().to_string_lossy())
+                    .collect::<Vec<_>>()
+                    .join("/");
+                result.push(StoredObject { path: relative, modified_at: DateTime::from(metadata.modified()?) });
+            }
+        }
+
+        Ok(result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use mongodb::bson::oid::ObjectId;
+    use tokio_stream::StreamExt;
+
+    use crate::storage::backend::StorageBackend;
+    use crate::storage::local::LocalStorageBackend;
+
+    #[tokio::test]
+    async fn put_get_list_and_delete_object() {
+        // Given
+        let root = std::env::temp_dir().join(ObjectId::new().to_hex());
+        let backend = LocalStorageBackend::new(root.clone());
+        let mut body: &[u8] = b"Hello, world!";
+
+        // When
+        backend.put("2022/03/hello.txt", "text/plain", &mut body).await.unwrap();
+        let size = backend.size("2022/03/hello.txt").await.unwrap();
+        let mut content: Vec<u8> = vec![];
+        let mut stream = backend.get("2022/03/hello.txt", Some((7, 11))).await.unwrap();
+        while let Some(chunk) = stream.next().await {
+            content.extend(chunk.unwrap());
+        }
+        let objects = backend.list().await.unwrap();
+        backend.delete("2022/03/hello.txt").await.unwrap();
+
+        // Then
+        assert_eq!(13, size);
+        assert_eq!(vec!["2022/03/hello.txt"], objects.iter().map(|o| o.path.as_str()).collect::<Vec<&str>>());
+        assert_eq!(b"world".to_vec(), content);
+        assert!(!root.join("2022/03/hello.txt").exists());
+        std::fs::remove_dir_all(root).ok();
+    }
+}
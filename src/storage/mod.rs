@@ -1 +1,10 @@
+pub mod backend;
 pub mod file;
+pub mod gc;
+pub mod image;
+pub mod local;
+pub mod s3;
+pub mod serve;
+pub mod service;
+pub mod usage;
+pub mod validate;
@@ -0,0 +1,143 @@
+use mongodb::bson::DateTime;
+use s3::{Bucket, creds::Credentials, Region};
+use s3::command::Command;
+use s3::request::{Request, tokio_backend::Reqwest};
+use tokio::io::AsyncRead;
+use tokio_stream::StreamExt;
+
+use crate::storage::backend::{ByteStream, check_path, StorageBackend, StoredObject};
+
+/// An error returned when the S3-compatible server responds with an unsuccessful status.
+#[derive(Debug)]
+pub struct S3StatusError {
+    pub status_code: u16,
+}
+
+impl std::fmt::Display for S3StatusError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "S3 request failed with status {}", self.status_code)
+    }
+}
+
+impl std::error::Error for S3StatusError {}
+
+/// An implementation of the StorageBackend specifies with Amazon S3 or any S3-compatible server (e.g. MinIO).
+pub struct S3StorageBackend {
+    bucket: Bucket,
+    region: String,
+}
+
+impl S3StorageBackend {
+    /// Create a backend of the bucket, a custom endpoint is addressed with the path-style URLs as MinIO expects.
+    pub fn new(
+        endpoint: Option<&str>,
+        region: &str,
+        bucket: &str,
+        access_key: &str,
+        secret_key: &str,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let credentials = Credentials::new(Some(access_key), Some(secret_key), None, None, None)?;
+        let bucket = match endpoint {
+            Some(endpoint) => Bucket::new(
+                bucket,
+                Region::Custom { region: region.to_owned(), endpoint: endpoint.to_owned() },
+                credentials,
+            )?.with_path_style(),
+            _ => Bucket::new(bucket, region.parse()?, credentials)?,
+        };
+
+        Ok(S3StorageBackend { bucket, region: region.to_owned() })
+    }
+}
+
+/// Turn the unsuccessful status code into an error.
+fn check_status(status_code: u16) -> Result<(), S3StatusError> {
+    if (200..300).contains(&status_code) {
+        return Ok(());
+    }
+
+    Err(S3StatusError { status_code })
+}
+
+#[tonic::async_trait]
+impl StorageBackend for S3StorageBackend {
+    fn provider(&self) -> &str {
+        "s3"
+    }
+
+    fn region(&self) -> &str {
+        self.region.as_str()
+    }
+
+    fn bucket(&self) -> &str {
+        self.bucket.name.as_str()
+    }
+
+    async fn put(
+        &self,
+        path: &str,
+        mime_type: &str,
+        body: &mut (dyn AsyncRead + Send + Unpin),
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        check_path(path)?;
+
+        // Large objects are sent as a multipart upload, so they never have to be held in memory
+        let mut body = body;
+        let status_code = self.bucket
+            .put_object_stream_with_content_type(&mut body, path, mime_type)
+            .await?;
+
+        Ok(check_status(status_code)?)
+    }
+
+    async fn size(&self, path: &str) -> Result<u64, Box<dyn std::error::Error>> {
+        check_path(path)?;
+
+        let (head, status_code) = self.bucket.head_object(path).await?;
+        check_status(status_code)?;
+
+        Ok(head.content_length.unwrap_or_default() as u64)
+    }
+
+    async fn get(&self, path: &str, range: Option<(u64, u64)>) -> Result<ByteStream, Box<dyn std::error::Error>> {
+        check_path(path)?;
+
+        let response = match range {
+            // The bucket has no streaming range request of its own, so the range command is sent directly
+            Some((start, end)) => {
+                Reqwest::new(&self.bucket, path, Command::GetObjectRange { start, end: Some(end) })?
+                    .response_data_to_stream()
+                    .await?
+            }
+            _ => self.bucket.get_object_stream(path).await?,
+        };
+        check_status(response.status_code)?;
+
+        Ok(Box::pin(response.bytes.map(Ok)))
+    }
+
+    async fn delete(&self, path: &str) -> Result<(), Box<dyn std::error::Error>> {
+        check_path(path)?;
+
+        let response = self.bucket.delete_object(path).await?;
+
+        Ok(check_status(response.status_code())?)
+    }
+
+    async fn list(&self) -> Result<Vec<StoredObject>, Box<dyn std::error::Error>> {
+        let mut result: Vec<StoredObject> = vec![];
+
+        for page in self.bucket.list(String::new(), None).await? {
+            for object in page.contents {
+                let modified_at = chrono::DateTime::parse_from_rfc3339(object.last_modified.as_str())?;
+
+                result.push(StoredObject {
+                    path: object.key,
+                    modified_at: DateTime::from_millis(modified_at.timestamp_millis()),
+                });
+            }
+        }
+
+        Ok(result)
+    }
+}
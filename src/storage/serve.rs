@@ -0,0 +1,313 @@
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::sync::Arc;
+
+use hmac::{Hmac, Mac};
+use mongodb::bson::DateTime;
+use sha2::Sha256;
+use warp::{Filter, Rejection, Reply};
+use warp::http::{header, Response, StatusCode};
+use warp::hyper::Body;
+
+use crate::storage::backend::StorageBackend;
+use crate::storage::file::FileRepository;
+
+/// A Cache-Control of the public objects, they are never overwritten at their path so they can be cached forever.
+const IMMUTABLE_CACHE_CONTROL: &str = "public, max-age=31536000, immutable";
+
+/// A Cache-Control of the private objects which must not outlive their signed URLs in the shared caches.
+const PRIVATE_CACHE_CONTROL: &str = "private, no-cache";
+
+/// Sign and verify the expiring URLs of the private files.
+#[derive(Clone)]
+pub struct UrlSigner {
+    secret: Vec<u8>,
+}
+
+impl UrlSigner {
+    pub fn new(secret: &[u8]) -> Self {
+        UrlSigner { secret: secret.to_vec() }
+    }
+
+    /// Return the signature of the slug which is valid until the time in milliseconds.
+    pub fn sign(&self, slug: &str, expires: i64) -> String {
+        hex::encode(self.mac(slug, expires).finalize().into_bytes())
+    }
+
+    pub fn verify(&self, slug: &str, expires: i64, signature: &str) -> bool {
+        match hex::decode(signature) {
+            Ok(signature) => {
+                self.mac(slug, expires).verify_slice(&signature).is_ok()
+                    && expires > DateTime::now().timestamp_millis()
+            }
+            _ => false,
+        }
+    }
+
+    fn mac(&self, slug: &str, expires: i64) -> Hmac<Sha256> {
+        let mut mac = Hmac::<Sha256>::new_from_slice(&self.secret)
+            .expect("HMAC can take key of any size");
+        mac.update(format!("{}.{}", slug, expires).as_bytes());
+        mac
+    }
+}
+
+/// An error returned when none of the requested bytes are within the object.
+#[derive(Debug, PartialEq)]
+pub struct RangeNotSatisfiable;
+
+/// Parse a single `bytes=` range of the Range header into an inclusive range within the object size.
+///
+/// `None` is returned for the invalid or multiple ranges, which are served as the whole object.
+pub fn parse_range(range: &str, size: u64) -> Result<Option<(u64, u64)>, RangeNotSatisfiable> {
+    let spec = match range.trim().strip_prefix("bytes=") {
+        Some(spec) if !spec.contains(',') => spec.trim(),
+        _ => return Ok(None),
+    };
+    let (start, end) = match spec.split_once('-') {
+        Some(bounds) => bounds,
+        _ => return Ok(None),
+    };
+
+    match (start.parse::<u64>().ok(), end.parse::<u64>().ok()) {
+        // A suffix range requests the last bytes of the object
+        (None, Some(length)) if start.is_empty() => {
+            if length == 0 || size == 0 {
+                return Err(RangeNotSatisfiable);
+            }
+            Ok(Some((size - length.min(size), size - 1)))
+        }
+        (Some(start), None) if end.is_empty() => {
+            if start >= size {
+                return Err(RangeNotSatisfiable);
+            }
+            Ok(Some((start, size - 1)))
+        }
+        (Some(start), Some(end)) if start <= end => {
+            if start >= size {
+                return Err(RangeNotSatisfiable);
+            }
+            Ok(Some((start, end.min(size - 1))))
+        }
+        _ => Ok(None),
+    }
+}
+
+/// Return true if the browsers may render the type in place, everything else is served as a download
+/// so an uploaded HTML or script can never run on the origin of the files.
+pub fn is_inline(mime_type: &str) -> bool {
+    // SVG images can carry scripts of their own
+    (mime_type.starts_with("image/") && mime_type != "image/svg+xml") || mime_type == "application/pdf"
+}
+
+fn status(status: StatusCode) -> Response<Body> {
+    Response::builder().status(status).body(Body::empty()).unwrap()
+}
+
+async fn serve(
+    file_repository: Arc<dyn FileRepository>,
+    storage_backend: Arc<dyn StorageBackend>,
+    signer: Arc<UrlSigner>,
+    slug: String,
+    range: Option<String>,
+    if_none_match: Option<String>,
+    query: HashMap<String, String>,
+) -> Result<Response<Body>, Infallible> {
+    let file = match file_repository.find_by_slug(slug.as_str()).await.map_err(|err| err.to_string()) {
+        Ok(Some(file)) => file,
+        Ok(None) => return Ok(status(StatusCode::NOT_FOUND)),
+        Err(e) => {
+            eprintln!("failed to find file '{}': {}", slug, e);
+            return Ok(status(StatusCode::INTERNAL_SERVER_ERROR));
+        }
+    };
+    let (path, mime_type) = match file.derivatives.iter().find(|derivative| derivative.slug == slug) {
+        Some(derivative) => (derivative.uploaded_file_path.clone(), derivative.mime_type.clone()),
+        _ => (file.uploaded_file_path.clone(), file.mime_type.clone()),
+    };
+
+    if file.private {
+        let expires = query.get("expires").and_then(|expires| expires.parse::<i64>().ok()).unwrap_or_default();
+        let signature = query.get("signature").map(String::as_str).unwrap_or_default();
+
+        if !signer.verify(slug.as_str(), expires, signature) {
+            return Ok(status(StatusCode::FORBIDDEN));
+        }
+    }
+    let cache_control = if file.private { PRIVATE_CACHE_CONTROL } else { IMMUTABLE_CACHE_CONTROL };
+
+    // The object at the path never changes, so the slug identifies its content
+    let etag = format!("\"{}\"", slug);
+    if let Some(if_none_match) = if_none_match {
+        if if_none_match.split(',').any(|tag| tag.trim() == etag || tag.trim() == "*") {
+            return Ok(Response::builder()
+                .status(StatusCode::NOT_MODIFIED)
+                .header(header::ETAG, etag)
+                .header(header::CACHE_CONTROL, cache_control)
+                .body(Body::empty())
+                .unwrap());
+        }
+    }
+
+    let size = match storage_backend.size(path.as_str()).await.map_err(|err| err.to_string()) {
+        Ok(size) => size,
+        Err(e) => {
+            eprintln!("failed to read object '{}': {}", path, e);
+            return Ok(status(StatusCode::NOT_FOUND));
+        }
+    };
+    let range = match range.map(|range| parse_range(range.as_str(), size)) {
+        Some(Ok(range)) => range,
+        Some(Err(RangeNotSatisfiable)) => {
+            return Ok(Response::builder()
+                .status(StatusCode::RANGE_NOT_SATISFIABLE)
+                .header(header::CONTENT_RANGE, format!("bytes */{}", size))
+                .body(Body::empty())
+                .unwrap());
+        }
+        _ => None,
+    };
+
+    let stream = match storage_backend.get(path.as_str(), range).await.map_err(|err| err.to_string()) {
+        Ok(stream) => stream,
+        Err(e) => {
+            eprintln!("failed to read object '{}': {}", path, e);
+            return Ok(status(StatusCode::INTERNAL_SERVER_ERROR));
+        }
+    };
+
+    let inline = is_inline(mime_type.as_str());
+    let mut response = Response::builder()
+        .header(header::CONTENT_TYPE, mime_type)
+        .header(header::X_CONTENT_TYPE_OPTIONS, "nosniff")
+        .header(header::ACCEPT_RANGES, "bytes")
+        .header(header::ETAG, etag)
+        .header(header::CACHE_CONTROL, cache_control);
+    if !inline {
+        response = response.header(header::CONTENT_DISPOSITION, "attachment");
+    }
+    response = match range {
+        Some((start, end)) => response
+            .status(StatusCode::PARTIAL_CONTENT)
+            .header(header::CONTENT_RANGE, format!("bytes {}-{}/{}", start, end, size))
+            .header(header::CONTENT_LENGTH, end - start + 1),
+        _ => response.status(StatusCode::OK).header(header::CONTENT_LENGTH, size),
+    };
+
+    Ok(response.body(Body::wrap_stream(stream)).unwrap())
+}
+
+/// Return the route which serves the stored files by their slugs on `GET /files/{slug}`.
+pub fn routes(
+    file_repository: Arc<dyn FileRepository>,
+    storage_backend: Arc<dyn StorageBackend>,
+    signer: Arc<UrlSigner>,
+) -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+    warp::get()
+        .and(warp::path!("files" / String))
+        .and(warp::header::optional::<String>("range"))
+        .and(warp::header::optional::<String>("if-none-match"))
+        .and(warp::query::<HashMap<String, String>>())
+        .and_then(move |slug, range, if_none_match, query| {
+            serve(
+                file_repository.clone(),
+                storage_backend.clone(),
+                signer.clone(),
+                slug,
+                range,
+                if_none_match,
+                query,
+            )
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use mongodb::bson::DateTime;
+
+    use crate::storage::serve::{is_inline, parse_range, RangeNotSatisfiable, UrlSigner};
+
+    #[test]
+    fn parse_satisfiable_ranges() {
+        // Given
+        let size = 1000;
+
+        // When
+
+        // Then
+        assert_eq!(Ok(Some((0, 499))), parse_range("bytes=0-499", size));
+        assert_eq!(Ok(Some((500, 999))), parse_range("bytes=500-", size));
+        assert_eq!(Ok(Some((900, 999))), parse_range("bytes=-100", size));
+        assert_eq!(Ok(Some((900, 999))), parse_range("bytes=900-2000", size));
+        assert_eq!(Ok(Some((0, 999))), parse_range("bytes=-2000", size));
+    }
+
+    #[test]
+    fn parse_unsatisfiable_ranges() {
+        // Given
+        let size = 1000;
+
+        // When
+
+        // Then
+        assert_eq!(Err(RangeNotSatisfiable), parse_range("bytes=1000-", size));
+        assert_eq!(Err(RangeNotSatisfiable), parse_range("bytes=-0", size));
+        assert_eq!(Err(RangeNotSatisfiable), parse_range("bytes=0-0", 0));
+    }
+
+    #[test]
+    fn parse_ignored_ranges() {
+        // Given
+        let size = 1000;
+
+        // When
+
+        // Then
+        assert_eq!(Ok(None), parse_range("items=0-1", size));
+        assert_eq!(Ok(None), parse_range("bytes=0-1,5-6", size));
+        assert_eq!(Ok(None), parse_range("bytes=5-1", size));
+        assert_eq!(Ok(None), parse_range("bytes=a-b", size));
+    }
+
+    #[test]
+    fn inline_only_images_and_pdf() {
+        // Given
+
+        // When
+
+        // Then
+        assert!(is_inline("image/png"));
+        assert!(is_inline("application/pdf"));
+        assert!(!is_inline("image/svg+xml"));
+        assert!(!is_inline("text/html"));
+        assert!(!is_inline("application/octet-stream"));
+    }
+
+    #[test]
+    fn verify_signed_url() {
+        // Given
+        let signer = UrlSigner::new(b"secret");
+        let expires = DateTime::now().timestamp_millis() + 60 * 1000;
+
+        // When
+        let signature = signer.sign("my-file.pdf", expires);
+
+        // Then
+        assert!(signer.verify("my-file.pdf", expires, signature.as_str()));
+        assert!(!signer.verify("other-file.pdf", expires, signature.as_str()));
+        assert!(!UrlSigner::new(b"other").verify("my-file.pdf", expires, signature.as_str()));
+    }
+
+    #[test]
+    fn verify_expired_signed_url() {
+        // Given
+        let signer = UrlSigner::new(b"secret");
+        let expires = DateTime::now().timestamp_millis() - 1;
+
+        // When
+        let signature = signer.sign("my-file.pdf", expires);
+
+        // Then
+        assert!(!signer.verify("my-file.pdf", expires, signature.as_str()));
+    }
+}
@@ -0,0 +1,420 @@
+use std::io;
+use std::sync::Arc;
+use std::time::SystemTime;
+
+use bytes::Bytes;
+use chrono::{Datelike, Utc};
+use mongodb::bson::{DateTime, oid::ObjectId};
+use myblog_proto_rust::myblog::proto::storage::{
+    CreateSignedFileUrlRequest, CreateSignedFileUrlResponse,
+    File, ImageDerivative,
+    storage_service_server::StorageService,
+    upload_file_request::Data,
+    UploadFileRequest, UploadFileResponse,
+};
+use prost_types::Timestamp;
+use tokio::io::AsyncReadExt;
+use tokio_stream::StreamExt;
+use tokio_util::io::StreamReader;
+use tonic::{Request, Response, Status, Streaming};
+
+use crate::auth::Claims;
+use crate::storage::backend::{HashingReader, StorageBackend};
+use crate::storage::file::{derivative_slug, file_slug, FileRepository};
+use crate::storage::image::{self, ProcessedImage};
+use crate::storage::serve::UrlSigner;
+use crate::storage::usage::UsageRepository;
+use crate::storage::validate::{self, SNIFF_SIZE, UploadLimits, ValidationError};
+
+/// A permission which is required to upload the files.
+const UPLOAD_PERMISSION: &str = "write:post";
+
+/// A default and maximum lifetime of the signed file URLs.
+const SIGNED_URL_DEFAULT_TTL_SECONDS: i64 = 15 * 60;
+const SIGNED_URL_MAX_TTL_SECONDS: i64 = 24 * 60 * 60;
+
+pub struct MyStorageService {
+    file_repository: Box<dyn FileRepository>,
+    usage_repository: Box<dyn UsageRepository>,
+    storage_backend: Arc<dyn StorageBackend>,
+    url_signer: Arc<UrlSigner>,
+
+    file_base_url: String,
+    image_widths: Vec<u32>,
+    upload_limits: UploadLimits,
+}
+
+/// Check that the token subject is allowed to manage the files, and return its claims.
+fn authorized<T>(request: &Request<T>) -> Result<Claims, Status> {
+    match request.extensions().get::<Claims>() {
+        Some(claims) if claims.has_permission(UPLOAD_PERMISSION) => Ok(claims.clone()),
+        Some(_) => Err(Status::permission_denied(format!("Missing required '{}' permission", UPLOAD_PERMISSION))),
+        _ => Err(Status::unauthenticated("Forbidden")),
+    }
+}
+
+impl MyStorageService {
+    pub fn builder() -> MyStorageServiceBuilder { MyStorageServiceBuilder::default() }
+
+    /// Store the stripped original image and its derivatives next to each other, then record them on the file.
+    async fn put_image(&self, file: &mut File, processed: ProcessedImage) -> Result<(), Status> {
+        let directory = match file.uploaded_file_path.rfind('/') {
+            Some(i) => file.uploaded_file_path[..i + 1].to_owned(),
+            _ => String::new(),
+        };
+
+        self.storage_backend
+            .put(file.uploaded_file_path.as_str(), processed.original.mime_type, &mut processed.original.data.as_slice())
+            .await
+            .or_else(|err| Err(Status::internal(err.to_string())))?;
+        file.width = processed.original.width;
+        file.height = processed.original.height;
+        file.blurhash = processed.blurhash;
+
+        for derivative in processed.derivatives {
+            let slug = derivative_slug(file.slug.as_str(), derivative.width, derivative.extension);
+            let path = format!("{}{}", directory, slug);
+
+            let put = self.storage_backend
+                .put(path.as_str(), derivative.mime_type, &mut derivative.data.as_slice())
+                .await
+                .map_err(|err| err.to_string());
+            if let Err(message) = put {
+                self.delete_objects(file).await;
+                return Err(Status::internal(message));
+            }
+
+            file.derivatives.push(ImageDerivative {
+                url: format!("{}/{}", self.file_base_url.trim_end_matches('/'), slug),
+                slug,
+                uploaded_file_path: path,
+                mime_type: derivative.mime_type.to_owned(),
+                width: derivative.width,
+                height: derivative.height,
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Point the file at the objects of an existing file of the same content, instead of storing them again.
+    ///
+    /// The derivatives get slugs of their own, so the file is served by its slugs only.
+    fn share_objects(&self, file: &mut File, existing: File) {
+        file.uploaded_file_path = existing.uploaded_file_path;
+        file.mime_type = existing.mime_type;
+        file.width = existing.width;
+        file.height = existing.height;
+        file.blurhash = existing.blurhash;
+        file.derivatives = existing.derivatives
+            .into_iter()
+            .map(|derivative| {
+                let extension = derivative.uploaded_file_path.rsplit('.').next().unwrap_or_default().to_owned();
+                let slug = derivative_slug(file.slug.as_str(), derivative.width, extension.as_str());
+
+                ImageDerivative {
+                    url: format!("{}/{}", self.file_base_url.trim_end_matches('/'), slug),
+                    slug,
+                    ..derivative
+                }
+            })
+            .collect();
+    }
+
+    /// Check that the upload of the size fits within the size limit of its MIME type and the remaining quota.
+    fn check_size(&self, size: u64, size_limit: u64, remaining: u64) -> Result<(), Status> {
+        if size > remaining && remaining < size_limit {
+            return Err(Status::resource_exhausted(ValidationError::QuotaExceeded(self.upload_limits.quota).to_string()));
+        }
+        if size > size_limit {
+            return Err(Status::invalid_argument(ValidationError::TooLarge(size_limit).to_string()));
+        }
+
+        Ok(())
+    }
+
+    /// Return the storage usage of the uploader, its counter is started from the existing files on the first upload.
+    async fn find_usage(&self, sub: &str) -> Result<u64, Status> {
+        let usage = self.usage_repository.find_usage(sub).await.map_err(|err| err.to_string());
+        let usage = match usage {
+            Ok(Some(usage)) => return Ok(usage),
+            Ok(None) => self.file_repository.sum_size_by_uploader(sub).await.map_err(|err| err.to_string()),
+            Err(message) => Err(message),
+        };
+
+        match usage {
+            Ok(usage) => self.usage_repository.init(sub, usage).await.map_err(|err| Status::internal(err.to_string())),
+            Err(message) => Err(Status::internal(message)),
+        }
+    }
+
+    async fn find_by_sha256(&self, sha256: &str) -> Result<Option<File>, Status> {
+        self.file_repository
+            .find_by_sha256(self.storage_backend.provider(), self.storage_backend.bucket(), sha256)
+            .await
+            .or_else(|err| Err(Status::internal(err.to_string())))
+    }
+
+    /// Delete the object of the file and all of its derivatives, ignoring the failures.
+    async fn delete_objects(&self, file: &File) {
+        self.storage_backend.delete(file.uploaded_file_path.as_str()).await.ok();
+
+        for derivative in file.derivatives.iter() {
+            self.storage_backend.delete(derivative.uploaded_file_path.as_str()).await.ok();
+        }
+    }
+}
+
+#[tonic::async_trait]
+impl StorageService for MyStorageService {
+    async fn upload_file(
+        &self,
+        request: Request<Streaming<UploadFileRequest>>,
+    ) -> Result<Response<UploadFileResponse>, Status> {
+        let sub = authorized(&request)?.sub;
+        let mut stream = request.into_inner();
+
+        // The first message carries the file metadata and all following messages carry its content
+        let metadata = match stream.message().await? {
+            Some(UploadFileRequest { data: Some(Data::Metadata(metadata)) }) => Ok(metadata),
+            _ => Err(Status::invalid_argument("First message must contain the file metadata")),
+        }?;
+        if metadata.file_name.is_empty() {
+            return Err(Status::invalid_argument("Missing required 'file_name' field"));
+        }
+
+        let usage = self.find_usage(sub.as_str()).await?;
+        let remaining = self.upload_limits.quota.saturating_sub(usage);
+        if remaining == 0 {
+            return Err(Status::resource_exhausted(ValidationError::QuotaExceeded(self.upload_limits.quota).to_string()));
+        }
+
+        let mut body = StreamReader::new(stream.map(|message| match message {
+            Ok(UploadFileRequest { data: Some(Data::Chunk(chunk)) }) => Ok(Bytes::from(chunk)),
+            Ok(_) => Err(io::Error::new(io::ErrorKind::InvalidData, "Expected a file chunk")),
+            Err(status) => Err(io::Error::new(io::ErrorKind::Other, status.message().to_owned())),
+        }));
+
+        // The MIME type is detected from the leading bytes, the client is not trusted to declare it
+        let mut head: Vec<u8> = vec![];
+        (&mut body)
+            .take(SNIFF_SIZE as u64)
+            .read_to_end(&mut head)
+            .await
+            .or_else(|err| Err(Status::internal(err.to_string())))?;
+        let mime_type = validate::resolve_mime_type(metadata.mime_type.as_str(), &head)
+            .or_else(|err| Err(Status::invalid_argument(err.to_string())))?;
+
+        let id = ObjectId::new().to_hex();
+        let slug = file_slug(id.as_str(), metadata.file_name.as_str());
+        let now = Utc::now();
+        let mut file = File {
+            id,
+            file_name: metadata.file_name,
+            uploaded_file_path: format!("{}/{:02}/{}", now.year(), now.month(), slug),
+            slug,
+            mime_type,
+            provider: self.storage_backend.provider().to_owned(),
+            region: self.storage_backend.region().to_owned(),
+            bucket: self.storage_backend.bucket().to_owned(),
+            private: metadata.private,
+            uploaded_by: sub,
+            uploaded_at: Some(Timestamp::from(SystemTime::from(now))),
+            ..Default::default()
+        };
+
+        // One byte more than allowed is read, so an oversized upload can be told apart from one of the exact limit
+        let size_limit = self.upload_limits.size_limit(file.mime_type.as_str());
+        let limit = size_limit.min(remaining);
+        let mut body = HashingReader::new(head.as_slice().chain(body).take(limit.saturating_add(1)));
+
+        // Whether the objects of an existing file of the same content are reused, they must then be kept on failures
+        let mut shared = false;
+        if image::is_processable(file.mime_type.as_str()) {
+            // Images have to be decoded as a whole, the processing is moved off the async runtime threads
+            let mut data: Vec<u8> = vec![];
+            body.read_to_end(&mut data).await.or_else(|err| Err(Status::internal(err.to_string())))?;
+            file.size = body.bytes_read();
+            self.check_size(file.size, size_limit, remaining)?;
+            file.sha256 = body.finish();
+
+            // The dimensions are read from the header, a decompression bomb is refused before it is decoded
+            let (width, height) = validate::image_dimensions(&data)
+                .or_else(|err| Err(Status::invalid_argument(format!("Invalid image: {}", err))))?;
+            self.upload_limits
+                .check_dimensions(width, height)
+                .or_else(|err| Err(Status::invalid_argument(err.to_string())))?;
+
+            if let Some(existing) = self.find_by_sha256(file.sha256.as_str()).await? {
+                self.share_objects(&mut file, existing);
+                shared = true;
+            } else {
+                let mime_type = file.mime_type.clone();
+                let widths = self.image_widths.clone();
+
+                let processed = tokio::task::spawn_blocking(move || {
+                    image::process(&data, mime_type.as_str(), &widths).map_err(|err| err.to_string())
+                })
+                    .await
+                    .or_else(|err| Err(Status::internal(err.to_string())))?
+                    .or_else(|err| Err(Status::invalid_argument(format!("Invalid image: {}", err))))?;
+                self.put_image(&mut file, processed).await?;
+            }
+        } else {
+            // Images which are stored as is, e.g. GIF, have their dimensions within the leading bytes
+            if file.mime_type.starts_with("image/") {
+                let (width, height) = validate::image_dimensions(&head)
+                    .or_else(|err| Err(Status::invalid_argument(format!("Invalid image: {}", err))))?;
+                self.upload_limits
+                    .check_dimensions(width, height)
+                    .or_else(|err| Err(Status::invalid_argument(err.to_string())))?;
+            }
+
+            // Other files are hashed while they are streamed, so a duplicate is only known once it has been stored
+            self.storage_backend
+                .put(file.uploaded_file_path.as_str(), file.mime_type.as_str(), &mut body)
+                .await
+                .or_else(|err| Err(Status::internal(err.to_string())))?;
+            file.size = body.bytes_read();
+            if let Err(status) = self.check_size(file.size, size_limit, remaining) {
+                self.delete_objects(&file).await;
+                return Err(status);
+            }
+            file.sha256 = body.finish();
+
+            if let Some(existing) = self.find_by_sha256(file.sha256.as_str()).await? {
+                self.delete_objects(&file).await;
+                self.share_objects(&mut file, existing);
+                shared = true;
+            }
+        }
+
+        // The remaining quota above may be taken by the concurrent uploads meanwhile, the counter is only raised
+        // if the file still fits
+        let reserved = match self.usage_repository
+            .reserve(file.uploaded_by.as_str(), file.size, self.upload_limits.quota)
+            .await
+        {
+            Ok(true) => Ok(()),
+            Ok(false) => Err(Status::resource_exhausted(ValidationError::QuotaExceeded(self.upload_limits.quota).to_string())),
+            Err(e) => Err(Status::internal(e.to_string())),
+        };
+        if let Err(status) = reserved {
+            if !shared {
+                self.delete_objects(&file).await;
+            }
+            return Err(status);
+        }
+
+        let created = self.file_repository.create(&file).await.map_err(|err| err.to_string());
+        if let Err(message) = created {
+            // Do not leave objects behind which nothing describes, nor the usage of a file which is not stored
+            if !shared {
+                self.delete_objects(&file).await;
+            }
+            self.usage_repository.release(file.uploaded_by.as_str(), file.size).await.ok();
+            return Err(Status::internal(message));
+        }
+
+        Ok(Response::new(UploadFileResponse { file: Some(file) }))
+    }
+
+    async fn create_signed_file_url(
+        &self,
+        request: Request<CreateSignedFileUrlRequest>,
+    ) -> Result<Response<CreateSignedFileUrlResponse>, Status> {
+        authorized(&request)?;
+        let CreateSignedFileUrlRequest { slug, ttl_seconds } = request.into_inner();
+
+        match self.file_repository.find_by_slug(slug.as_str()).await {
+            Ok(Some(_)) => Ok(()),
+            Ok(None) => Err(Status::not_found("File not found")),
+            Err(e) => Err(Status::internal(e.to_string())),
+        }?;
+
+        let ttl = match ttl_seconds {
+            0 => SIGNED_URL_DEFAULT_TTL_SECONDS,
+            ttl => (ttl as i64).min(SIGNED_URL_MAX_TTL_SECONDS),
+        };
+        let expires = DateTime::now().timestamp_millis() + ttl * 1000;
+
+        Ok(Response::new(CreateSignedFileUrlResponse {
+            url: format!(
+                "{}/{}?expires={}&signature={}",
+                self.file_base_url.trim_end_matches('/'),
+                slug,
+                expires,
+                self.url_signer.sign(slug.as_str(), expires),
+            ),
+            expires_at: Some(Timestamp { seconds: expires / 1000, nanos: (expires % 1000) as i32 * 1_000_000 }),
+        }))
+    }
+}
+
+#[derive(Default)]
+pub struct MyStorageServiceBuilder {
+    /* Repository */
+    file_repository: Option<Box<dyn FileRepository>>,
+    usage_repository: Option<Box<dyn UsageRepository>>,
+
+    /* Storage */
+    storage_backend: Option<Arc<dyn StorageBackend>>,
+    url_signer: Option<Arc<UrlSigner>>,
+
+    /* Options */
+    file_base_url: Option<String>,
+    image_widths: Option<Vec<u32>>,
+    upload_limits: Option<UploadLimits>,
+}
+
+impl MyStorageServiceBuilder {
+    pub fn with_file_repository(mut self, repository: Box<dyn FileRepository>) -> Self {
+        self.file_repository = Some(repository);
+        self
+    }
+
+    pub fn with_usage_repository(mut self, repository: Box<dyn UsageRepository>) -> Self {
+        self.usage_repository = Some(repository);
+        self
+    }
+
+    pub fn with_storage_backend(mut self, backend: Arc<dyn StorageBackend>) -> Self {
+        self.storage_backend = Some(backend);
+        self
+    }
+
+    pub fn with_url_signer(mut self, signer: Arc<UrlSigner>) -> Self {
+        self.url_signer = Some(signer);
+        self
+    }
+
+    /// Set the base URL from which the stored files are served, e.g. `https://example.com/files`.
+    pub fn with_file_base_url(mut self, file_base_url: &str) -> Self {
+        self.file_base_url = Some(file_base_url.to_owned());
+        self
+    }
+
+    /// Set the widths of the derivatives which are produced from every uploaded image.
+    pub fn with_image_widths(mut self, image_widths: Vec<u32>) -> Self {
+        self.image_widths = Some(image_widths);
+        self
+    }
+
+    /// Set the limits which every upload is checked against.
+    pub fn with_upload_limits(mut self, upload_limits: UploadLimits) -> Self {
+        self.upload_limits = Some(upload_limits);
+        self
+    }
+
+    pub fn build(self) -> MyStorageService {
+        MyStorageService {
+            file_repository: self.file_repository.unwrap(),
+            usage_repository: self.usage_repository.unwrap(),
+            storage_backend: self.storage_backend.unwrap(),
+            url_signer: self.url_signer.unwrap(),
+            file_base_url: self.file_base_url.unwrap_or_default(),
+            image_widths: self.image_widths.unwrap_or_else(|| vec![320, 640, 1280]),
+            upload_limits: self.upload_limits.unwrap_or_default(),
+        }
+    }
+}
@@ -0,0 +1,79 @@
+use mongodb::{bson::doc, bson::Document, Collection};
+use mongodb::options::{FindOneAndUpdateOptions, ReturnDocument};
+
+/// A storage usage repository definition.
+///
+/// The usage of each uploader is kept as a counter which is only raised within the quota, so the concurrent
+/// uploads cannot all pass the quota check against the same sum of the existing files.
+#[tonic::async_trait]
+pub trait UsageRepository: Send + Sync + 'static {
+    /// Return the usage of the uploader in bytes, or `None` if its counter has not been started yet.
+    async fn find_usage(&self, sub: &str) -> Result<Option<u64>, Box<dyn std::error::Error>>;
+    /// Start the counter of the uploader at the size of its existing files unless it has been started meanwhile,
+    /// and return the usage in bytes.
+    async fn init(&self, sub: &str, used: u64) -> Result<u64, Box<dyn std::error::Error>>;
+    /// Add the size to the usage only if it stays within the quota, return true if it has been reserved.
+    async fn reserve(&self, sub: &str, size: u64, quota: u64) -> Result<bool, Box<dyn std::error::Error>>;
+    /// Give the size of a file which is no longer stored back to the uploader.
+    async fn release(&self, sub: &str, size: u64) -> Result<(), Box<dyn std::error::Error>>;
+}
+
+/// An implementation of the UsageRepository specifies with MongoDB.
+pub struct MongoUsageRepository {
+    collection: Collection<Document>,
+}
+
+impl MongoUsageRepository {
+    pub fn new(collection: Collection<Document>) -> Self {
+        MongoUsageRepository { collection }
+    }
+}
+
+#[tonic::async_trait]
+impl UsageRepository for MongoUsageRepository {
+    async fn find_usage(&self, sub: &str) -> Result<Option<u64>, Box<dyn std::error::Error>> {
+        match self.collection.find_one(doc! {"_id": sub}, None).await? {
+            Some(document) => Ok(Some(document.get_i64("used")?.max(0) as u64)),
+            _ => Ok(None),
+        }
+    }
+
+    async fn init(&self, sub: &str, used: u64) -> Result<u64, Box<dyn std::error::Error>> {
+        let options = FindOneAndUpdateOptions::builder()
+            .upsert(true)
+            .return_document(ReturnDocument::After)
+            .build();
+
+        match self.collection
+            .find_one_and_update(doc! {"_id": sub}, doc! {"$setOnInsert": {"used": used as i64}}, options)
+            .await?
+        {
+            Some(document) => Ok(document.get_i64("used")?.max(0) as u64),
+            _ => Ok(used),
+        }
+    }
+
+    async fn reserve(&self, sub: &str, size: u64, quota: u64) -> Result<bool, Box<dyn std::error::Error>> {
+        if size > quota {
+            return Ok(false);
+        }
+
+        let result = self.collection
+            .update_one(
+                doc! {"_id": sub, "used": {"$lte": (quota - size) as i64}},
+                doc! {"$inc": {"used": size as i64}},
+                None,
+            )
+            .await?;
+
+        Ok(result.matched_count > 0)
+    }
+
+    async fn release(&self, sub: &str, size: u64) -> Result<(), Box<dyn std::error::Error>> {
+        self.collection
+            .update_one(doc! {"_id": sub}, doc! {"$inc": {"used": -(size as i64)}}, None)
+            .await?;
+
+        Ok(())
+    }
+}
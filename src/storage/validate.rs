@@ -0,0 +1,251 @@
+use std::error::Error;
+use std::fmt;
+use std::io::Cursor;
+
+/// A number of the leading bytes from which the MIME type of an upload is detected.
+pub const SNIFF_SIZE: usize = 512;
+
+const MIB: u64 = 1024 * 1024;
+
+/// An error returned when the upload is refused.
+#[derive(Debug, PartialEq)]
+pub enum ValidationError {
+    /// The content does not match the declared MIME type, it is `None` if the content type is not recognized.
+    MimeTypeMismatch(String, Option<&'static str>),
+    /// The file is larger than the size limit of its MIME type in bytes.
+    TooLarge(u64),
+    /// The decoded image would be larger than the dimension limits.
+    DimensionsExceeded(u32, u32),
+    /// The file does not fit in the storage quota of the uploader in bytes.
+    QuotaExceeded(u64),
+}
+
+impl fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ValidationError::MimeTypeMismatch(declared, Some(detected)) => {
+                write!(f, "Declared MIME type '{}' does not match the detected '{}'", declared, detected)
+            }
+            ValidationError::MimeTypeMismatch(declared, None) => {
+                write!(f, "Content is not of the declared MIME type '{}'", declared)
+            }
+            ValidationError::TooLarge(limit) => write!(f, "File exceeds the size limit of {} bytes", limit),
+            ValidationError::DimensionsExceeded(width, height) => {
+                write!(f, "Image dimensions {}x{} exceed the limits", width, height)
+            }
+            ValidationError::QuotaExceeded(quota) => write!(f, "Storage quota of {} bytes exceeded", quota),
+        }
+    }
+}
+
+impl Error for ValidationError {}
+
+/// The limits which every upload is checked against.
+#[derive(Clone, Debug)]
+pub struct UploadLimits {
+    /// The size limits in bytes by MIME type, which is either exact, `type/*` or `*`.
+    pub size_limits: Vec<(String, u64)>,
+    /// The maximum width and height of the images.
+    pub max_image_dimension: u32,
+    /// The maximum number of pixels of the images, a decompression bomb has few bytes but many pixels.
+    pub max_image_pixels: u64,
+    /// The total size in bytes which each user can upload.
+    pub quota: u64,
+}
+
+impl Default for UploadLimits {
+    fn default() -> Self {
+        UploadLimits {
+            size_limits: vec![(String::from("image/*"), 10 * MIB), (String::from("*"), 50 * MIB)],
+            max_image_dimension: 10_000,
+            max_image_pixels: 40_000_000,
+            quota: 1024 * MIB,
+        }
+    }
+}
+
+impl UploadLimits {
+    /// Return the size limit of the MIME type, the most specific one applies.
+    pub fn size_limit(&self, mime_type: &str) -> u64 {
+        let wildcard = match mime_type.split_once('/') {
+            Some((kind, _)) => format!("{}/*", kind),
+            _ => String::from("*"),
+        };
+
+        [mime_type, wildcard.as_str(), "*"]
+            .iter()
+            .find_map(|pattern| self.size_limits.iter().find(|(limit_type, _)| limit_type == pattern))
+            .map(|(_, limit)| *limit)
+            .unwrap_or(u64::MAX)
+    }
+
+    pub fn check_dimensions(&self, width: u32, height: u32) -> Result<(), ValidationError> {
+        if width > self.max_image_dimension
+            || height > self.max_image_dimension
+            || width as u64 * height as u64 > self.max_image_pixels
+        {
+            return Err(ValidationError::DimensionsExceeded(width, height));
+        }
+
+        Ok(())
+    }
+}
+
+/// Parse the size limits in MiB, e.g. `image/*=10,application/pdf=25,*=50`.
+pub fn parse_size_limits(s: &str) -> Result<Vec<(String, u64)>, Box<dyn Error>> {
+    s.split(',')
+        .filter(|limit| !limit.trim().is_empty())
+        .map(|limit| match limit.split_once('=') {
+            Some((mime_type, size)) => Ok((mime_type.trim().to_lowercase(), size.trim().parse::<u64>()? * MIB)),
+            _ => Err(format!("Invalid size limit '{}'", limit).into()),
+        })
+        .collect()
+}
+
+/// The declared MIME types which are kept without being detected, any other is stored as
+/// `application/octet-stream` so a type such as `text/html` can never be chosen by the client.
+const ALLOWED_MIME_TYPES: &[&str] = &[
+    "application/json",
+    "application/zip",
+    "audio/mpeg",
+    "audio/ogg",
+    "text/csv",
+    "text/markdown",
+    "text/plain",
+    "video/mp4",
+    "video/webm",
+];
+
+/// Detect the MIME type from the magic bytes at the beginning of the content.
+///
+/// Only the types which are served inline are detected, the others are kept as declared if they are allowed.
+pub fn sniff_mime_type(head: &[u8]) -> Option<&'static str> {
+    if head.starts_with(&[0xFF, 0xD8, 0xFF]) {
+        Some("image/jpeg")
+    } else if head.starts_with(&[0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A]) {
+        Some("image/png")
+    } else if head.starts_with(b"GIF87a") || head.starts_with(b"GIF89a") {
+        Some("image/gif")
+    } else if head.len() >= 12 && &head[..4] == b"RIFF" && &head[8..12] == b"WEBP" {
+        Some("image/webp")
+    } else if head.starts_with(b"%PDF-") {
+        Some("application/pdf")
+    } else {
+        None
+    }
+}
+
+fn is_sniffable(mime_type: &str) -> bool {
+    matches!(mime_type, "image/jpeg" | "image/png" | "image/gif" | "image/webp" | "application/pdf")
+}
+
+/// Return the MIME type of the upload from its declared type and leading bytes.
+///
+/// The detected type is used when none is declared, and the upload is refused when the declared type
+/// is contradicted by the content, or claims to be one of the detected types which the content is not.
+/// The undetected types which are not allowed are stored as `application/octet-stream`.
+pub fn resolve_mime_type(declared: &str, head: &[u8]) -> Result<String, ValidationError> {
+    let declared = declared.split(';').next().unwrap_or_default().trim().to_lowercase();
+    let declared = match declared.as_str() {
+        "image/jpg" | "image/pjpeg" => String::from("image/jpeg"),
+        _ => declared,
+    };
+
+    match sniff_mime_type(head) {
+        Some(detected) if declared.is_empty() || declared == "application/octet-stream" => Ok(detected.to_owned()),
+        Some(detected) if declared == detected => Ok(declared),
+        Some(detected) => Err(ValidationError::MimeTypeMismatch(declared, Some(detected))),
+        None if is_sniffable(declared.as_str()) => Err(ValidationError::MimeTypeMismatch(declared, None)),
+        None if ALLOWED_MIME_TYPES.contains(&declared.as_str()) => Ok(declared),
+        None => Ok(String::from("application/octet-stream")),
+    }
+}
+
+/// Read the image dimensions from its header without decoding the pixels.
+pub fn image_dimensions(data: &[u8]) -> Result<(u32, u32), image::ImageError> {
+    image::io::Reader::new(Cursor::new(data)).with_guessed_format()?.into_dimensions()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::storage::validate::{parse_size_limits, resolve_mime_type, UploadLimits, ValidationError};
+
+    const PNG_HEAD: &[u8] = &[0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A, 0, 0, 0, 0x0D];
+
+    #[test]
+    fn resolve_declared_mime_type() {
+        // Given
+
+        // When
+
+        // Then
+        assert_eq!(Ok(String::from("image/png")), resolve_mime_type("image/png", PNG_HEAD));
+        assert_eq!(Ok(String::from("image/png")), resolve_mime_type("", PNG_HEAD));
+        assert_eq!(Ok(String::from("image/jpeg")), resolve_mime_type("image/jpg", &[0xFF, 0xD8, 0xFF, 0xE0]));
+        assert_eq!(Ok(String::from("text/markdown")), resolve_mime_type("text/markdown; charset=utf-8", b"# Hello"));
+        assert_eq!(Ok(String::from("application/octet-stream")), resolve_mime_type("", b"# Hello"));
+    }
+
+    #[test]
+    fn resolve_disallowed_mime_type_as_octet_stream() {
+        // Given
+
+        // When
+
+        // Then
+        assert_eq!(Ok(String::from("application/octet-stream")), resolve_mime_type("text/html", b"<html>"));
+        assert_eq!(Ok(String::from("application/octet-stream")), resolve_mime_type("image/svg+xml", b"<svg>"));
+        assert_eq!(Ok(String::from("application/octet-stream")), resolve_mime_type("text/x-unknown", b"Hello"));
+    }
+
+    #[test]
+    fn reject_mismatched_mime_type() {
+        // Given
+
+        // When
+
+        // Then
+        assert_eq!(
+            Err(ValidationError::MimeTypeMismatch(String::from("image/jpeg"), Some("image/png"))),
+            resolve_mime_type("image/jpeg", PNG_HEAD),
+        );
+        assert_eq!(
+            Err(ValidationError::MimeTypeMismatch(String::from("text/plain"), Some("application/pdf"))),
+            resolve_mime_type("text/plain", b"%PDF-1.7"),
+        );
+        assert_eq!(
+            Err(ValidationError::MimeTypeMismatch(String::from("image/png"), None)),
+            resolve_mime_type("image/png", b"<svg onload=\"alert(1)\">"),
+        );
+    }
+
+    #[test]
+    fn most_specific_size_limit() {
+        // Given
+        let limits = UploadLimits {
+            size_limits: parse_size_limits("image/*=10, image/gif=2, *=50").unwrap(),
+            ..Default::default()
+        };
+
+        // When
+
+        // Then
+        assert_eq!(2 * 1024 * 1024, limits.size_limit("image/gif"));
+        assert_eq!(10 * 1024 * 1024, limits.size_limit("image/png"));
+        assert_eq!(50 * 1024 * 1024, limits.size_limit("application/pdf"));
+        assert!(parse_size_limits("image/*").is_err());
+    }
+
+    #[test]
+    fn check_image_dimensions() {
+        // Given
+        let limits = UploadLimits { max_image_dimension: 1000, max_image_pixels: 500_000, ..Default::default() };
+
+        // When
+
+        // Then
+        assert_eq!(Ok(()), limits.check_dimensions(1000, 500));
+        assert_eq!(Err(ValidationError::DimensionsExceeded(1001, 1)), limits.check_dimensions(1001, 1));
+        assert_eq!(Err(ValidationError::DimensionsExceeded(1000, 501)), limits.check_dimensions(1000, 501));
+    }
+}